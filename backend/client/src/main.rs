@@ -14,13 +14,28 @@ use tungstenite::{
 
 use tokio::{
     io::{self, AsyncBufReadExt, BufReader},
+    sync::mpsc,
 };
 
 use tokio_util::{
     sync::CancellationToken,
 };
 
-use protocols::{ServerProtocol, ClientProtocol};
+use std::{
+    env,
+    fs,
+    sync::{Arc, Mutex},
+};
+
+use protocols::{ServerProtocol, ClientProtocol, PresenceStatus};
+
+use error::{ProtocolError, AuthenticateErrorType};
+
+// Arquivo onde o token de sessão fica guardado entre
+// execuções, para reconectar sem a senha.
+fn token_file() -> String {
+    env::var("DW_TOKEN_FILE").unwrap_or_else(|_| String::from(".dw_token"))
+}
 
 #[tokio::main]
 async fn main() {
@@ -32,8 +47,8 @@ async fn main() {
 
     let (mut write, mut read) = socket.split();
 
-    let (username, password) = (String::from("Artur"), String::from("1234"));
-    // let (username, password) = (String::from("nyoxon"), String::from("1234"));
+    let (username, password) = (String::from("Artur"), String::from("senha-1234"));
+    // let (username, password) = (String::from("nyoxon"), String::from("senha-1234"));
 
     // apague o comentario para tentar adicionar algum usuario,
     // basta mudar (username, password) ali em cima
@@ -47,12 +62,22 @@ async fn main() {
     //     .await
     //     .expect("Erro ao enviar mensagem");
 
+    // Com um token guardado a senha não é enviada. Se o
+    // token for recusado ele é apagado e a próxima execução
+    // volta a usar a senha.
+    let authenticate = match fs::read_to_string(token_file()) {
+        Ok(token) => ClientProtocol::AuthenticateWithToken {
+            token: token.trim().to_string(),
+        },
+        Err(_) => ClientProtocol::RequestAuthenticate {
+            username: username.clone(),
+            password: password.clone(),
+        },
+    };
+
     write
         .send(Message::Text(
-            serde_json::to_string(&ClientProtocol::RequestAuthenticate {
-                username: username.clone(),
-                password: password.clone(),
-            }).unwrap().into()
+            serde_json::to_string(&authenticate).unwrap().into()
         ))
         .await
         .expect("Erro ao enviar mensagem");
@@ -62,6 +87,15 @@ async fn main() {
     let cancel = CancellationToken::new();
     let cancel_sender = cancel.clone();
 
+    // Desafio de 2FA recebido no login. Enquanto houver um,
+    // a próxima linha digitada é enviada como o código.
+    let challenge: Arc<Mutex<Option<String>>> = Arc::new(Mutex::new(None));
+    let challenge_reader = challenge.clone();
+
+    // Confirmações das mensagens recebidas, enviadas ao
+    // servidor pela task que escreve na socket.
+    let (acks, mut pending_acks) = mpsc::unbounded_channel::<ClientProtocol>();
+
     let sender = {
         tokio::spawn(async move {
             let stdin = BufReader::new(io::stdin());
@@ -74,16 +108,32 @@ async fn main() {
                         break;
                     }
 
+                    Some(ack) = pending_acks.recv() => {
+                        let json = serde_json::to_string(&ack).unwrap();
+
+                        write
+                            .send(Message::Text(json.into()))
+                            .await
+                            .expect("erro ao enviar mensagem");
+                    }
+
                     line = lines.next_line() => {
                         if let Ok(Some(line)) = line {
                             if line.is_empty() {
                                 continue;
                             }
 
-                            let msg = ClientProtocol::SendMessage {
-                                from: username.clone(),
-                                to: String::from("nyoxon"),
-                                text: line,
+                            let pending = challenge.lock().unwrap().take();
+
+                            let msg = match pending {
+                                Some(challenge) => ClientProtocol::VerifyTwoFactor {
+                                    challenge,
+                                    code: line.trim().to_string(),
+                                },
+                                None => ClientProtocol::SendMessage {
+                                    to: String::from("nyoxon"),
+                                    text: line,
+                                },
                             };
                             let json = serde_json::to_string(&msg).unwrap();
 
@@ -104,16 +154,163 @@ async fn main() {
         while let Some(Ok(msg)) = read.next().await {
             if let Message::Text(json) = msg {
                 match serde_json::from_str::<ServerProtocol>(&json) {
-                    Ok(ServerProtocol::Message { from, to: _, text }) => {
+                    Ok(ServerProtocol::Message { id, from, text, .. }) => {
                         println!("from {from}: {text}");
+                        let _ = acks.send(ClientProtocol::AckMessages { ids: vec![id] });
+                    },
+
+                    Ok(ServerProtocol::RoomMessage { id, room, from, text, .. }) => {
+                        println!("[{room}] from {from}: {text}");
+                        let _ = acks.send(ClientProtocol::AckRoomMessages { ids: vec![id] });
+                    },
+
+                    Ok(ServerProtocol::Rooms { rooms }) => {
+                        for room in rooms {
+                            let joined = if room.joined { " (participando)" } else { "" };
+                            println!("{} ({} membros){joined}", room.name, room.members);
+                        }
+                    },
+
+                    Ok(ServerProtocol::MemberJoined { room, username }) => {
+                        println!("[{room}] {username} entrou");
+                    },
+
+                    Ok(ServerProtocol::MemberLeft { room, username }) => {
+                        println!("[{room}] {username} saiu");
+                    },
+
+                    Ok(reply @ (
+                        ServerProtocol::RoomCreated { .. }
+                        | ServerProtocol::RoomJoined { .. }
+                        | ServerProtocol::RoomLeft { .. }
+                    )) => {
+                        println!("{reply:?}");
+                    },
+
+                    Ok(ServerProtocol::MessageDelivered { to, .. }) => {
+                        println!("(entregue a {to})");
+                    },
+
+                    Ok(ServerProtocol::PresenceChanged { username, status, .. }) => {
+                        match status {
+                            PresenceStatus::Online => println!("{username} entrou"),
+                            PresenceStatus::Offline => println!("{username} saiu"),
+                        }
+                    },
+
+                    Ok(ServerProtocol::Authenticated { username, token, unread, .. }) => {
+                        println!("{username} autenticado com sucesso");
+
+                        for conversation in unread {
+                            println!("{} mensagens não lidas de {}", conversation.count, conversation.with);
+                        }
+
+                        if let Err(e) = fs::write(token_file(), token) {
+                            println!("Erro ao guardar o token de sessão: {e}");
+                        }
+                    },
+
+                    Ok(ServerProtocol::TwoFactorRequired { challenge, .. }) => {
+                        println!("Digite o código do autenticador (ou um código de recuperação):");
+                        *challenge_reader.lock().unwrap() = Some(challenge);
+                    },
+
+                    Ok(ServerProtocol::TwoFactorEnrollment { uri, .. }) => {
+                        println!("Configure o autenticador com {uri}");
+                    },
+
+                    Ok(ServerProtocol::TwoFactorEnabled { recovery_codes }) => {
+                        println!("2FA ativado. Códigos de recuperação:");
+
+                        for code in recovery_codes {
+                            println!("  {code}");
+                        }
+                    },
+
+                    Ok(ServerProtocol::TwoFactorDisabled) => {
+                        println!("2FA desativado");
                     },
 
-                    Ok(ServerProtocol::UserDisconnected { username }) => {
-                        println!("{username} saiu da conversa");
+                    Ok(ServerProtocol::TokenRevoked) => {
+                        println!("Token de sessão revogado");
                     },
 
-                    Ok(ServerProtocol::Authenticated) => {
-                        println!("Usuário autenticado com sucesso");
+                    Ok(ServerProtocol::PasswordChanged { token, .. }) => {
+                        println!("Senha alterada");
+
+                        if let Err(e) = fs::write(token_file(), token) {
+                            println!("Erro ao guardar o token de sessão: {e}");
+                        }
+                    },
+
+                    Ok(ServerProtocol::PasswordReset) => {
+                        println!("Senha redefinida");
+                    },
+
+                    Ok(ServerProtocol::LoggedOut) => {
+                        println!("Sessão encerrada");
+                    },
+
+                    Ok(ServerProtocol::SessionEnded { reason }) => {
+                        println!("Sessão encerrada pelo servidor: {reason:?}");
+                        let _ = fs::remove_file(token_file());
+                        break;
+                    },
+
+                    Ok(ServerProtocol::OnlineUsers { users }) => {
+                        for user in users {
+                            println!("{} ({} sessões)", user.username, user.sessions);
+                        }
+                    },
+
+                    Ok(ServerProtocol::History { with, messages, .. }) => {
+                        println!("Histórico com {with}:");
+
+                        for message in messages {
+                            println!("  {}: {}", message.from, message.text);
+                        }
+                    },
+
+                    Ok(ServerProtocol::Presence { users }) => {
+                        for user in users {
+                            println!("{}: {:?}", user.username, user.status);
+                        }
+                    },
+
+                    Ok(ServerProtocol::NewPost { post } | ServerProtocol::PostCreated { post }) => {
+                        println!("[feed] {}: {}", post.author, post.text);
+                    },
+
+                    Ok(ServerProtocol::Feed { posts, .. }) => {
+                        for post in posts {
+                            println!("[feed] {}: {}", post.author, post.text);
+                        }
+                    },
+
+                    Ok(reply @ (ServerProtocol::FeedSubscribed | ServerProtocol::FeedUnsubscribed)) => {
+                        println!("{reply:?}");
+                    },
+
+                    Ok(ServerProtocol::Typing { from }) => {
+                        println!("{from} está digitando...");
+                    },
+
+                    Ok(ServerProtocol::StoppedTyping { .. }) => {},
+
+                    Ok(ServerProtocol::MessagesRead { by, .. }) => {
+                        println!("(lida por {by})");
+                    },
+
+                    Ok(ServerProtocol::MarkedRead { .. }) => {},
+
+                    // Respostas aos protocolos de administração.
+                    Ok(reply @ (
+                        ServerProtocol::UserKicked { .. }
+                        | ServerProtocol::UserBanned { .. }
+                        | ServerProtocol::UserUnbanned { .. }
+                        | ServerProtocol::RoleChanged { .. }
+                    )) => {
+                        println!("{reply:?}");
                     },
 
                     Ok(ServerProtocol::UserCreated) => {
@@ -122,6 +319,14 @@ async fn main() {
 
                     Ok(ServerProtocol::Error { error }) => {
                         println!("Erro -> {error}");
+
+                        if let ProtocolError::AuthenticateError(
+                            AuthenticateErrorType::InvalidToken
+                            | AuthenticateErrorType::TokenExpired
+                        ) = error {
+                            let _ = fs::remove_file(token_file());
+                        }
+
                         break;
                     },
 
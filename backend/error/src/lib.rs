@@ -13,19 +13,59 @@ use std::{
     fmt,
 };
 
-// Erros relacionados a database mysql e usuários.
+// Erros relacionados a database e usuários.
 #[derive(Debug, Serialize, Deserialize)]
 pub enum AuthenticateErrorType {
     Std,
     Hash,
     Sql,
     Envy,
+    UnsupportedDatabase,
     PasswordMismatch,
     UserNotFound,
     UserNotAdded,
     UserAlreadyExists,
     OfflineMessageError,
     UserTxNotExist,
+    InvalidToken,
+    TokenExpired,
+    // Login bloqueado por excesso de falhas; pode ser
+    // tentado de novo em retry_after segundos.
+    TooManyAttempts { retry_after: u64 },
+    InvalidResetCode,
+    UsernameTooShort { min: usize },
+    UsernameTooLong { max: usize },
+    // Só letras, dígitos, '_', '.' e '-', começando
+    // por letra ou dígito.
+    UsernameInvalidCharacters,
+    UsernameReserved,
+    PasswordTooShort { min: usize },
+    PasswordTooLong { max: usize },
+    // Precisa de pelo menos min_classes entre minúsculas,
+    // maiúsculas, dígitos e símbolos.
+    PasswordTooWeak { min_classes: usize },
+    PasswordContainsUsername,
+    // Código TOTP ou de recuperação errado (ou já usado).
+    InvalidTwoFactorCode,
+    // Desafio de login com dois fatores desconhecido ou expirado.
+    InvalidChallenge,
+    TwoFactorAlreadyEnabled,
+    TwoFactorNotEnabled,
+    // until é o timestamp unix do fim de uma suspensão,
+    // None para um banimento permanente.
+    AccountBanned { reason: String, until: Option<i64> },
+    // O fim de uma suspensão precisa estar no futuro.
+    InvalidBanExpiry,
+    // Nomes de sala seguem as mesmas regras de caracteres
+    // dos usernames, com até max caracteres.
+    InvalidRoomName { max: usize },
+    RoomAlreadyExists,
+    RoomNotFound,
+    NotRoomMember,
+    // Posts não podem ser vazios nem passar de max caracteres.
+    InvalidPost { max: usize },
+    // A mensagem não existe ou não é da conversa dada.
+    MessageNotFound,
 }
 
 impl From<argon2::password_hash::Error> for AuthenticateErrorType {
@@ -55,12 +95,49 @@ impl fmt::Display for AuthenticateErrorType {
             AuthenticateErrorType::Hash => write!(f, "Erro de argon2"),
             AuthenticateErrorType::Envy => write!(f, "Erro de dotenvy"),
             AuthenticateErrorType::Sql => write!(f, "Erro de slqx"),
+            AuthenticateErrorType::UnsupportedDatabase => write!(f, "Esquema de DATABASE_URL não suportado"),
             AuthenticateErrorType::PasswordMismatch => write!(f, "Senha inválida"),
             AuthenticateErrorType::UserNotFound => write!(f, "Usuário não encontrado"),
             AuthenticateErrorType::UserNotAdded => write!(f, "Usuário não foi cadastrado"),
             AuthenticateErrorType::UserAlreadyExists => write!(f, "Usuário já está cadastrado"),
             AuthenticateErrorType::OfflineMessageError => write!(f, "Erro ao tentar guardar mensagem offline"),
             AuthenticateErrorType::UserTxNotExist => write!(f, "Sender do user não existe"),
+            AuthenticateErrorType::InvalidToken => write!(f, "Token de sessão inválido ou revogado"),
+            AuthenticateErrorType::TokenExpired => write!(f, "Token de sessão expirado"),
+            AuthenticateErrorType::TooManyAttempts { retry_after } =>
+                write!(f, "Muitas tentativas de login; tente de novo em {retry_after} segundos"),
+            AuthenticateErrorType::InvalidResetCode => write!(f, "Código de redefinição de senha inválido ou expirado"),
+            AuthenticateErrorType::UsernameTooShort { min } =>
+                write!(f, "Username precisa ter pelo menos {min} caracteres"),
+            AuthenticateErrorType::UsernameTooLong { max } =>
+                write!(f, "Username pode ter no máximo {max} caracteres"),
+            AuthenticateErrorType::UsernameInvalidCharacters =>
+                write!(f, "Username só pode ter letras, dígitos, '_', '.' e '-', e precisa começar com letra ou dígito"),
+            AuthenticateErrorType::UsernameReserved => write!(f, "Esse username é reservado"),
+            AuthenticateErrorType::PasswordTooShort { min } =>
+                write!(f, "Senha precisa ter pelo menos {min} caracteres"),
+            AuthenticateErrorType::PasswordTooLong { max } =>
+                write!(f, "Senha pode ter no máximo {max} caracteres"),
+            AuthenticateErrorType::PasswordTooWeak { min_classes } =>
+                write!(f, "Senha precisa misturar pelo menos {min_classes} tipos de caractere (minúsculas, maiúsculas, dígitos, símbolos)"),
+            AuthenticateErrorType::PasswordContainsUsername => write!(f, "Senha não pode conter o username"),
+            AuthenticateErrorType::InvalidTwoFactorCode => write!(f, "Código de verificação inválido"),
+            AuthenticateErrorType::InvalidChallenge => write!(f, "Desafio de login inválido ou expirado"),
+            AuthenticateErrorType::TwoFactorAlreadyEnabled => write!(f, "Autenticação em dois fatores já está ativa"),
+            AuthenticateErrorType::TwoFactorNotEnabled => write!(f, "Autenticação em dois fatores não está ativa"),
+            AuthenticateErrorType::AccountBanned { reason, until: None } =>
+                write!(f, "Conta banida: {reason}"),
+            AuthenticateErrorType::AccountBanned { reason, until: Some(until) } =>
+                write!(f, "Conta suspensa até {until} (timestamp unix): {reason}"),
+            AuthenticateErrorType::InvalidBanExpiry => write!(f, "O fim da suspensão precisa estar no futuro"),
+            AuthenticateErrorType::InvalidRoomName { max } =>
+                write!(f, "Nome de sala precisa ter até {max} letras, dígitos, '_', '.' ou '-', começando com letra ou dígito"),
+            AuthenticateErrorType::RoomAlreadyExists => write!(f, "Já existe uma sala com esse nome"),
+            AuthenticateErrorType::RoomNotFound => write!(f, "Sala não encontrada"),
+            AuthenticateErrorType::NotRoomMember => write!(f, "Você não participa dessa sala"),
+            AuthenticateErrorType::InvalidPost { max } =>
+                write!(f, "Post precisa ter entre 1 e {max} caracteres"),
+            AuthenticateErrorType::MessageNotFound => write!(f, "Mensagem não encontrada nessa conversa"),
         }
     }
 }
@@ -76,6 +153,13 @@ pub enum ProtocolError {
     UserDisconnectedError,
     UserNotExist,
     UserOffline,
+    NotAuthenticated,
+    // O papel do usuário não permite o protocolo enviado.
+    PermissionDenied,
+    // O destinatário da mensagem está banido.
+    UserBanned,
+    // Um ack com mais de max ids.
+    TooManyIds { max: usize },
     AuthenticateError(AuthenticateErrorType),
 }
 
@@ -96,8 +180,75 @@ impl fmt::Display for ProtocolError {
             ProtocolError::UserDisconnectedError => write!(f, "Erro ao tentar remover usuário do chat"),
             ProtocolError::UserNotExist => write!(f, "Usuário inexistente"),
             ProtocolError::UserOffline => write!(f, "Usuário offline"),
+            ProtocolError::NotAuthenticated => write!(f, "É preciso se autenticar antes"),
+            ProtocolError::PermissionDenied => write!(f, "Permissão negada"),
+            ProtocolError::UserBanned => write!(f, "Usuário banido"),
+            ProtocolError::TooManyIds { max } => write!(f, "No máximo {max} ids por vez"),
             ProtocolError::AuthenticateError(e) => write!(f, "Erro de autenticação: {e}"),
             ProtocolError::Serde => write!(f, "Erro ao tentar serializar/deserializar uma mensagem"),
         }
     }
 }
+
+// Erros do subsistema de migrations da database.
+#[derive(Debug)]
+pub enum MigrationError {
+    Sql(sqlx::Error),
+    // A migration já aplicada foi alterada depois
+    // de ter sido rodada na database.
+    ChecksumMismatch { version: i64 },
+    // A database tem uma migration aplicada que
+    // não existe nesta versão do programa.
+    UnknownVersion { version: i64 },
+    // O backend não possui schema (ex: memory://).
+    Unsupported,
+}
+
+impl From<sqlx::Error> for MigrationError {
+    fn from(e: sqlx::Error) -> Self {
+        Self::Sql(e)
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Sql(e) => write!(f, "Erro de sqlx: {e}"),
+            MigrationError::ChecksumMismatch { version } => 
+                write!(f, "Migration {version} foi alterada depois de aplicada"),
+            MigrationError::UnknownVersion { version } =>
+                write!(f, "Migration {version} aplicada na database é desconhecida"),
+            MigrationError::Unsupported => write!(f, "Backend de armazenamento não suporta migrations"),
+        }
+    }
+}
+
+// Erros ao carregar a configuração do servidor. Sempre
+// carregam o suficiente para o usuário saber o que corrigir.
+#[derive(Debug)]
+pub enum ConfigError {
+    // Não foi possível ler o arquivo de configuração.
+    File { path: String, reason: String },
+    // O arquivo existe mas não é um toml válido.
+    Parse { path: String, reason: String },
+    // Uma variável de ambiente tem um valor inválido.
+    Env { var: String, reason: String },
+    // Um campo tem um valor inválido depois de todas
+    // as camadas (arquivo, ambiente e flags) aplicadas.
+    Invalid { field: String, reason: String },
+}
+
+impl std::error::Error for ConfigError {}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::File { path, reason } => write!(f, "Erro ao ler {path}: {reason}"),
+            ConfigError::Parse { path, reason } => write!(f, "Erro de sintaxe em {path}: {reason}"),
+            ConfigError::Env { var, reason } => write!(f, "Variável de ambiente {var} inválida: {reason}"),
+            ConfigError::Invalid { field, reason } => write!(f, "Valor inválido para {field}: {reason}"),
+        }
+    }
+}
@@ -21,6 +21,8 @@ use error::{
 	ProtocolError,
 };
 
+use users::Role;
+
 use std::future::Future;
 
 
@@ -65,8 +67,17 @@ pub trait Protocol: Sized + Serialize + for<'de> Deserialize<'de> {
 #[derive(Debug, Deserialize, Serialize)]
 #[serde(tag = "type")]
 pub enum ClientProtocol {
+    // O remetente não vai no protocolo: o servidor usa
+    // o usuário autenticado na conexão.
     #[serde(rename = "send_message")]
-    SendMessage { from: String, to: String, text: String },
+    SendMessage { to: String, text: String },
+
+    // Confirma o recebimento de mensagens (pelo id de
+    // ServerProtocol::Message). Mensagens não confirmadas
+    // são enviadas de novo no próximo login. Mais de
+    // users::MAX_ACK_IDS ids é recusado com TooManyIds.
+    #[serde(rename = "ack_messages")]
+    AckMessages { ids: Vec<i64> },
 
     #[serde(rename = "request_authenticate")]
     RequestAuthenticate { username: String, password: String },
@@ -74,10 +85,199 @@ pub enum ClientProtocol {
     #[serde(rename = "create_user")]
     CreateUser { username: String, password: String },
 
-    /* 
-    Protocols a implementar:
-    RequestFeed,
-    */
+    // Reconecta usando o token recebido em Authenticated,
+    // sem precisar da senha.
+    #[serde(rename = "authenticate_with_token")]
+    AuthenticateWithToken { token: String },
+
+    // Invalida um token do próprio usuário antes dele expirar.
+    #[serde(rename = "revoke_token")]
+    RevokeToken { token: String },
+
+    #[serde(rename = "change_password")]
+    ChangePassword { current_password: String, new_password: String },
+
+    // Redefine a senha com um código de uso único gerado
+    // por um administrador. Não exige autenticação.
+    #[serde(rename = "reset_password")]
+    ResetPassword { username: String, code: String, new_password: String },
+
+    #[serde(rename = "delete_account")]
+    DeleteAccount { password: String },
+
+    // Desassocia a conexão do usuário sem fechar a socket,
+    // que pode ser usada para outro login. Se token for
+    // dado ele também é revogado.
+    #[serde(rename = "logout")]
+    Logout {
+        #[serde(default)]
+        token: Option<String>,
+    },
+
+    // Responde ao TwoFactorRequired enviado no login, com
+    // um código TOTP ou de recuperação.
+    #[serde(rename = "verify_two_factor")]
+    VerifyTwoFactor { challenge: String, code: String },
+
+    // Gera um segredo TOTP novo; o 2FA só fica ativo
+    // depois de ConfirmTwoFactor.
+    #[serde(rename = "enable_two_factor")]
+    EnableTwoFactor,
+
+    #[serde(rename = "confirm_two_factor")]
+    ConfirmTwoFactor { code: String },
+
+    #[serde(rename = "disable_two_factor")]
+    DisableTwoFactor { code: String },
+
+    // Protocolos de administração; veja required_role.
+    #[serde(rename = "list_online_users")]
+    ListOnlineUsers,
+
+    // Encerra todas as sessões do usuário e revoga os
+    // tokens dele.
+    #[serde(rename = "kick_user")]
+    KickUser { username: String },
+
+    // Sem until o banimento é permanente; com until
+    // (timestamp unix) é uma suspensão até esse momento.
+    #[serde(rename = "ban_user")]
+    BanUser {
+        username: String,
+        reason: String,
+        #[serde(default)]
+        until: Option<i64>,
+    },
+
+    #[serde(rename = "unban_user")]
+    UnbanUser { username: String },
+
+    #[serde(rename = "set_role")]
+    SetRole { username: String, role: Role },
+
+    // Cria a sala e entra nela.
+    #[serde(rename = "create_room")]
+    CreateRoom { name: String },
+
+    #[serde(rename = "join_room")]
+    JoinRoom { name: String },
+
+    #[serde(rename = "leave_room")]
+    LeaveRoom { name: String },
+
+    #[serde(rename = "list_rooms")]
+    ListRooms,
+
+    // Só membros da sala podem enviar mensagens a ela.
+    #[serde(rename = "send_room_message")]
+    SendRoomMessage { room: String, text: String },
+
+    // Como AckMessages, com ids de ServerProtocol::RoomMessage.
+    #[serde(rename = "ack_room_messages")]
+    AckRoomMessages { ids: Vec<i64> },
+
+    // Mensagens trocadas com with, da mais nova para a mais
+    // antiga. Sem before vêm as últimas; para as anteriores,
+    // before é o id da mais antiga já recebida.
+    #[serde(rename = "request_history")]
+    RequestHistory {
+        with: String,
+        #[serde(default)]
+        before: Option<i64>,
+        #[serde(default)]
+        limit: Option<u32>,
+    },
+
+    // Presença atual dos usuários. Daí em diante a sessão
+    // recebe PresenceChanged sempre que um deles ficar
+    // online ou offline.
+    #[serde(rename = "request_presence")]
+    RequestPresence { usernames: Vec<String> },
+
+    // Publica um post no feed público.
+    #[serde(rename = "create_post")]
+    CreatePost { text: String },
+
+    // Posts do feed, do mais novo para o mais antigo. Como
+    // em RequestHistory, sem before vêm os últimos; para os
+    // anteriores, before é o id do mais antigo já recebido.
+    #[serde(rename = "request_feed")]
+    RequestFeed {
+        #[serde(default)]
+        before: Option<i64>,
+        #[serde(default)]
+        limit: Option<u32>,
+    },
+
+    // Passa a receber ServerProtocol::NewPost a cada post
+    // novo, até UnsubscribeFeed, o logout ou a conexão fechar.
+    #[serde(rename = "subscribe_feed")]
+    SubscribeFeed,
+
+    #[serde(rename = "unsubscribe_feed")]
+    UnsubscribeFeed,
+
+    // Enviado repetidamente enquanto o usuário digita uma
+    // mensagem para to. Nunca é guardado; se to estiver
+    // offline é descartado.
+    #[serde(rename = "typing")]
+    Typing { to: String },
+
+    // Marca como lidas as mensagens da conversa com with até
+    // up_to (id de uma mensagem dela). O cursor de leitura só
+    // anda para frente.
+    #[serde(rename = "mark_read")]
+    MarkRead { with: String, up_to: i64 },
+}
+
+impl ClientProtocol {
+    // Papel mínimo para enviar o protocolo, ou None para os
+    // que podem ser enviados antes da conexão estar
+    // autenticada. Sem autenticação o pedido é recusado com
+    // ProtocolError::NotAuthenticated, e com um papel abaixo
+    // do exigido com ProtocolError::PermissionDenied.
+    pub fn required_role(&self) -> Option<Role> {
+        match self {
+            ClientProtocol::RequestAuthenticate { .. }
+            | ClientProtocol::CreateUser { .. }
+            | ClientProtocol::AuthenticateWithToken { .. }
+            | ClientProtocol::ResetPassword { .. }
+            | ClientProtocol::VerifyTwoFactor { .. } => None,
+
+            ClientProtocol::ListOnlineUsers
+            | ClientProtocol::KickUser { .. }
+            | ClientProtocol::BanUser { .. }
+            | ClientProtocol::UnbanUser { .. } => Some(Role::Moderator),
+
+            ClientProtocol::SetRole { .. } => Some(Role::Admin),
+
+            // Sem curinga, para que um protocolo novo precise
+            // ter o papel escolhido aqui.
+            ClientProtocol::SendMessage { .. }
+            | ClientProtocol::AckMessages { .. }
+            | ClientProtocol::RevokeToken { .. }
+            | ClientProtocol::ChangePassword { .. }
+            | ClientProtocol::DeleteAccount { .. }
+            | ClientProtocol::Logout { .. }
+            | ClientProtocol::EnableTwoFactor
+            | ClientProtocol::ConfirmTwoFactor { .. }
+            | ClientProtocol::DisableTwoFactor { .. }
+            | ClientProtocol::CreateRoom { .. }
+            | ClientProtocol::JoinRoom { .. }
+            | ClientProtocol::LeaveRoom { .. }
+            | ClientProtocol::ListRooms
+            | ClientProtocol::SendRoomMessage { .. }
+            | ClientProtocol::AckRoomMessages { .. }
+            | ClientProtocol::RequestHistory { .. }
+            | ClientProtocol::RequestPresence { .. }
+            | ClientProtocol::CreatePost { .. }
+            | ClientProtocol::RequestFeed { .. }
+            | ClientProtocol::SubscribeFeed
+            | ClientProtocol::UnsubscribeFeed
+            | ClientProtocol::Typing { .. }
+            | ClientProtocol::MarkRead { .. } => Some(Role::User),
+        }
+    }
 }
 
 // Protocolos enviados pelo server ao client com
@@ -86,21 +286,122 @@ pub enum ClientProtocol {
 #[derive(Debug, Serialize, Deserialize)]
 #[serde(tag = "type")]
 pub enum ServerProtocol {
+    // id e sent_at (timestamp unix, em segundos) são
+    // definidos pelo servidor e são os mesmos no histórico.
     #[serde(rename = "message")]
-    Message { from: String, to: String, text: String },
+    Message { id: i64, from: String, to: String, text: String, sent_at: i64 },
+
+    // Enviado às sessões de quem mandou a mensagem id
+    // quando o destinatário confirma o recebimento.
+    #[serde(rename = "message_delivered")]
+    MessageDelivered { id: i64, to: String, delivered_at: i64 },
 
-    #[serde(rename = "user_disconnected")]
-    UserDisconnected { username: String },
+    // Enviado às sessões que acompanham a presença de
+    // username (veja RequestPresence) quando ele fica online
+    // ou offline. last_seen é quando ficou offline pela
+    // última vez (timestamp unix, em segundos).
+    #[serde(rename = "presence_changed")]
+    PresenceChanged { username: String, status: PresenceStatus, last_seen: Option<i64> },
 
     #[serde(rename = "error")]
     Error { error: ProtocolError },
 
+    // token serve para reconectar com AuthenticateWithToken
+    // até expires_at (timestamp unix, em segundos). unread
+    // traz as conversas com mensagens ainda não lidas.
     #[serde(rename = "authenticated")]
-    Authenticated,
+    Authenticated { username: String, token: String, expires_at: i64, unread: Vec<UnreadCount> },
+
+    // A senha confere, mas a conta tem 2FA: o login
+    // continua com VerifyTwoFactor até expires_at.
+    #[serde(rename = "two_factor_required")]
+    TwoFactorRequired { challenge: String, expires_at: i64 },
+
+    // secret (base32) e uri (otpauth://) servem para
+    // configurar o app autenticador.
+    #[serde(rename = "two_factor_enrollment")]
+    TwoFactorEnrollment { secret: String, uri: String },
+
+    // Os códigos de recuperação só são mostrados aqui.
+    #[serde(rename = "two_factor_enabled")]
+    TwoFactorEnabled { recovery_codes: Vec<String> },
+
+    #[serde(rename = "two_factor_disabled")]
+    TwoFactorDisabled,
+
+    #[serde(rename = "token_revoked")]
+    TokenRevoked,
+
+    // Os tokens antigos foram revogados; token substitui
+    // o que o client tinha guardado.
+    #[serde(rename = "password_changed")]
+    PasswordChanged { token: String, expires_at: i64 },
+
+    #[serde(rename = "password_reset")]
+    PasswordReset,
+
+    #[serde(rename = "logged_out")]
+    LoggedOut,
+
+    // Enviado pelo servidor, sem pedido do client, a uma
+    // sessão encerrada por algo que aconteceu em outra
+    // conexão. A socket é fechada logo depois.
+    #[serde(rename = "session_ended")]
+    SessionEnded { reason: SessionEndReason },
 
     #[serde(rename = "user_created")]
     UserCreated,
 
+    #[serde(rename = "online_users")]
+    OnlineUsers { users: Vec<OnlineUser> },
+
+    // sessions é quantas sessões foram encerradas.
+    #[serde(rename = "user_kicked")]
+    UserKicked { username: String, sessions: usize },
+
+    #[serde(rename = "user_banned")]
+    UserBanned { username: String, until: Option<i64> },
+
+    #[serde(rename = "user_unbanned")]
+    UserUnbanned { username: String },
+
+    #[serde(rename = "role_changed")]
+    RoleChanged { username: String, role: Role },
+
+    #[serde(rename = "room_created")]
+    RoomCreated { room: String },
+
+    // members já inclui quem entrou.
+    #[serde(rename = "room_joined")]
+    RoomJoined { room: String, members: Vec<String> },
+
+    #[serde(rename = "room_left")]
+    RoomLeft { room: String },
+
+    #[serde(rename = "rooms")]
+    Rooms { rooms: Vec<RoomInfo> },
+
+    // Mensagem enviada à sala room. Como Message, id e
+    // sent_at são definidos pelo servidor.
+    #[serde(rename = "room_message")]
+    RoomMessage { id: i64, room: String, from: String, text: String, sent_at: i64 },
+
+    // Enviados aos outros membros online da sala.
+    #[serde(rename = "member_joined")]
+    MemberJoined { room: String, username: String },
+
+    #[serde(rename = "member_left")]
+    MemberLeft { room: String, username: String },
+
+    // Uma página do histórico, da mais antiga para a mais
+    // nova. has_more diz se há mensagens anteriores.
+    #[serde(rename = "history")]
+    History { with: String, messages: Vec<HistoryMessage>, has_more: bool },
+
+    // Usuários que não existem ficam de fora.
+    #[serde(rename = "presence")]
+    Presence { users: Vec<UserPresence> },
+
     // Protocolo especial que serve
     // apenas "comunicar" o proprio servidor
     // que algo pedido pelo cliente foi
@@ -111,14 +412,118 @@ pub enum ServerProtocol {
     #[serde(rename = "success")]
     Success,
 
-    /* 
-    Protocols a implementar:
-    Feed,
-    */
+    // Resposta a CreatePost, com o id e o horário dados
+    // pelo servidor.
+    #[serde(rename = "post_created")]
+    PostCreated { post: FeedPost },
+
+    // Enviado às sessões inscritas no feed, menos a que
+    // publicou o post.
+    #[serde(rename = "new_post")]
+    NewPost { post: FeedPost },
+
+    // Uma página do feed, do mais novo para o mais antigo.
+    // has_more diz se há posts anteriores.
+    #[serde(rename = "feed")]
+    Feed { posts: Vec<FeedPost>, has_more: bool },
+
+    #[serde(rename = "feed_subscribed")]
+    FeedSubscribed,
+
+    #[serde(rename = "feed_unsubscribed")]
+    FeedUnsubscribed,
+
+    // from está digitando uma mensagem para quem recebe.
+    // Repetido de tempos em tempos enquanto ele digitar.
+    #[serde(rename = "typing")]
+    Typing { from: String },
+
+    // from parou de digitar (nenhum Typing dele por um tempo).
+    #[serde(rename = "stopped_typing")]
+    StoppedTyping { from: String },
+
+    // Resposta a MarkRead.
+    #[serde(rename = "marked_read")]
+    MarkedRead { with: String, up_to: i64 },
+
+    // Enviado às sessões do outro participante quando by lê
+    // a conversa até a mensagem up_to.
+    #[serde(rename = "messages_read")]
+    MessagesRead { by: String, up_to: i64, read_at: i64 },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SessionEndReason {
+    // A senha foi trocada em outra sessão.
+    PasswordChanged,
+    // A senha foi redefinida com um código de reset.
+    PasswordReset,
+    AccountDeleted,
+    // Um admin derrubou as sessões do usuário.
+    Kicked,
+    Banned,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct OnlineUser {
+    pub username: String,
+    // Conexões abertas pelo usuário.
+    pub sessions: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct RoomInfo {
+    pub name: String,
+    pub members: usize,
+    // Se quem pediu a lista participa da sala.
+    pub joined: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HistoryMessage {
+    pub id: i64,
+    pub from: String,
+    pub to: String,
+    pub text: String,
+    // Timestamp unix, em segundos.
+    pub sent_at: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UnreadCount {
+    pub with: String,
+    // Mensagens recebidas de with depois do cursor de leitura.
+    pub count: usize,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct FeedPost {
+    pub id: i64,
+    pub author: String,
+    pub text: String,
+    // Timestamp unix, em segundos.
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PresenceStatus {
+    Online,
+    Offline,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct UserPresence {
+    pub username: String,
+    pub status: PresenceStatus,
+    pub last_seen: Option<i64>,
 }
 
 pub enum InternalProtocol {
-    OfflineMessage { username: String }
+    OfflineMessage { username: String },
+    // A sessão de username acabou de ser autenticada.
+    PresenceChanged { username: String },
 }
 
 impl Protocol for ClientProtocol {}
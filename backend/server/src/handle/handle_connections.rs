@@ -15,9 +15,12 @@ use axum::{
         ConnectInfo,
         State,
     },
-    response::Response,
+    http::StatusCode,
+    response::{IntoResponse, Response},
 };
 
+use tracing::{info, warn};
+
 use futures_util::{
     sink::SinkExt,
     stream::StreamExt,
@@ -30,14 +33,17 @@ use protocols::{
 
 use users::{
     Users,
-    User,
 };
 
+use crate::handle::match_protocol::presence::announce_presence;
+
 use crate::handle::handle_protocols::{
     handle_protocol,
     handle_internal,
 };
 
+use crate::state::AppState;
+
 use types::{Tx, Rx, ArcReader,
     ArcWriter, ArcUser, ArcUsers,
     TxInt, RxInt};
@@ -46,13 +52,24 @@ use types::{Tx, Rx, ArcReader,
 pub async fn handler
 (
     ws: WebSocketUpgrade,
-    State(users): State<Users>,
+    State(state): State<AppState>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
 ) -> Response 
 {
+    // A vaga fica ocupada enquanto a socket estiver aberta.
+    let Some(guard) = state.try_connect() else {
+        warn!("conexão de {addr} recusada: limite de conexões atingido");
+        return (StatusCode::SERVICE_UNAVAILABLE, "Limite de conexões atingido")
+            .into_response();
+    };
+
     // Se o HTTPS vier com a tag UPGRADE, aprimora
     // o WebSocketUpgrade em um WebSocket
-    ws.on_upgrade(move |socket| handle_socket(socket, users, addr))
+    ws.max_message_size(state.limits.max_message_size)
+        .on_upgrade(move |socket| async move {
+            handle_socket(socket, state.users, addr).await;
+            drop(guard);
+        })
 }
 
 // Função principal para leitura e envio de 
@@ -66,7 +83,7 @@ pub async fn handle_socket
     addr: SocketAddr
 )
 {
-    println!("client conectado: {addr}");
+    info!("client conectado: {addr}");
     // Cria a socket e o channel.
     let (tx, rx): (Tx, Rx) = unbounded_channel();
     let (txi, rxi): (TxInt, RxInt) = unbounded_channel();
@@ -74,44 +91,58 @@ pub async fn handle_socket
 
     let reader = Arc::new(Mutex::new(read));
     let writer = Arc::new(Mutex::new(write));
-    let user = Arc::new(Mutex::new(User::new("")));
+    let user = Arc::new(Mutex::new(None));
     let users = Arc::new(Mutex::new(users));
 
     // Task responsável pela leitura
-    let tx_task = tokio::spawn(receive_from_socket(
+    let mut tx_task = tokio::spawn(receive_from_socket(
         Arc::clone(&reader),
         Arc::clone(&user),
         Arc::clone(&users),
         tx.clone(),
         txi.clone(),
+        addr,
     ));
 
     // Task responsável pelo canal interno
-    let int_channel_task = tokio::spawn(handle_internal_channel(
+    let mut int_channel_task = tokio::spawn(handle_internal_channel(
         Arc::clone(&users),
+        tx.clone(),
         rxi,
     ));
 
     // Task responsável pelo envio
-    let rx_task = tokio::spawn(send_to_socket(
+    let mut rx_task = tokio::spawn(send_to_socket(
         writer,
         rx,
         addr,
     ));
 
-    // Espera até uma das duas tasks acima criadas
-    // terem terminado, cancelando de forma segura
-    // a outra.
+    // Espera até uma das tasks acima criadas
+    // terminar e cancela as outras, o que fecha a
+    // socket mesmo que o client não responda ao Close.
     tokio::select! {
-        _ = tx_task => {}
-        _ = rx_task => {}
-        _ = int_channel_task => {}
+        _ = &mut tx_task => {}
+        _ = &mut rx_task => {}
+        _ = &mut int_channel_task => {}
     }
 
-    println!("client desconectado: {addr:?}");
+    tx_task.abort();
+    rx_task.abort();
+    int_channel_task.abort();
+
+    info!("client desconectado: {addr}");
     let mut users = users.lock().await;
-    let user = user.lock().await;
-    users.remove_user(&*user.username).await;
+
+    // Só a sessão desta conexão é encerrada; outras
+    // conexões do mesmo usuário continuam online.
+    if let Some(user) = &*user.lock().await {
+        users.remove_session(&user.username, &tx).await;
+        announce_presence(&users, &user.username, tx.clone()).await;
+    }
+
+    users.unwatch_presence(&tx);
+    users.unsubscribe_feed(&tx);
 }
 
 // Função responsável pela leitura de dados.
@@ -122,6 +153,7 @@ async fn receive_from_socket
     users: ArcUsers,
     tx: Tx,
     txi: TxInt,
+    addr: SocketAddr,
 )
 {  
     let mut reader = reader.lock().await;
@@ -136,6 +168,7 @@ async fn receive_from_socket
                         users.clone(),
                         tx.clone(),
                         txi.clone(),
+                        addr,
                 ).await;             
             }).await;
         }
@@ -151,10 +184,18 @@ async fn send_to_socket
 )
 {
     while let Some(msg) = rx.recv().await {
+        // Um Close enviado pelo servidor (ex: end_sessions)
+        // encerra a conexão depois de ser escrito.
+        let closing = matches!(msg, Message::Close(_));
+
         let mut writer = writer.lock().await;
         if writer.send(msg).await.is_err() {
             let _ = writer.close().await;
-            eprintln!("Conexão com {addr:?} foi fechada");
+            warn!("Conexão com {addr} foi fechada");
+            break;
+        }
+
+        if closing {
             break;
         }
     }
@@ -163,6 +204,7 @@ async fn send_to_socket
 async fn handle_internal_channel
 (
     users: ArcUsers,
+    tx: Tx,
     mut rx: RxInt,
 )
 {
@@ -170,6 +212,7 @@ async fn handle_internal_channel
         handle_internal(
             msg,
             users.clone(),
+            tx.clone(),
         ).await;
     }
 }
\ No newline at end of file
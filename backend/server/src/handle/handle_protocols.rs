@@ -1,19 +1,77 @@
+use std::net::SocketAddr;
+
+use error::ProtocolError;
+
 use protocols::{
     ClientProtocol, 
     InternalProtocol,
+    ServerProtocol,
+};
+
+use users::{
+    User,
+    Role,
 };
 
 use types::{Tx, TxInt, ArcUser, ArcUsers};
 
 use crate::handle::match_protocol::client::{
     send_message,
+    ack_messages,
     request_authenticate,
+    authenticate_with_token,
+    revoke_token,
     create_user,
+    change_password,
+    reset_password,
+    delete_account,
+    logout,
+    verify_two_factor,
+    enable_two_factor,
+    confirm_two_factor,
+    disable_two_factor,
+    request_history,
+    mark_read,
+};
+
+use crate::handle::match_protocol::admin::{
+    list_online_users,
+    kick_user,
+    ban_user,
+    unban_user,
+    set_role,
+};
+
+use crate::handle::match_protocol::rooms::{
+    create_room,
+    join_room,
+    leave_room,
+    list_rooms,
+    send_room_message,
+    ack_room_messages,
+};
+
+use crate::handle::match_protocol::feed::{
+    create_post,
+    request_feed,
+    subscribe_feed,
+    unsubscribe_feed,
 };
 
+use crate::handle::match_protocol::presence::{
+    request_presence,
+    announce_presence,
+};
+
+use crate::handle::match_protocol::typing::typing;
+
 use crate::handle::match_protocol::internal::offline_message;
 
+use crate::handle::match_protocol::utils::handle_instance;
+
 // Lida com ClientProtocol's enviados pelo client.
+// addr é o endereço do client, usado para limitar
+// tentativas de login por ip.
 pub async fn handle_protocol
 (
     protocol: ClientProtocol,
@@ -21,14 +79,28 @@ pub async fn handle_protocol
     users: ArcUsers,
     tx: Tx,
     txi: TxInt,
+    addr: SocketAddr,
 )
 {   
+    // Qualquer protocolo além dos de autenticação
+    // exige que a conexão já esteja autenticada, e os de
+    // administração também um papel mínimo.
+    let current = user.lock().await.clone();
+
+    if let Err(error) = check_permission(&protocol, current.as_ref(), &users).await {
+        handle_instance(tx, ServerProtocol::Error { error }).await;
+        return;
+    }
+
     // Os drops explícitos são usadas para
     // liberar o Mutex o mais cedo possível, isto é,
     // na medida em que o Mutex não é mais necessário, para não
     // bloquear o valor por mais tempo que o necessário.
     match protocol {
-        ClientProtocol::SendMessage { from, to, text } => {
+        ClientProtocol::SendMessage { to, text } => {
+            // Garantido pela verificação acima.
+            let Some(from) = current else { return };
+
             send_message(
                 from,
                 to,
@@ -38,10 +110,22 @@ pub async fn handle_protocol
             ).await
         },
 
+        ClientProtocol::AckMessages { ids } => {
+            let Some(current) = current else { return };
+
+            ack_messages(
+                current,
+                ids,
+                users,
+                tx,
+            ).await
+        },
+
         ClientProtocol::RequestAuthenticate { username, password } => {
             request_authenticate(
                 username,
                 password,
+                addr.ip(),
                 user,
                 users,
                 tx,
@@ -53,17 +137,366 @@ pub async fn handle_protocol
             create_user(
                 username,
                 password,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::AuthenticateWithToken { token } => {
+            authenticate_with_token(
+                token,
+                user,
+                users,
+                tx,
+                txi,
+            ).await
+        },
+
+        ClientProtocol::RevokeToken { token } => {
+            let Some(current) = current else { return };
+
+            revoke_token(
+                current,
+                token,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::ChangePassword { current_password, new_password } => {
+            let Some(current) = current else { return };
+
+            change_password(
+                current,
+                current_password,
+                new_password,
+                addr.ip(),
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::ResetPassword { username, code, new_password } => {
+            reset_password(
+                username,
+                code,
+                new_password,
+                addr.ip(),
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::DeleteAccount { password } => {
+            let Some(current) = current else { return };
+
+            delete_account(
+                current,
+                password,
+                addr.ip(),
+                user,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::Logout { token } => {
+            let Some(current) = current else { return };
+
+            logout(
+                current,
+                token,
+                user,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::VerifyTwoFactor { challenge, code } => {
+            verify_two_factor(
+                challenge,
+                code,
+                addr.ip(),
+                user,
+                users,
+                tx,
+                txi,
+            ).await
+        },
+
+        ClientProtocol::EnableTwoFactor => {
+            let Some(current) = current else { return };
+
+            enable_two_factor(
+                current,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::ConfirmTwoFactor { code } => {
+            let Some(current) = current else { return };
+
+            confirm_two_factor(
+                current,
+                code,
+                addr.ip(),
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::DisableTwoFactor { code } => {
+            let Some(current) = current else { return };
+
+            disable_two_factor(
+                current,
+                code,
+                addr.ip(),
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::ListOnlineUsers => {
+            list_online_users(
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::KickUser { username } => {
+            let Some(current) = current else { return };
+
+            kick_user(
+                current,
+                username,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::BanUser { username, reason, until } => {
+            let Some(current) = current else { return };
+
+            ban_user(
+                current,
+                username,
+                reason,
+                until,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::UnbanUser { username } => {
+            let Some(current) = current else { return };
+
+            unban_user(
+                current,
+                username,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::SetRole { username, role } => {
+            set_role(
+                username,
+                role,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::CreateRoom { name } => {
+            let Some(current) = current else { return };
+
+            create_room(
+                current,
+                name,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::JoinRoom { name } => {
+            let Some(current) = current else { return };
+
+            join_room(
+                current,
+                name,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::LeaveRoom { name } => {
+            let Some(current) = current else { return };
+
+            leave_room(
+                current,
+                name,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::ListRooms => {
+            let Some(current) = current else { return };
+
+            list_rooms(
+                current,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::SendRoomMessage { room, text } => {
+            let Some(current) = current else { return };
+
+            send_room_message(
+                current,
+                room,
+                text,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::AckRoomMessages { ids } => {
+            let Some(current) = current else { return };
+
+            ack_room_messages(
+                current,
+                ids,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::RequestHistory { with, before, limit } => {
+            let Some(current) = current else { return };
+
+            request_history(
+                current,
+                with,
+                before,
+                limit,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::RequestPresence { usernames } => {
+            request_presence(
+                usernames,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::CreatePost { text } => {
+            let Some(current) = current else { return };
+
+            create_post(
+                current,
+                text,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::RequestFeed { before, limit } => {
+            request_feed(
+                before,
+                limit,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::SubscribeFeed => {
+            subscribe_feed(
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::UnsubscribeFeed => {
+            unsubscribe_feed(
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::MarkRead { with, up_to } => {
+            let Some(current) = current else { return };
+
+            mark_read(
+                current,
+                with,
+                up_to,
+                users,
+                tx,
+            ).await
+        },
+
+        ClientProtocol::Typing { to } => {
+            let Some(current) = current else { return };
+
+            typing(
+                current,
+                to,
+                users,
                 tx,
             ).await
         },
     }
 }
 
+// Confere se a conexão pode enviar protocol (veja
+// ClientProtocol::required_role). O papel é lido da
+// database só quando o protocolo exige mais que Role::User,
+// então rebaixar um admin vale já no próximo pedido dele.
+async fn check_permission
+(
+    protocol: &ClientProtocol,
+    current: Option<&User>,
+    users: &ArcUsers,
+) -> Result<(), ProtocolError>
+{
+    let Some(required) = protocol.required_role() else {
+        return Ok(());
+    };
 
+    let Some(current) = current else {
+        return Err(ProtocolError::NotAuthenticated);
+    };
+
+    if required == Role::User {
+        return Ok(());
+    }
+
+    let role = users.lock().await
+        .role(&current.username)
+        .await
+        .map_err(ProtocolError::AuthenticateError)?;
+
+    match role >= required {
+        true => Ok(()),
+        false => Err(ProtocolError::PermissionDenied),
+    }
+}
+
+
+// Lida com InternalProtocol's enviados pela própria
+// conexão; tx é o sender da sessão dela.
 pub async fn handle_internal
 (
     protocol: InternalProtocol,
     users: ArcUsers,
+    tx: Tx,
 )
 {
     match protocol {
@@ -71,8 +504,14 @@ pub async fn handle_internal
             offline_message(
                 username,
                 users,
+                tx,
             ).await
-        }
+        },
+
+        InternalProtocol::PresenceChanged { username } => {
+            let users = users.lock().await;
+            announce_presence(&users, &username, tx).await;
+        },
     }
 }
 
@@ -0,0 +1,197 @@
+// Protocolos de administração. handle_protocol já conferiu
+// que quem enviou tem o papel exigido por cada um deles.
+
+use error::{ProtocolError};
+
+use protocols::{
+    ServerProtocol,
+    SessionEndReason,
+    OnlineUser,
+};
+
+use users::{Role, User, Users};
+
+use types::{Tx, ArcUsers};
+
+use crate::handle::match_protocol::utils::*;
+
+pub async fn list_online_users
+(
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let online = users.lock().await.online_users().await;
+
+    let users = online
+        .into_iter()
+        .map(|(username, sessions)| OnlineUser { username, sessions })
+        .collect();
+
+    handle_instance(tx, ServerProtocol::OnlineUsers { users }).await;
+}
+
+// Moderadores só agem sobre contas comuns; admins, sobre
+// qualquer conta.
+async fn check_target
+(
+    users: &Users,
+    current: &User,
+    username: &str,
+) -> Result<(), ProtocolError>
+{
+    let role = users.role(&current.username).await.map_err(ProtocolError::AuthenticateError)?;
+
+    if role == Role::Admin {
+        return Ok(());
+    }
+
+    let target = users.role(username).await.map_err(ProtocolError::AuthenticateError)?;
+
+    match target < role {
+        true => Ok(()),
+        false => Err(ProtocolError::PermissionDenied),
+    }
+}
+
+pub async fn kick_user
+(
+    current: User,
+    username: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let mut users = users.lock().await;
+    let username = users.canonical_username(&username);
+
+    if let Err(error) = check_target(&users, &current, &username).await {
+        drop(users);
+        handle_instance(tx, ServerProtocol::Error { error }).await;
+        return;
+    }
+
+    let result = users.kick_user(&username).await;
+    drop(users);
+
+    match result {
+        Ok(sessions) => {
+            let kicked = ServerProtocol::UserKicked {
+                username,
+                sessions: sessions.len(),
+            };
+
+            end_sessions(sessions, SessionEndReason::Kicked).await;
+            handle_instance(tx, kicked).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn ban_user
+(
+    current: User,
+    username: String,
+    reason: String,
+    until: Option<i64>,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let mut users = users.lock().await;
+    let username = users.canonical_username(&username);
+
+    if let Err(error) = check_target(&users, &current, &username).await {
+        drop(users);
+        handle_instance(tx, ServerProtocol::Error { error }).await;
+        return;
+    }
+
+    let result = users.ban_user(&username, &reason, until).await;
+    drop(users);
+
+    match result {
+        Ok(sessions) => {
+            end_sessions(sessions, SessionEndReason::Banned).await;
+            handle_instance(tx, ServerProtocol::UserBanned { username, until }).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn unban_user
+(
+    current: User,
+    username: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let username = users.canonical_username(&username);
+
+    if let Err(error) = check_target(&users, &current, &username).await {
+        drop(users);
+        handle_instance(tx, ServerProtocol::Error { error }).await;
+        return;
+    }
+
+    let result = users.unban_user(&username).await;
+    drop(users);
+
+    match result {
+        Ok(()) => {
+            handle_instance(tx, ServerProtocol::UserUnbanned { username }).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn set_role
+(
+    username: String,
+    role: Role,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let username = users.canonical_username(&username);
+    let result = users.set_role(&username, role).await;
+    drop(users);
+
+    match result {
+        Ok(()) => {
+            handle_instance(tx, ServerProtocol::RoleChanged { username, role }).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
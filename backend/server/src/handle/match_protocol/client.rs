@@ -1,122 +1,257 @@
+use std::net::IpAddr;
+
 use error::{ProtocolError};
 
+use tracing::{error};
+
 use protocols::{
     ServerProtocol,
+    HistoryMessage,
+    UnreadCount,
+    SessionEndReason,
     InternalProtocol,
-    Protocol,
 };
 
 use users::{
-    Users,
     User,
+    Users,
+    Login,
+    TokenSigner,
+    MAX_ACK_IDS,
+    unix_now,
 };
 
 use types::{Tx, TxInt, ArcUser, ArcUsers};
 
 use crate::handle::match_protocol::utils::*;
 
+use crate::handle::match_protocol::presence::announce_presence;
+
 pub async fn send_message
 (
-    from: String,
+    from: User,
     to: String,
     text: String,
     users: ArcUsers,
     tx: Tx,
 )
 {
-    // Se alguém pode enviar mensagem significa
-    // que ele já está autenticado e, portanto
-    // está no hashmap de users. (é obrigação
-    // do client garantir que o usuário esteja autenticado).
-    // Agora se "to" não está no hash, entao
-    // um erro é retornado (por enquanto).
+    // from é sempre o usuário autenticado na conexão
+    // (handle_protocol recusa SendMessage antes da
+    // autenticação), então ninguém envia mensagens
+    // em nome de outro usuário.
+    let from = from.username;
 
-    let reply = ServerProtocol::Message {
-        from: from.clone(),
-        to: to.clone(),
-        text: text.clone(),
-    };
+    let users = users.lock().await;
+    let to = users.canonical_username(&to);
+    let targets = users.get_sessions(User::new(&to)).await;
 
-    let result = reply.serialize_and(async |json| {
-        let users = users.lock().await;
+    if let Err(error) = check_receiver(&users, &to, targets.is_empty()).await {
+        drop(users);
+        handle_instance(tx, ServerProtocol::Error { error }).await;
+        return;
+    }
 
-        if let Some(target) = users.get_user(User::new(&to))
-        .await 
-        {
+    // O id e o horário da mensagem vêm do histórico, então
+    // ela é registrada antes de ser entregue.
+    let message = match users.record_message(&from, &to, &text).await {
+        Ok(message) => message,
+        Err(e) => {
             drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
 
-            try_send(target.clone(), &json).await;
-            ServerProtocol::Success
-        } else {
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    // Mesmo com o destinatário online a mensagem fica
+    // pendente até ele confirmar o recebimento, pois a
+    // sessão dele pode estar fechando.
+    if users.store_message(&message).await.is_err() {
+        error!("Erro ao tentar armazenar mensagens");
+    }
+
+    drop(users);
+
+    // A mensagem vai para todas as sessões abertas
+    // do destinatário.
+    broadcast(targets, message_protocol(message), tx).await;
+}
+
+// Confirma o recebimento das mensagens e avisa as sessões
+// abertas de quem as enviou.
+pub async fn ack_messages
+(
+    current: User,
+    ids: Vec<i64>,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    if ids.len() > MAX_ACK_IDS {
+        let err = ServerProtocol::Error {
+            error: ProtocolError::TooManyIds { max: MAX_ACK_IDS },
+        };
+
+        handle_instance(tx, err).await;
+        return;
+    }
+
+    let users = users.lock().await;
+
+    let acked = match users.ack_messages(&current.username, &ids).await {
+        Ok(acked) => acked,
+        Err(e) => {
             drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    let delivered_at = unix_now();
+    let mut receipts = Vec::new();
 
-            match Users::user_exists(&to).await {
-                Ok(result) => {
-                    if !result {
-                        let err = ServerProtocol::Error {
-                            error: ProtocolError::UserNotExist,
-                        };
+    for message in acked {
+        let targets = users.get_sessions(User::new(&message.sender)).await;
 
-                        handle_instance(tx.clone(), err).await;
-                        return ServerProtocol::Success
-                    }
-                },
-                Err(e) => {
-                    let err = ServerProtocol::Error {
-                        error: ProtocolError::AuthenticateError(e),
-                    };
+        let receipt = ServerProtocol::MessageDelivered {
+            id: message.id,
+            to: message.receiver,
+            delivered_at,
+        };
 
-                    handle_instance(tx.clone(), err).await;
-                    return ServerProtocol::Success
-                }
-            }
+        receipts.push((targets, receipt));
+    }
 
-            let result = Users
-            ::store_message(&from, &to, &text)
-            .await;
+    drop(users);
 
-            if result.is_err() {
-                eprintln!("Erro ao tentar armazenar mensagens");
-            }
+    for (targets, receipt) in receipts {
+        broadcast(targets, receipt, tx.clone()).await;
+    }
+}
 
-            ServerProtocol::Success
+// Confere se uma mensagem pode ser enviada a to. Só é
+// preciso procurar a conta quando ele não tem nenhuma
+// sessão aberta; o banimento é conferido sempre.
+async fn check_receiver
+(
+    users: &Users,
+    to: &str,
+    offline: bool,
+) -> Result<(), ProtocolError>
+{
+    if offline {
+        match users.user_exists(to).await {
+            Ok(true) => {},
+            Ok(false) => return Err(ProtocolError::UserNotExist),
+            Err(e) => return Err(ProtocolError::AuthenticateError(e)),
         }
-    }).await;
+    }
 
-    handle_result(tx, result).await;
+    match users.active_ban(to).await {
+        Ok(None) => Ok(()),
+        Ok(Some(_)) => Err(ProtocolError::UserBanned),
+        Err(e) => Err(ProtocolError::AuthenticateError(e)),
+    }
 }
 
 pub async fn request_authenticate
 (
     username: String,
     password: String,
+    ip: IpAddr,
     user: ArcUser,
     users: ArcUsers,
     tx: Tx,
     txi: TxInt,
 )
 {
-    *user.lock().await = User::new(&username);
     let mut users = users.lock().await;
 
-    match users.authenticate_user(&username, &password, tx.clone()).await {
-        Ok(()) => {
+    match users.authenticate_user(&username, &password, ip, tx.clone()).await {
+        Ok(Login::Authenticated(token)) => {
+            // token.username é a forma canônica do username
+            // digitado, que passa a identificar a conexão.
+            logged_in(token.username, token.token, token.expires_at, &mut users, user, tx, txi).await;
+        },
+        Ok(Login::TwoFactorRequired { challenge, expires_at }) => {
+            drop(users);
+
+            // A conexão continua não autenticada até o
+            // desafio ser respondido.
+            let required = ServerProtocol::TwoFactorRequired {
+                challenge,
+                expires_at,
+            };
+
+            handle_instance(tx, required).await;
+        },
+        Err(e) => {
             drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
 
-            let authenticated = ServerProtocol::Authenticated;
-            handle_instance(tx.clone(), authenticated).await;
+pub async fn authenticate_with_token
+(
+    token: String,
+    user: ArcUser,
+    users: ArcUsers,
+    tx: Tx,
+    txi: TxInt,
+)
+{
+    let mut users = users.lock().await;
 
-            // Envia um sinal para verificar as possíveis
-            // mensagens que foram armazenadas enquanto
-            // o usuário esteve offline.
-            let check_stored_messages = InternalProtocol::OfflineMessage {
-                username: username,
+    match users.authenticate_with_token(&token, tx.clone()).await {
+        Ok(username) => {
+            // O token continua o mesmo, então o client
+            // recebe de volta o que enviou.
+            let expires_at = TokenSigner::parse(&token)
+                .map(|t| t.expires_at)
+                .unwrap_or_default();
+
+            logged_in(username, token, expires_at, &mut users, user, tx, txi).await;
+        },
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
             };
 
-            if txi.send(check_stored_messages).is_err() {
-                eprintln!(
-                "Erro ao tentar enviar pelo channel; Motivo: rxi foi dropado");
-            }
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn verify_two_factor
+(
+    challenge: String,
+    code: String,
+    ip: IpAddr,
+    user: ArcUser,
+    users: ArcUsers,
+    tx: Tx,
+    txi: TxInt,
+)
+{
+    let mut users = users.lock().await;
+
+    match users.verify_two_factor(&challenge, &code, ip, tx.clone()).await {
+        Ok(token) => {
+            logged_in(token.username, token.token, token.expires_at, &mut users, user, tx, txi).await;
         },
         Err(e) => {
             drop(users);
@@ -129,15 +264,120 @@ pub async fn request_authenticate
     }
 }
 
+// Comum aos jeitos de autenticar: associa a conexão
+// ao usuário, responde com o token e as conversas não
+// lidas, pede o envio das mensagens guardadas enquanto ele
+// esteve offline e o aviso de que ele está online.
+async fn logged_in
+(
+    username: String,
+    token: String,
+    expires_at: i64,
+    users: &mut Users,
+    user: ArcUser,
+    tx: Tx,
+    txi: TxInt,
+)
+{
+    // Só depois das credenciais conferirem a conexão
+    // passa a agir em nome do usuário.
+    let previous = user.lock().await.replace(User::new(&username));
+
+    // Um login em cima de outro, com outra conta, encerra a
+    // sessão anterior como o logout faria; senão a conexão
+    // continuaria recebendo o que é da conta antiga.
+    if let Some(previous) = previous
+        && previous.username != username {
+        users.remove_session(&previous.username, &tx).await;
+        users.unwatch_presence(&tx);
+        users.unsubscribe_feed(&tx);
+        announce_presence(users, &previous.username, tx.clone()).await;
+    }
+
+    // Um erro aqui não impede o login; só as contagens
+    // ficam de fora.
+    let unread = match users.unread_counts(&username).await {
+        Ok(counts) => counts
+            .into_iter()
+            .map(|c| UnreadCount {
+                with: c.with,
+                count: c.count as usize,
+            })
+            .collect(),
+        Err(e) => {
+            error!("Erro ao tentar contar as mensagens não lidas de {username}: {e}");
+            Vec::new()
+        }
+    };
+
+    let authenticated = ServerProtocol::Authenticated {
+        username: username.clone(),
+        token,
+        expires_at,
+        unread,
+    };
+    handle_instance(tx.clone(), authenticated).await;
+
+    // Envia um sinal para verificar as possíveis
+    // mensagens que foram armazenadas enquanto
+    // o usuário esteve offline.
+    let check_stored_messages = InternalProtocol::OfflineMessage {
+        username: username.clone(),
+    };
+
+    // E outro para avisar quem acompanha a presença dele.
+    let presence_changed = InternalProtocol::PresenceChanged {
+        username,
+    };
+
+    if txi.send(check_stored_messages).is_err()
+        || txi.send(presence_changed).is_err() {
+        error!(
+        "Erro ao tentar enviar pelo channel; Motivo: rxi foi dropado");
+    }
+}
+
+pub async fn revoke_token
+(
+    current: User,
+    token: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.revoke_token(&current.username, &token).await;
+    drop(users);
+
+    match result {
+        Ok(()) => {
+            handle_instance(tx, ServerProtocol::TokenRevoked).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
 pub async fn create_user
 (
     username: String,
     password: String,
+    users: ArcUsers,
     tx: Tx,
 )
 {
-    match Users::add_user(&username, &password).await {
-        Ok(()) => {
+    let users = users.lock().await;
+    let result = users.add_user(&username, &password).await;
+    drop(users);
+
+    match result {
+        Ok(_) => {
             let added = ServerProtocol::UserCreated;
 
             handle_instance(tx, added).await;
@@ -152,3 +392,340 @@ pub async fn create_user
         }
     }    
 }
+
+pub async fn change_password
+(
+    current: User,
+    current_password: String,
+    new_password: String,
+    ip: IpAddr,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let mut users = users.lock().await;
+    let result = users
+        .change_password(&current.username, &current_password, &new_password, ip)
+        .await;
+
+    match result {
+        Ok(token) => {
+            // As outras sessões do usuário podem ter sido
+            // abertas por quem descobriu a senha antiga.
+            let others = users.remove_other_sessions(&current.username, &tx).await;
+            drop(users);
+
+            end_sessions(others, SessionEndReason::PasswordChanged).await;
+
+            let changed = ServerProtocol::PasswordChanged {
+                token: token.token,
+                expires_at: token.expires_at,
+            };
+
+            handle_instance(tx, changed).await;
+        },
+
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn reset_password
+(
+    username: String,
+    code: String,
+    new_password: String,
+    ip: IpAddr,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let mut users = users.lock().await;
+    let result = users
+        .reset_password(&username, &code, &new_password, ip)
+        .await;
+    drop(users);
+
+    match result {
+        Ok(sessions) => {
+            handle_instance(tx, ServerProtocol::PasswordReset).await;
+
+            let reason = SessionEndReason::PasswordReset;
+            end_sessions(sessions, reason).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn delete_account
+(
+    current: User,
+    password: String,
+    ip: IpAddr,
+    user: ArcUser,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let mut users = users.lock().await;
+    let result = users
+        .delete_account(&current.username, &password, ip)
+        .await;
+
+    // Com user em None a limpeza da conexão não avisa mais
+    // a saída, então ela é anunciada aqui, como no logout.
+    if result.is_ok() {
+        announce_presence(&users, &current.username, tx.clone()).await;
+    }
+
+    drop(users);
+
+    match result {
+        Ok(sessions) => {
+            *user.lock().await = None;
+
+            // Todas as sessões da conta, inclusive esta,
+            // são avisadas e fechadas.
+            end_sessions(sessions, SessionEndReason::AccountDeleted).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn logout
+(
+    current: User,
+    token: Option<String>,
+    user: ArcUser,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let mut users = users.lock().await;
+
+    if let Some(token) = token
+        && let Err(e) = users.revoke_token(&current.username, &token).await {
+        drop(users);
+        let err = ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(e),
+        };
+
+        handle_instance(tx, err).await;
+        return;
+    }
+
+    // Só esta sessão sai de on_users; a socket continua
+    // aberta e pode autenticar de novo.
+    users.remove_session(&current.username, &tx).await;
+    users.unwatch_presence(&tx);
+    users.unsubscribe_feed(&tx);
+    announce_presence(&users, &current.username, tx.clone()).await;
+    drop(users);
+
+    *user.lock().await = None;
+
+    handle_instance(tx, ServerProtocol::LoggedOut).await;
+}
+
+pub async fn enable_two_factor
+(
+    current: User,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.begin_two_factor(&current.username).await;
+    drop(users);
+
+    match result {
+        Ok(enrollment) => {
+            let enrollment = ServerProtocol::TwoFactorEnrollment {
+                secret: enrollment.secret,
+                uri: enrollment.uri,
+            };
+
+            handle_instance(tx, enrollment).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn confirm_two_factor
+(
+    current: User,
+    code: String,
+    ip: IpAddr,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.confirm_two_factor(&current.username, &code, ip).await;
+    drop(users);
+
+    match result {
+        Ok(recovery_codes) => {
+            let enabled = ServerProtocol::TwoFactorEnabled { recovery_codes };
+
+            handle_instance(tx, enabled).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn disable_two_factor
+(
+    current: User,
+    code: String,
+    ip: IpAddr,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.disable_two_factor(&current.username, &code, ip).await;
+    drop(users);
+
+    match result {
+        Ok(()) => {
+            handle_instance(tx, ServerProtocol::TwoFactorDisabled).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn request_history
+(
+    current: User,
+    with: String,
+    before: Option<i64>,
+    limit: Option<u32>,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let with = users.canonical_username(&with);
+    let result = users.history(&current.username, &with, before, limit).await;
+    drop(users);
+
+    match result {
+        Ok(page) => {
+            let messages = page.messages
+                .into_iter()
+                .map(|m| HistoryMessage {
+                    id: m.id,
+                    from: m.sender,
+                    to: m.receiver,
+                    text: m.body,
+                    sent_at: m.sent_at,
+                })
+                .collect();
+
+            let history = ServerProtocol::History {
+                with,
+                messages,
+                has_more: page.has_more,
+            };
+
+            handle_instance(tx, history).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+// O recibo de leitura só é enviado ao outro participante
+// quando o cursor de fato anda.
+pub async fn mark_read
+(
+    current: User,
+    with: String,
+    up_to: i64,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+
+    let receipt = match users.mark_read(&current.username, &with, up_to).await {
+        Ok(receipt) => receipt,
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    let targets = match receipt.advanced {
+        true => users.get_sessions(User::new(&receipt.with)).await,
+        false => Vec::new(),
+    };
+    drop(users);
+
+    let read = ServerProtocol::MessagesRead {
+        by: current.username,
+        up_to: receipt.up_to,
+        read_at: receipt.read_at,
+    };
+    broadcast(targets, read, tx.clone()).await;
+
+    let marked = ServerProtocol::MarkedRead {
+        with: receipt.with,
+        up_to: receipt.up_to,
+    };
+
+    handle_instance(tx, marked).await;
+}
@@ -0,0 +1,119 @@
+// Protocolos do feed público. Todos exigem uma conexão
+// autenticada.
+
+use error::{ProtocolError};
+
+use protocols::{
+    ServerProtocol,
+    FeedPost,
+};
+
+use users::{
+    User,
+    StoredPost,
+};
+
+use types::{Tx, ArcUsers};
+
+use crate::handle::match_protocol::utils::*;
+
+fn feed_post(post: StoredPost) -> FeedPost {
+    FeedPost {
+        id: post.id,
+        author: post.author,
+        text: post.body,
+        created_at: post.created_at,
+    }
+}
+
+pub async fn create_post
+(
+    current: User,
+    text: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+
+    let post = match users.create_post(&current.username, &text).await {
+        Ok(post) => post,
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    // Quem publicou já recebe PostCreated.
+    let targets: Vec<Tx> = users.feed_subscribers()
+        .into_iter()
+        .filter(|target| !target.same_channel(&tx))
+        .collect();
+    drop(users);
+
+    let post = feed_post(post);
+
+    let new_post = ServerProtocol::NewPost {
+        post: post.clone(),
+    };
+    broadcast(targets, new_post, tx.clone()).await;
+
+    handle_instance(tx, ServerProtocol::PostCreated { post }).await;
+}
+
+pub async fn request_feed
+(
+    before: Option<i64>,
+    limit: Option<u32>,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.feed(before, limit).await;
+    drop(users);
+
+    match result {
+        Ok(page) => {
+            let feed = ServerProtocol::Feed {
+                posts: page.posts.into_iter().map(feed_post).collect(),
+                has_more: page.has_more,
+            };
+
+            handle_instance(tx, feed).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn subscribe_feed
+(
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    users.lock().await.subscribe_feed(&tx);
+    handle_instance(tx, ServerProtocol::FeedSubscribed).await;
+}
+
+pub async fn unsubscribe_feed
+(
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    users.lock().await.unsubscribe_feed(&tx);
+    handle_instance(tx, ServerProtocol::FeedUnsubscribed).await;
+}
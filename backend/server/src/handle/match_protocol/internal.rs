@@ -1,67 +1,46 @@
-use protocols::{
-    ServerProtocol,
-    Protocol,
-};
+use tracing::{error, info};
 
-use users::{
-    Users,
-    User,
-};
-
-use types::ArcUsers;
+use types::{Tx, ArcUsers};
 
 use crate::handle::match_protocol::utils::*;
 
+// As mensagens pendentes vão apenas para a sessão que
+// acabou de autenticar (tx). Elas só deixam de ser pendentes
+// quando o client confirma o recebimento com AckMessages,
+// então uma socket que cai no meio do envio não as perde.
 pub async fn offline_message
 (
     username: String,
     users: ArcUsers,
+    tx: Tx,
 )
 {
-    let messages = Users::get_stored_messages(&username)
-        .await;
-
-    if messages.is_err() {
-        eprintln!("Erro ao tentar recuperar as mensagens armazenadas");
-        return;
-    }
-
-    let messages = messages.unwrap();
-
-    if messages.len() == 0 {
-        return;
-    }
-
-    println!("Há mensagens para você");
-
     let users = users.lock().await;
 
-    // Essa função será chamada sempre que
-    // um user ficar online, portanto é garantido
-    // que get_user vai retornar um Some()
-    let tx = users.get_user(User::new(&username)).await.unwrap();
+    let messages = users.get_stored_messages(&username)
+        .await;
+    let room_messages = users.get_stored_room_messages(&username)
+        .await;
 
     drop(users);
 
-    for (sender, message) in messages {
-        let reply = ServerProtocol::Message {
-            from: sender,
-            to: username.clone(),
-            text: message,
-        };
-
-        let result = reply.serialize_and(async |json| {
-            try_send(tx.clone(), &json).await;
-            ServerProtocol::Success
-        }).await;
+    let (Ok(messages), Ok(room_messages)) = (messages, room_messages) else {
+        error!("Erro ao tentar recuperar as mensagens armazenadas");
+        return;
+    };
 
-        handle_result(tx.clone(), result).await;
+    if messages.is_empty() && room_messages.is_empty() {
+        return;
     }
 
-    let result = Users::delete_stored_messages(&username.clone())
-        .await;
+    info!("Há mensagens para você");
+
+    let protocols = messages
+        .into_iter()
+        .map(message_protocol)
+        .chain(room_messages.into_iter().map(room_message_protocol));
 
-    if result.is_err() {
-        eprintln!("Erro ao tentar excluir mensagens armazenadas");
+    for protocol in protocols {
+        handle_instance(tx.clone(), protocol).await;
     }
 }
@@ -1,3 +1,8 @@
+pub mod admin;
 pub mod client;
+pub mod feed;
 pub mod internal;
-pub mod utils;
\ No newline at end of file
+pub mod presence;
+pub mod rooms;
+pub mod typing;
+pub mod utils;
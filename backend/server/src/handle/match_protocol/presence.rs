@@ -0,0 +1,91 @@
+// Presença dos usuários: consulta e avisos de quando eles
+// ficam online ou offline (veja /users/src/presence.rs).
+
+use error::{ProtocolError};
+
+use tracing::{error};
+
+use protocols::{
+    ServerProtocol,
+    PresenceStatus,
+    UserPresence,
+};
+
+use users::Users;
+
+use types::{Tx, ArcUsers};
+
+use crate::handle::match_protocol::utils::*;
+
+fn presence_status(online: bool) -> PresenceStatus {
+    match online {
+        true => PresenceStatus::Online,
+        false => PresenceStatus::Offline,
+    }
+}
+
+pub async fn request_presence
+(
+    usernames: Vec<String>,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.watch_presence(&tx, &usernames).await;
+    drop(users);
+
+    match result {
+        Ok(presences) => {
+            let presence = ServerProtocol::Presence {
+                users: presences
+                    .into_iter()
+                    .map(|p| UserPresence {
+                        username: p.username,
+                        status: presence_status(p.online),
+                        last_seen: p.last_seen,
+                    })
+                    .collect(),
+            };
+
+            handle_instance(tx, presence).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+// Avisa quem acompanha username se ele ficou online ou
+// offline. Precisa ser chamado depois de toda sessão
+// aberta ou fechada; se o estado dele não mudou (ex:
+// ainda há outras sessões abertas) nada é enviado.
+pub async fn announce_presence
+(
+    users: &Users,
+    username: &str,
+    tx: Tx,
+)
+{
+    let change = match users.update_presence(username).await {
+        Ok(Some(change)) => change,
+        Ok(None) => return,
+        Err(e) => {
+            error!("Erro ao tentar atualizar a presença de {username}: {e}");
+            return;
+        }
+    };
+
+    let changed = ServerProtocol::PresenceChanged {
+        username: change.presence.username,
+        status: presence_status(change.presence.online),
+        last_seen: change.presence.last_seen,
+    };
+
+    broadcast(change.watchers, changed, tx).await;
+}
@@ -0,0 +1,226 @@
+// Protocolos das salas de conversa em grupo. Todos exigem
+// uma conexão autenticada; current é o usuário dela.
+
+use error::{ProtocolError};
+
+use protocols::{
+    ServerProtocol,
+    RoomInfo,
+};
+
+use users::{User, MAX_ACK_IDS};
+
+use types::{Tx, ArcUsers};
+
+use crate::handle::match_protocol::utils::*;
+
+pub async fn create_room
+(
+    current: User,
+    name: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let result = users.create_room(&current.username, &name).await;
+    drop(users);
+
+    match result {
+        Ok(room) => {
+            handle_instance(tx, ServerProtocol::RoomCreated { room }).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+pub async fn join_room
+(
+    current: User,
+    name: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+
+    let join = match users.join_room(&current.username, &name).await {
+        Ok(join) => join,
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    // Quem já participava não é anunciado de novo.
+    let others: Vec<String> = match join.joined {
+        true => join.members
+            .iter()
+            .filter(|m| **m != current.username)
+            .cloned()
+            .collect(),
+        false => Vec::new(),
+    };
+
+    let targets = users.get_sessions_of(&others).await;
+    drop(users);
+
+    let joined = ServerProtocol::MemberJoined {
+        room: join.room.clone(),
+        username: current.username,
+    };
+
+    broadcast(targets, joined, tx.clone()).await;
+
+    let reply = ServerProtocol::RoomJoined {
+        room: join.room,
+        members: join.members,
+    };
+
+    handle_instance(tx, reply).await;
+}
+
+pub async fn leave_room
+(
+    current: User,
+    name: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+
+    let (room, members) = match users.leave_room(&current.username, &name).await {
+        Ok(left) => left,
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    let targets = users.get_sessions_of(&members).await;
+    drop(users);
+
+    let left = ServerProtocol::MemberLeft {
+        room: room.clone(),
+        username: current.username,
+    };
+
+    broadcast(targets, left, tx.clone()).await;
+    handle_instance(tx, ServerProtocol::RoomLeft { room }).await;
+}
+
+pub async fn list_rooms
+(
+    current: User,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let result = users.lock().await
+        .list_rooms(&current.username)
+        .await;
+
+    match result {
+        Ok(rooms) => {
+            let rooms = rooms
+                .into_iter()
+                .map(|r| RoomInfo {
+                    name: r.name,
+                    members: r.members as usize,
+                    joined: r.joined,
+                })
+                .collect();
+
+            handle_instance(tx, ServerProtocol::Rooms { rooms }).await;
+        },
+
+        Err(e) => {
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+        }
+    }
+}
+
+// A mensagem vai para as sessões abertas dos outros
+// membros e fica pendente para todos eles, como as
+// mensagens diretas.
+pub async fn send_room_message
+(
+    current: User,
+    room: String,
+    text: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+
+    let (message, members) = match users.send_room_message(&current.username, &room, &text).await {
+        Ok(sent) => sent,
+        Err(e) => {
+            drop(users);
+            let err = ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(e),
+            };
+
+            handle_instance(tx, err).await;
+            return;
+        }
+    };
+
+    let targets = users.get_sessions_of(&members).await;
+    drop(users);
+
+    broadcast(targets, room_message_protocol(message), tx).await;
+}
+
+pub async fn ack_room_messages
+(
+    current: User,
+    ids: Vec<i64>,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    if ids.len() > MAX_ACK_IDS {
+        let err = ServerProtocol::Error {
+            error: ProtocolError::TooManyIds { max: MAX_ACK_IDS },
+        };
+
+        handle_instance(tx, err).await;
+        return;
+    }
+
+    let result = users.lock().await
+        .ack_room_messages(&current.username, &ids)
+        .await;
+
+    if let Err(e) = result {
+        let err = ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(e),
+        };
+
+        handle_instance(tx, err).await;
+    }
+}
@@ -0,0 +1,75 @@
+// Indicadores de "digitando" (veja /users/src/typing.rs).
+
+use tokio::time::{sleep_until, Instant};
+
+use protocols::ServerProtocol;
+
+use users::{
+    User,
+    Users,
+    Typing,
+};
+
+use types::{Tx, ArcUsers};
+
+use crate::handle::match_protocol::utils::*;
+
+pub async fn typing
+(
+    current: User,
+    to: String,
+    users: ArcUsers,
+    tx: Tx,
+)
+{
+    let users = users.lock().await;
+    let to = users.canonical_username(&to);
+
+    if to == current.username {
+        return;
+    }
+
+    // Destinatário offline (ou inexistente): não há quem
+    // avisar e nada é guardado.
+    let targets = users.get_sessions(User::new(&to)).await;
+
+    if targets.is_empty() {
+        return;
+    }
+
+    let typing = users.typing(&current.username, &to);
+    let timer_users = users.clone();
+    drop(users);
+
+    if typing == Typing::Throttled {
+        return;
+    }
+
+    let notice = ServerProtocol::Typing {
+        from: current.username.clone(),
+    };
+    broadcast(targets, notice, tx.clone()).await;
+
+    // Um único timer por indicador ativo, que espera até
+    // ele expirar.
+    if typing == Typing::Started {
+        tokio::spawn(stop_typing(timer_users, current.username, to, tx));
+    }
+}
+
+async fn stop_typing
+(
+    users: Users,
+    from: String,
+    to: String,
+    tx: Tx,
+)
+{
+    // Cada Typing novo empurra o prazo para frente.
+    while let Some(deadline) = users.typing_deadline(&from, &to) {
+        sleep_until(Instant::from_std(deadline)).await;
+    }
+
+    let targets = users.get_sessions(User::new(&to)).await;
+    broadcast(targets, ServerProtocol::StoppedTyping { from }, tx).await;
+}
@@ -1,12 +1,46 @@
 use error::{ProtocolError};
 
+use tracing::{error};
+
 use protocols::{
     ServerProtocol,
+    SessionEndReason,
     Protocol,
 };
 
+use axum::extract::ws::Message;
+
+use users::{
+    StoredMessage,
+    StoredRoomMessage,
+};
+
 use types::Tx;
 
+// ServerProtocol::Message com o id e o horário com que
+// a mensagem foi registrada no histórico.
+pub fn message_protocol(message: StoredMessage) -> ServerProtocol {
+    ServerProtocol::Message {
+        id: message.id,
+        from: message.sender,
+        to: message.receiver,
+        text: message.body,
+        sent_at: message.sent_at,
+    }
+}
+
+// ServerProtocol::RoomMessage correspondente à mensagem
+// registrada.
+pub fn room_message_protocol(message: StoredRoomMessage) -> ServerProtocol {
+    ServerProtocol::RoomMessage {
+        id: message.id,
+        room: message.room,
+        from: message.sender,
+        text: message.body,
+        sent_at: message.sent_at,
+    }
+}
+
 // Lida com cada tipo de ServerProtocol criado
 // dentro de handle_protocol();
 pub async fn handle_instance
@@ -54,11 +88,32 @@ pub async fn handle_result
         // o servidor está online, é inteligente verificar
         // a stderr do server.
         if result.is_err() {
-            eprintln!("Erro de serialização");
+            error!("Erro de serialização");
         }
     }
 }
 
+// Envia instance a todas as sessões em targets,
+// serializando uma única vez. Um erro de serialização
+// vai para tx, a sessão que causou o envio.
+pub async fn broadcast
+(
+    targets: Vec<Tx>,
+    instance: ServerProtocol,
+    tx: Tx,
+)
+{
+    let result = instance.serialize_and(async |json| {
+        for target in targets {
+            try_send(target, &json).await;
+        }
+
+        ServerProtocol::Success
+    }).await;
+
+    handle_result(tx, result).await;
+}
+
 // Tenta enviar to_send pela socket
 pub async fn try_send
 (
@@ -67,7 +122,28 @@ pub async fn try_send
 ) 
 {
     if tx.send(to_send.into()).is_err() {
-        eprintln!(
+        error!(
         "Erro ao tentar enviar pelo channel; Motivo: rx foi dropado");
     }
-}
\ No newline at end of file
+}
+
+// Avisa cada sessão de sessions do motivo pelo qual ela
+// foi encerrada e fecha a socket dela. As sessões já
+// precisam ter sido removidas de on_users.
+pub async fn end_sessions
+(
+    sessions: Vec<Tx>,
+    reason: SessionEndReason,
+)
+{
+    for tx in sessions {
+        let notice = ServerProtocol::SessionEnded {
+            reason: reason.clone(),
+        };
+
+        handle_instance(tx.clone(), notice).await;
+
+        // Se rx já foi dropado a socket já está fechando.
+        let _ = tx.send(Message::Close(None));
+    }
+}
@@ -1,3 +1,31 @@
+pub mod config;
 pub mod handle;
+pub mod state;
 
 pub use handle::*;
+
+use axum::{
+    Router,
+    routing::any,
+};
+
+use users::{
+    Users,
+};
+
+use crate::{
+    config::Config,
+    handle::handle_connections::handler,
+    state::AppState,
+};
+
+// Cria a estrutura do server. Fica separada de main
+// para que os testes de integração possam subir
+// exatamente o mesmo Router.
+pub fn app(users: Users, config: &Config) -> Router {
+    let state = AppState::new(users, config.limits.clone());
+
+    Router::new()
+        .route(&config.server.ws_path, any(handler))
+        .with_state(state)
+}
@@ -1,18 +1,15 @@
 use std::{
     net::SocketAddr,
+    process::ExitCode,
 };
 
-use tokio::{
-    io::Result,
-};
+use clap::Parser;
 
-use axum::{
-    Router,
-    routing::any,
-};
+use tracing::{info, warn, error};
 
 use server::{
-    handle::handle_connections::handler,
+    app,
+    config::{Cli, Config},
 };
 
 use users::{
@@ -20,20 +17,86 @@ use users::{
 };
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let users = Users::new();
+async fn main() -> ExitCode {
+    // Erros de configuração são mostrados de forma clara
+    // e o servidor encerra, sem panic.
+    let config = match Config::load(&Cli::parse()) {
+        Ok(config) => config,
+        Err(e) => {
+            eprintln!("Erro de configuração: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    match run(config).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn run(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    // validate() já garantiu que os valores abaixo existem
+    // e são válidos.
+    tracing_subscriber::fmt()
+        .with_max_level(config.log_level()?)
+        .init();
+
+    let db_url = config.database_url()?;
+    let addr = config.bind_addr()?;
+
+    // O pool de conexões com a database é criado uma única vez
+    // aqui e compartilhado por todas as conexões via state.
+    // Com memory:// (ou --demo) nada é salvo em database.
+    let users = Users::connect_with(db_url, config.database.pool())
+        .await
+        .map_err(|e| format!("Erro ao tentar conectar na database: {e}"))?
+        .with_token_signer(config.auth.token_signer())
+        .with_login_throttle(config.auth.throttle())
+        .with_two_factor(config.auth.two_factor())
+        .with_typing(config.limits.typing())
+        .with_validation_policy(config.accounts.policy()?)
+        .with_password_hashing(config.hashing.hashing()?);
+
+    if config.auth.token_secret.is_none() {
+        warn!("auth.token_secret não definido: tokens de sessão deixam de valer quando o servidor reinicia");
+    }
+
+    if db_url.starts_with("memory:") {
+        info!("Modo demo: nada será salvo em database");
+    }
+
+    // Com --migrate (ou RUN_MIGRATIONS=1) as migrations
+    // pendentes são aplicadas antes do servidor subir.
+    if config.database.run_migrations {
+        let applied = users.migrate()
+            .await
+            .map_err(|e| format!("Erro ao tentar aplicar as migrations: {e}"))?;
+
+        for version in applied {
+            info!("Migration {version} aplicada");
+        }
+
+        let conflicts = users.username_conflicts()
+            .await
+            .map_err(|e| format!("Erro ao tentar ler os conflitos de username: {e}"))?;
+
+        for c in conflicts {
+            warn!("username {:?} não está na forma canônica ({}): renomeie a conta", c.username, c.reason);
+        }
+    }
 
     // cria a estrutura do server
-    let app = Router::new()
-        .route("/ws", any(handler))
-        .with_state(users);
+    let app = app(users, &config);
 
     // ouve via tcp no endereço dado
-    let addr = SocketAddr::from(([0, 0, 0, 0], 3000));
     let listener = tokio::net::TcpListener::bind(addr)
-        .await?;
+        .await
+        .map_err(|e| format!("Erro ao tentar escutar em {addr}: {e}"))?;
 
-    println!("Server rodando em ws::/{addr}");
+    info!("Server rodando em ws://{addr}{}", config.server.ws_path);
 
     // cria efetivamente o servidor web
     axum::serve(listener,
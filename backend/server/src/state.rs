@@ -0,0 +1,58 @@
+/*
+Estado compartilhado por todas as conexões, entregue
+pelo axum ao handler da websocket.
+*/
+
+use std::sync::{
+    Arc,
+    atomic::{AtomicUsize, Ordering},
+};
+
+use users::Users;
+
+use crate::config::LimitsConfig;
+
+#[derive(Clone)]
+pub struct AppState {
+    pub users: Users,
+    pub limits: LimitsConfig,
+    connections: Arc<AtomicUsize>,
+}
+
+// Vaga ocupada por uma websocket aberta. A vaga é
+// liberada quando o guard é dropado.
+pub struct ConnectionGuard {
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.connections.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl AppState {
+    pub fn new(users: Users, limits: LimitsConfig) -> Self {
+        Self {
+            users,
+            limits,
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    // Ocupa uma vaga de conexão, ou retorna None se
+    // limits.max_connections já foi atingido (0 = sem limite).
+    pub fn try_connect(&self) -> Option<ConnectionGuard> {
+        let max = self.limits.max_connections;
+
+        self.connections
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| {
+                (max == 0 || n < max).then_some(n + 1)
+            })
+            .ok()?;
+
+        Some(ConnectionGuard {
+            connections: Arc::clone(&self.connections),
+        })
+    }
+}
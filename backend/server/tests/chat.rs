@@ -0,0 +1,1871 @@
+/*
+Testes de integração: sobem o mesmo Router de main
+com Users::in_memory() e conversam com ele por uma
+WebSocket de verdade.
+*/
+
+use std::{
+    net::SocketAddr,
+    time::Duration,
+};
+
+use futures_util::{
+    sink::SinkExt,
+    stream::StreamExt,
+};
+
+use tokio::{
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+
+use tokio_tungstenite::{
+    connect_async,
+    MaybeTlsStream,
+    WebSocketStream,
+    tungstenite::Message,
+};
+
+use protocols::{
+    ClientProtocol,
+    ServerProtocol,
+    SessionEndReason,
+    HistoryMessage,
+    RoomInfo,
+    PresenceStatus,
+    UserPresence,
+    FeedPost,
+    UnreadCount,
+};
+
+use error::{
+    AuthenticateErrorType,
+    ProtocolError,
+};
+
+use server::config::Config;
+
+use users::{
+    Users,
+    User,
+    ThrottleConfig,
+    HashConfig,
+    PasswordHashing,
+    Totp,
+    Role,
+    TypingConfig,
+    MAX_ACK_IDS,
+    unix_now,
+};
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+// Users em memória com um argon2 barato, para que os
+// testes não passem a maior parte do tempo fazendo hash.
+fn users() -> Users {
+    let hashing = PasswordHashing::new(HashConfig {
+        memory_cost: 64,
+        time_cost: 1,
+        parallelism: 1,
+        pepper: None,
+    })
+    .unwrap();
+
+    Users::in_memory().with_password_hashing(hashing)
+}
+
+async fn spawn_server() -> SocketAddr {
+    spawn_server_with(users()).await
+}
+
+async fn spawn_server_with(users: Users) -> SocketAddr {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let app = server::app(users, &Config::default());
+
+    tokio::spawn(async move {
+        axum::serve(listener,
+            app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .unwrap();
+    });
+
+    addr
+}
+
+async fn connect(addr: SocketAddr) -> Socket {
+    let (socket, _) = connect_async(format!("ws://{addr}/ws")).await.unwrap();
+    socket
+}
+
+async fn send(socket: &mut Socket, protocol: ClientProtocol) {
+    let json = serde_json::to_string(&protocol).unwrap();
+    socket.send(Message::Text(json.into())).await.unwrap();
+}
+
+async fn recv(socket: &mut Socket) -> ServerProtocol {
+    loop {
+        let msg = timeout(Duration::from_secs(5), socket.next())
+            .await
+            .expect("o servidor não respondeu a tempo")
+            .unwrap()
+            .unwrap();
+
+        if let Message::Text(json) = msg {
+            return serde_json::from_str(&json).unwrap();
+        }
+    }
+}
+
+// Cria o usuário e autentica com ele na mesma socket.
+async fn login(addr: SocketAddr, username: &str, password: &str) -> Socket {
+    login_with_token(addr, username, password).await.0
+}
+
+// Como login, mas também retorna o token de sessão recebido.
+async fn login_with_token(addr: SocketAddr, username: &str, password: &str) -> (Socket, String) {
+    let mut socket = connect(addr).await;
+
+    send(&mut socket, ClientProtocol::CreateUser {
+        username: username.into(),
+        password: password.into(),
+    }).await;
+    assert!(matches!(recv(&mut socket).await, ServerProtocol::UserCreated));
+
+    send(&mut socket, ClientProtocol::RequestAuthenticate {
+        username: username.into(),
+        password: password.into(),
+    }).await;
+    match recv(&mut socket).await {
+        ServerProtocol::Authenticated { token, .. } => (socket, token),
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn wrong_password_is_rejected() {
+    let addr = spawn_server().await;
+    let mut socket = login(addr, "alice", "senha-1234").await;
+    socket.close(None).await.unwrap();
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::RequestAuthenticate {
+        username: "alice".into(),
+        password: "errada".into(),
+    }).await;
+
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::PasswordMismatch),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn duplicated_user_is_rejected() {
+    let addr = spawn_server().await;
+    let mut socket = login(addr, "alice", "senha-1234").await;
+
+    send(&mut socket, ClientProtocol::CreateUser {
+        username: "alice".into(),
+        password: "outra-senha-1".into(),
+    }).await;
+
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::UserAlreadyExists),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn message_is_delivered_to_online_user() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "oi bob".into(),
+    }).await;
+
+    match recv(&mut bob).await {
+        ServerProtocol::Message { from, to, text, .. } => {
+            assert_eq!(from, "alice");
+            assert_eq!(to, "bob");
+            assert_eq!(text, "oi bob");
+        },
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn offline_message_is_delivered_on_login() {
+    let addr = spawn_server().await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    bob.close(None).await.unwrap();
+
+    // Dá tempo do servidor processar o fechamento
+    // da socket do bob e removê-lo de on_users.
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut alice = login(addr, "alice", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "guardada".into(),
+    }).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut bob = connect(addr).await;
+    send(&mut bob, ClientProtocol::RequestAuthenticate {
+        username: "bob".into(),
+        password: "senha-1234".into(),
+    }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::Authenticated { .. }));
+
+    match recv(&mut bob).await {
+        ServerProtocol::Message { from, text, .. } => {
+            assert_eq!(from, "alice");
+            assert_eq!(text, "guardada");
+        },
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn message_to_unknown_user_is_rejected() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "ninguem".into(),
+        text: "oi?".into(),
+    }).await;
+
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error { error: ProtocolError::UserNotExist }
+    ));
+}
+
+#[tokio::test]
+async fn unauthenticated_socket_cannot_send_messages() {
+    let addr = spawn_server().await;
+    let _bob = login(addr, "bob", "senha-1234").await;
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "sou a alice, confia".into(),
+    }).await;
+
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error { error: ProtocolError::NotAuthenticated }
+    ));
+}
+
+#[tokio::test]
+async fn sender_is_taken_from_the_connection() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    // Um "from" forjado no json é simplesmente ignorado.
+    let forged = r#"{"type":"send_message","from":"carol","to":"bob","text":"oi"}"#;
+    alice.send(Message::Text(forged.into())).await.unwrap();
+
+    match recv(&mut bob).await {
+        ServerProtocol::Message { from, .. } => assert_eq!(from, "alice"),
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn token_reconnects_without_password() {
+    let addr = spawn_server().await;
+    let (mut alice, token) = login_with_token(addr, "alice", "senha-1234").await;
+    alice.close(None).await.unwrap();
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::AuthenticateWithToken {
+        token: token.clone(),
+    }).await;
+
+    match recv(&mut socket).await {
+        ServerProtocol::Authenticated { username, token: same, .. } => {
+            assert_eq!(username, "alice");
+            assert_eq!(same, token);
+        },
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn tampered_token_is_rejected() {
+    let addr = spawn_server().await;
+    let (_alice, token) = login_with_token(addr, "alice", "senha-1234").await;
+
+    // Troca a data de expiração sem refazer a assinatura.
+    let mut parts: Vec<&str> = token.split('.').collect();
+    let later = (parts[1].parse::<i64>().unwrap() + 1000).to_string();
+    parts[1] = &later;
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::AuthenticateWithToken {
+        token: parts.join("."),
+    }).await;
+
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidToken),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn revoked_token_is_rejected() {
+    let addr = spawn_server().await;
+    let (mut alice, token) = login_with_token(addr, "alice", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::RevokeToken {
+        token: token.clone(),
+    }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::TokenRevoked));
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::AuthenticateWithToken { token }).await;
+
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidToken),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn every_session_receives_messages() {
+    let addr = spawn_server().await;
+    let (mut phone, token) = login_with_token(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    let mut laptop = connect(addr).await;
+    send(&mut laptop, ClientProtocol::AuthenticateWithToken { token }).await;
+    assert!(matches!(recv(&mut laptop).await, ServerProtocol::Authenticated { .. }));
+
+    send(&mut bob, ClientProtocol::SendMessage {
+        to: "alice".into(),
+        text: "primeira".into(),
+    }).await;
+
+    for socket in [&mut phone, &mut laptop] {
+        match recv(socket).await {
+            ServerProtocol::Message { text, .. } => assert_eq!(text, "primeira"),
+            other => panic!("esperava uma mensagem, veio {other:?}"),
+        }
+    }
+
+    // Fechar uma das sessões não desconecta a outra.
+    phone.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    send(&mut bob, ClientProtocol::SendMessage {
+        to: "alice".into(),
+        text: "segunda".into(),
+    }).await;
+
+    match recv(&mut laptop).await {
+        ServerProtocol::Message { text, .. } => assert_eq!(text, "segunda"),
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    }
+}
+
+async fn authenticate(socket: &mut Socket, username: &str, password: &str) -> ServerProtocol {
+    send(socket, ClientProtocol::RequestAuthenticate {
+        username: username.into(),
+        password: password.into(),
+    }).await;
+
+    recv(socket).await
+}
+
+#[tokio::test]
+async fn account_is_locked_after_too_many_failures() {
+    let users = users().with_login_throttle(ThrottleConfig {
+        max_failures_per_account: 2,
+        max_failures_per_ip: 0,
+        lockout: Duration::from_secs(60),
+        max_lockout: Duration::from_secs(600),
+    });
+
+    let addr = spawn_server_with(users).await;
+    let mut socket = login(addr, "alice", "senha-1234").await;
+
+    for _ in 0..2 {
+        assert!(matches!(
+            authenticate(&mut socket, "alice", "errada").await,
+            ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(AuthenticateErrorType::PasswordMismatch),
+            }
+        ));
+    }
+
+    // Nem a senha certa é aceita durante o bloqueio.
+    match authenticate(&mut socket, "alice", "senha-1234").await {
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(
+                AuthenticateErrorType::TooManyAttempts { retry_after }
+            ),
+        } => assert!(retry_after > 0 && retry_after <= 60),
+        other => panic!("esperava TooManyAttempts, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn ip_is_locked_after_too_many_failures() {
+    let users = users().with_login_throttle(ThrottleConfig {
+        max_failures_per_account: 0,
+        max_failures_per_ip: 2,
+        lockout: Duration::from_secs(60),
+        max_lockout: Duration::from_secs(600),
+    });
+
+    let addr = spawn_server_with(users).await;
+    let mut socket = connect(addr).await;
+
+    for username in ["alice", "bob"] {
+        assert!(matches!(
+            authenticate(&mut socket, username, "senha-1234").await,
+            ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(AuthenticateErrorType::UserNotFound),
+            }
+        ));
+    }
+
+    assert!(matches!(
+        authenticate(&mut socket, "carol", "senha-1234").await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::TooManyAttempts { .. }),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn password_change_requires_current_password() {
+    let addr = spawn_server().await;
+    let (mut alice, old_token) = login_with_token(addr, "alice", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::ChangePassword {
+        current_password: "errada".into(),
+        new_password: "nova-5678".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::PasswordMismatch),
+        }
+    ));
+
+    send(&mut alice, ClientProtocol::ChangePassword {
+        current_password: "senha-1234".into(),
+        new_password: "nova-5678".into(),
+    }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::PasswordChanged { .. }));
+
+    // A senha antiga e os tokens emitidos antes da troca
+    // deixam de valer.
+    let mut socket = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut socket, "alice", "senha-1234").await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::PasswordMismatch),
+        }
+    ));
+    assert!(matches!(
+        authenticate(&mut socket, "alice", "nova-5678").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::AuthenticateWithToken { token: old_token }).await;
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidToken),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn reset_code_works_only_once() {
+    let users = users();
+    let addr = spawn_server_with(users.clone()).await;
+    let _alice = login(addr, "alice", "senha-1234").await;
+
+    let code = users.issue_reset_code("alice", Duration::from_secs(60)).await.unwrap();
+
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::ResetPassword {
+        username: "alice".into(),
+        code: code.clone(),
+        new_password: "nova-5678".into(),
+    }).await;
+    assert!(matches!(recv(&mut socket).await, ServerProtocol::PasswordReset));
+
+    send(&mut socket, ClientProtocol::ResetPassword {
+        username: "alice".into(),
+        code,
+        new_password: "outra-9999".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidResetCode),
+        }
+    ));
+
+    assert!(matches!(
+        authenticate(&mut socket, "alice", "nova-5678").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+}
+
+#[tokio::test]
+async fn deleted_account_loses_sessions_and_messages() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    bob.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "guardada".into(),
+    }).await;
+
+    send(&mut alice, ClientProtocol::DeleteAccount { password: "senha-1234".into() }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::SessionEnded { reason: SessionEndReason::AccountDeleted }
+    ));
+
+    // O servidor fecha a socket logo depois do aviso.
+    let next = timeout(Duration::from_secs(5), alice.next()).await.unwrap();
+    assert!(matches!(next, None | Some(Ok(Message::Close(_))) | Some(Err(_))));
+
+    // A mensagem guardada para o bob some junto com a conta.
+    let mut bob = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut bob, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+    assert!(timeout(Duration::from_millis(300), bob.next()).await.is_err());
+
+    let mut socket = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut socket, "alice", "senha-1234").await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::UserNotFound),
+        }
+    ));
+}
+
+async fn create_user(socket: &mut Socket, username: &str, password: &str) -> ServerProtocol {
+    send(socket, ClientProtocol::CreateUser {
+        username: username.into(),
+        password: password.into(),
+    }).await;
+
+    recv(socket).await
+}
+
+#[tokio::test]
+async fn invalid_usernames_are_rejected() {
+    let addr = spawn_server().await;
+    let mut socket = connect(addr).await;
+
+    for (username, expected) in [
+        ("al", AuthenticateErrorType::UsernameTooShort { min: 3 }),
+        (&"a".repeat(33), AuthenticateErrorType::UsernameTooLong { max: 32 }),
+        ("ali ce", AuthenticateErrorType::UsernameInvalidCharacters),
+        ("..alice", AuthenticateErrorType::UsernameInvalidCharacters),
+        ("alice\u{7}", AuthenticateErrorType::UsernameInvalidCharacters),
+        ("Admin", AuthenticateErrorType::UsernameReserved),
+    ] {
+        match create_user(&mut socket, username, "senha-1234").await {
+            ServerProtocol::Error { error: ProtocolError::AuthenticateError(e) } => {
+                assert_eq!(format!("{e:?}"), format!("{expected:?}"), "username {username:?}");
+            },
+            other => panic!("esperava um erro para {username:?}, veio {other:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn weak_passwords_are_rejected() {
+    let addr = spawn_server().await;
+    let mut socket = connect(addr).await;
+
+    for (password, expected) in [
+        ("curta-1", AuthenticateErrorType::PasswordTooShort { min: 8 }),
+        ("somenteletras", AuthenticateErrorType::PasswordTooWeak { min_classes: 2 }),
+        ("alice-1234", AuthenticateErrorType::PasswordContainsUsername),
+    ] {
+        match create_user(&mut socket, "alice", password).await {
+            ServerProtocol::Error { error: ProtocolError::AuthenticateError(e) } => {
+                assert_eq!(format!("{e:?}"), format!("{expected:?}"), "senha {password:?}");
+            },
+            other => panic!("esperava um erro para {password:?}, veio {other:?}"),
+        }
+    }
+}
+
+#[tokio::test]
+async fn usernames_are_case_insensitive() {
+    let addr = spawn_server().await;
+    let _alice = login(addr, "Alice", "senha-1234").await;
+
+    let mut socket = connect(addr).await;
+    assert!(matches!(
+        create_user(&mut socket, "ALICE", "senha-1234").await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::UserAlreadyExists),
+        }
+    ));
+
+    // Forma de largura total (ＡＬＩＣＥ) vira "alice" pela NFKC.
+    match authenticate(&mut socket, "\u{ff21}\u{ff2c}\u{ff29}\u{ff23}\u{ff25}", "senha-1234").await {
+        ServerProtocol::Authenticated { username, .. } => assert_eq!(username, "alice"),
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn logout_keeps_the_socket_usable() {
+    let addr = spawn_server().await;
+    let _bob = login(addr, "bob", "senha-1234").await;
+    let (mut socket, token) = login_with_token(addr, "alice", "senha-1234").await;
+
+    send(&mut socket, ClientProtocol::Logout { token: Some(token.clone()) }).await;
+    assert!(matches!(recv(&mut socket).await, ServerProtocol::LoggedOut));
+
+    send(&mut socket, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "oi".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error { error: ProtocolError::NotAuthenticated }
+    ));
+
+    // O token dado no logout foi revogado...
+    send(&mut socket, ClientProtocol::AuthenticateWithToken { token }).await;
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidToken),
+        }
+    ));
+
+    // ...mas a mesma socket pode entrar com outra conta.
+    match authenticate(&mut socket, "bob", "senha-1234").await {
+        ServerProtocol::Authenticated { username, .. } => assert_eq!(username, "bob"),
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn password_change_ends_other_sessions() {
+    let addr = spawn_server().await;
+    let (mut phone, token) = login_with_token(addr, "alice", "senha-1234").await;
+
+    let mut laptop = connect(addr).await;
+    send(&mut laptop, ClientProtocol::AuthenticateWithToken { token }).await;
+    assert!(matches!(recv(&mut laptop).await, ServerProtocol::Authenticated { .. }));
+
+    send(&mut phone, ClientProtocol::ChangePassword {
+        current_password: "senha-1234".into(),
+        new_password: "nova-5678".into(),
+    }).await;
+    assert!(matches!(recv(&mut phone).await, ServerProtocol::PasswordChanged { .. }));
+
+    assert!(matches!(
+        recv(&mut laptop).await,
+        ServerProtocol::SessionEnded { reason: SessionEndReason::PasswordChanged }
+    ));
+
+    let next = timeout(Duration::from_secs(5), laptop.next()).await.unwrap();
+    assert!(matches!(next, None | Some(Ok(Message::Close(_))) | Some(Err(_))));
+
+    // A sessão que trocou a senha continua recebendo mensagens.
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    send(&mut bob, ClientProtocol::SendMessage {
+        to: "alice".into(),
+        text: "ainda aí?".into(),
+    }).await;
+
+    match recv(&mut phone).await {
+        ServerProtocol::Message { text, .. } => assert_eq!(text, "ainda aí?"),
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    }
+}
+
+// Começa um login com senha numa conta com 2FA, retornando
+// a socket e o desafio recebido.
+async fn two_factor_challenge(addr: SocketAddr, username: &str, password: &str) -> (Socket, String) {
+    let mut socket = connect(addr).await;
+
+    match authenticate(&mut socket, username, password).await {
+        ServerProtocol::TwoFactorRequired { challenge, .. } => (socket, challenge),
+        other => panic!("esperava TwoFactorRequired, veio {other:?}"),
+    }
+}
+
+async fn verify_two_factor(socket: &mut Socket, challenge: &str, code: &str) -> ServerProtocol {
+    send(socket, ClientProtocol::VerifyTwoFactor {
+        challenge: challenge.into(),
+        code: code.into(),
+    }).await;
+
+    recv(socket).await
+}
+
+fn is_invalid_code(reply: &ServerProtocol) -> bool {
+    matches!(reply, ServerProtocol::Error {
+        error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidTwoFactorCode),
+    })
+}
+
+// Ativa o 2FA para o usuário autenticado em socket,
+// retornando o segredo e os códigos de recuperação.
+async fn enable_two_factor(socket: &mut Socket) -> (Totp, Vec<String>) {
+    send(socket, ClientProtocol::EnableTwoFactor).await;
+    let totp = match recv(socket).await {
+        ServerProtocol::TwoFactorEnrollment { secret, uri } => {
+            assert!(uri.starts_with("otpauth://totp/"));
+            Totp::from_base32(&secret).unwrap()
+        },
+        other => panic!("esperava TwoFactorEnrollment, veio {other:?}"),
+    };
+
+    send(socket, ClientProtocol::ConfirmTwoFactor { code: totp.code(unix_now()) }).await;
+    match recv(socket).await {
+        ServerProtocol::TwoFactorEnabled { recovery_codes } => (totp, recovery_codes),
+        other => panic!("esperava TwoFactorEnabled, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn two_factor_is_required_after_enrollment() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+
+    let (totp, recovery_codes) = enable_two_factor(&mut alice).await;
+    assert_eq!(recovery_codes.len(), 10);
+
+    let (mut socket, challenge) = two_factor_challenge(addr, "alice", "senha-1234").await;
+
+    // Até responder o desafio a conexão não está autenticada.
+    send(&mut socket, ClientProtocol::SendMessage {
+        to: "alice".into(),
+        text: "oi".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error { error: ProtocolError::NotAuthenticated }
+    ));
+
+    assert!(is_invalid_code(&verify_two_factor(&mut socket, &challenge, "zzzzz-zzzzz").await));
+
+    // O código usado na confirmação já foi gasto, então
+    // o do passo seguinte é usado aqui.
+    let code = totp.code(unix_now() + 30);
+
+    match verify_two_factor(&mut socket, &challenge, &code).await {
+        ServerProtocol::Authenticated { username, .. } => assert_eq!(username, "alice"),
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+
+    // O mesmo código não vale duas vezes.
+    let (mut socket, challenge) = two_factor_challenge(addr, "alice", "senha-1234").await;
+    assert!(is_invalid_code(&verify_two_factor(&mut socket, &challenge, &code).await));
+}
+
+#[tokio::test]
+async fn recovery_codes_work_once_and_can_disable_two_factor() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let (_, recovery_codes) = enable_two_factor(&mut alice).await;
+
+    let (mut socket, challenge) = two_factor_challenge(addr, "alice", "senha-1234").await;
+    assert!(matches!(
+        verify_two_factor(&mut socket, &challenge, &recovery_codes[0]).await,
+        ServerProtocol::Authenticated { .. }
+    ));
+
+    let (mut socket, challenge) = two_factor_challenge(addr, "alice", "senha-1234").await;
+    assert!(is_invalid_code(&verify_two_factor(&mut socket, &challenge, &recovery_codes[0]).await));
+
+    send(&mut alice, ClientProtocol::DisableTwoFactor { code: recovery_codes[1].clone() }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::TwoFactorDisabled));
+
+    let mut socket = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut socket, "alice", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+}
+
+// Servidor com alice já cadastrada como admin.
+async fn spawn_server_with_admin() -> SocketAddr {
+    let users = users();
+    users.add_user("alice", "senha-1234").await.unwrap();
+    users.set_role("alice", Role::Admin).await.unwrap();
+
+    spawn_server_with(users).await
+}
+
+async fn admin_login(addr: SocketAddr) -> Socket {
+    let mut socket = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut socket, "alice", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+
+    socket
+}
+
+fn is_permission_denied(reply: &ServerProtocol) -> bool {
+    matches!(reply, ServerProtocol::Error { error: ProtocolError::PermissionDenied })
+}
+
+#[tokio::test]
+async fn admin_protocols_require_the_admin_role() {
+    let addr = spawn_server_with_admin().await;
+
+    let mut anonymous = connect(addr).await;
+    send(&mut anonymous, ClientProtocol::ListOnlineUsers).await;
+    assert!(matches!(
+        recv(&mut anonymous).await,
+        ServerProtocol::Error { error: ProtocolError::NotAuthenticated }
+    ));
+
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    send(&mut bob, ClientProtocol::KickUser { username: "alice".into() }).await;
+    assert!(is_permission_denied(&recv(&mut bob).await));
+
+    // Promovido, bob passa a poder; rebaixado, deixa de
+    // poder já no pedido seguinte.
+    let mut alice = admin_login(addr).await;
+    send(&mut alice, ClientProtocol::SetRole { username: "Bob".into(), role: Role::Admin }).await;
+    match recv(&mut alice).await {
+        ServerProtocol::RoleChanged { username, role } => {
+            assert_eq!(username, "bob");
+            assert_eq!(role, Role::Admin);
+        },
+        other => panic!("esperava RoleChanged, veio {other:?}"),
+    }
+
+    send(&mut bob, ClientProtocol::ListOnlineUsers).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::OnlineUsers { .. }));
+
+    send(&mut alice, ClientProtocol::SetRole { username: "bob".into(), role: Role::User }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::RoleChanged { .. }));
+
+    send(&mut bob, ClientProtocol::ListOnlineUsers).await;
+    assert!(is_permission_denied(&recv(&mut bob).await));
+}
+
+#[tokio::test]
+async fn moderators_only_act_on_regular_accounts() {
+    let users = users();
+
+    for username in ["alice", "bob", "carol", "dave"] {
+        users.add_user(username, "senha-1234").await.unwrap();
+    }
+
+    users.set_role("alice", Role::Admin).await.unwrap();
+    users.set_role("bob", Role::Moderator).await.unwrap();
+    users.set_role("dave", Role::Moderator).await.unwrap();
+
+    let addr = spawn_server_with(users).await;
+
+    let mut bob = connect(addr).await;
+    authenticate(&mut bob, "bob", "senha-1234").await;
+
+    let mut carol = connect(addr).await;
+    authenticate(&mut carol, "carol", "senha-1234").await;
+
+    send(&mut bob, ClientProtocol::ListOnlineUsers).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::OnlineUsers { .. }));
+
+    // Nem admins nem outros moderadores...
+    for target in ["alice", "dave"] {
+        send(&mut bob, ClientProtocol::KickUser { username: target.into() }).await;
+        assert!(is_permission_denied(&recv(&mut bob).await));
+
+        send(&mut bob, ClientProtocol::BanUser {
+            username: target.into(),
+            reason: "spam".into(),
+            until: None,
+        }).await;
+        assert!(is_permission_denied(&recv(&mut bob).await));
+    }
+
+    send(&mut bob, ClientProtocol::SetRole { username: "carol".into(), role: Role::Moderator }).await;
+    assert!(is_permission_denied(&recv(&mut bob).await));
+
+    // ...mas contas comuns, sim.
+    send(&mut bob, ClientProtocol::BanUser {
+        username: "carol".into(),
+        reason: "spam".into(),
+        until: None,
+    }).await;
+    assert!(matches!(
+        recv(&mut carol).await,
+        ServerProtocol::SessionEnded { reason: SessionEndReason::Banned }
+    ));
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::UserBanned { .. }));
+
+    send(&mut bob, ClientProtocol::UnbanUser { username: "carol".into() }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::UserUnbanned { .. }));
+}
+
+#[tokio::test]
+async fn admin_lists_and_kicks_online_users() {
+    let addr = spawn_server_with_admin().await;
+    let mut alice = admin_login(addr).await;
+    let (mut bob, token) = login_with_token(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::ListOnlineUsers).await;
+    match recv(&mut alice).await {
+        ServerProtocol::OnlineUsers { users } => {
+            let names: Vec<_> = users.iter().map(|u| u.username.as_str()).collect();
+            assert_eq!(names, ["alice", "bob"]);
+        },
+        other => panic!("esperava OnlineUsers, veio {other:?}"),
+    }
+
+    send(&mut alice, ClientProtocol::KickUser { username: "bob".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::SessionEnded { reason: SessionEndReason::Kicked }
+    ));
+    match recv(&mut alice).await {
+        ServerProtocol::UserKicked { username, sessions } => {
+            assert_eq!(username, "bob");
+            assert_eq!(sessions, 1);
+        },
+        other => panic!("esperava UserKicked, veio {other:?}"),
+    }
+
+    // O token de bob foi revogado, mas a senha continua valendo.
+    let mut socket = connect(addr).await;
+    send(&mut socket, ClientProtocol::AuthenticateWithToken { token }).await;
+    assert!(matches!(
+        recv(&mut socket).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidToken),
+        }
+    ));
+    assert!(matches!(
+        authenticate(&mut socket, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+}
+
+#[tokio::test]
+async fn banned_users_cannot_log_in_until_unbanned() {
+    let addr = spawn_server_with_admin().await;
+    let mut alice = admin_login(addr).await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::BanUser {
+        username: "bob".into(),
+        reason: "spam".into(),
+        until: None,
+    }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::SessionEnded { reason: SessionEndReason::Banned }
+    ));
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::UserBanned { until: None, .. }));
+
+    let mut socket = connect(addr).await;
+    match authenticate(&mut socket, "bob", "senha-1234").await {
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::AccountBanned { reason, until }),
+        } => {
+            assert_eq!(reason, "spam");
+            assert_eq!(until, None);
+        },
+        other => panic!("esperava AccountBanned, veio {other:?}"),
+    }
+
+    // Mensagens para um banido são recusadas, em vez de
+    // ficarem guardadas para quando ele voltar.
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "oi".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error { error: ProtocolError::UserBanned }
+    ));
+
+    send(&mut alice, ClientProtocol::UnbanUser { username: "bob".into() }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::UserUnbanned { .. }));
+
+    assert!(matches!(
+        authenticate(&mut socket, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+}
+
+#[tokio::test]
+async fn suspensions_end_on_their_own() {
+    let addr = spawn_server_with_admin().await;
+    let mut alice = admin_login(addr).await;
+    let _bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::BanUser {
+        username: "bob".into(),
+        reason: "flood".into(),
+        until: Some(unix_now() - 1),
+    }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidBanExpiry),
+        }
+    ));
+
+    let until = unix_now() + 2;
+    send(&mut alice, ClientProtocol::BanUser {
+        username: "bob".into(),
+        reason: "flood".into(),
+        until: Some(until),
+    }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::UserBanned { .. }));
+
+    let mut socket = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut socket, "bob", "senha-1234").await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::AccountBanned {
+                until: Some(u),
+                ..
+            }),
+        } if u == until
+    ));
+
+    tokio::time::sleep(Duration::from_secs(3)).await;
+
+    assert!(matches!(
+        authenticate(&mut socket, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+}
+
+// Confirma o recebimento da mensagem id.
+async fn ack(socket: &mut Socket, id: i64) {
+    send(socket, ClientProtocol::AckMessages { ids: vec![id] }).await;
+}
+
+async fn request_history
+(
+    socket: &mut Socket,
+    with: &str,
+    before: Option<i64>,
+    limit: Option<u32>,
+) -> (Vec<HistoryMessage>, bool)
+{
+    send(socket, ClientProtocol::RequestHistory {
+        with: with.into(),
+        before,
+        limit,
+    }).await;
+
+    match recv(socket).await {
+        ServerProtocol::History { messages, has_more, .. } => (messages, has_more),
+        other => panic!("esperava History, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn history_keeps_both_directions_after_reconnecting() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "oi".into(),
+    }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::Message { .. }));
+
+    send(&mut bob, ClientProtocol::SendMessage {
+        to: "alice".into(),
+        text: "olá".into(),
+    }).await;
+    match recv(&mut alice).await {
+        ServerProtocol::Message { id, .. } => ack(&mut alice, id).await,
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    }
+
+    bob.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Mensagens guardadas para entrega também entram no
+    // histórico.
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "guardada".into(),
+    }).await;
+    alice.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut alice = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut alice, "alice", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+
+    let (messages, has_more) = request_history(&mut alice, "BOB", None, None).await;
+    assert!(!has_more);
+
+    let texts: Vec<(&str, &str, &str)> = messages
+        .iter()
+        .map(|m| (m.from.as_str(), m.to.as_str(), m.text.as_str()))
+        .collect();
+
+    assert_eq!(texts, [
+        ("alice", "bob", "oi"),
+        ("bob", "alice", "olá"),
+        ("alice", "bob", "guardada"),
+    ]);
+
+    assert!(messages.windows(2).all(|w| w[0].id < w[1].id));
+}
+
+#[tokio::test]
+async fn history_is_paginated_from_the_newest() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut carol = login(addr, "carol", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    for i in 1..=5 {
+        send(&mut alice, ClientProtocol::SendMessage {
+            to: "bob".into(),
+            text: format!("m{i}"),
+        }).await;
+        assert!(matches!(recv(&mut bob).await, ServerProtocol::Message { .. }));
+    }
+
+    // Conversas com outras pessoas não aparecem.
+    send(&mut carol, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "de outra conversa".into(),
+    }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::Message { .. }));
+
+    let texts = |messages: &[HistoryMessage]| -> Vec<String> {
+        messages.iter().map(|m| m.text.clone()).collect()
+    };
+
+    let (page, has_more) = request_history(&mut bob, "alice", None, Some(2)).await;
+    assert_eq!(texts(&page), ["m4", "m5"]);
+    assert!(has_more);
+
+    let (page, has_more) = request_history(&mut bob, "alice", Some(page[0].id), Some(2)).await;
+    assert_eq!(texts(&page), ["m2", "m3"]);
+    assert!(has_more);
+
+    let (page, has_more) = request_history(&mut bob, "alice", Some(page[0].id), Some(2)).await;
+    assert_eq!(texts(&page), ["m1"]);
+    assert!(!has_more);
+
+    send(&mut bob, ClientProtocol::RequestHistory {
+        with: "ninguem".into(),
+        before: None,
+        limit: None,
+    }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::UserNotFound),
+        }
+    ));
+}
+
+#[tokio::test]
+async fn delivered_messages_carry_their_history_id() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    let before = unix_now();
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "online".into(),
+    }).await;
+    let (online_id, sent_at) = match recv(&mut bob).await {
+        ServerProtocol::Message { id, sent_at, .. } => (id, sent_at),
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    };
+    assert!(sent_at >= before && sent_at <= unix_now());
+    ack(&mut bob, online_id).await;
+
+    bob.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "offline".into(),
+    }).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // A mensagem guardada chega com o id e o horário de
+    // quando foi enviada.
+    let mut bob = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut bob, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+    let offline_id = match recv(&mut bob).await {
+        ServerProtocol::Message { id, text, .. } if text == "offline" => id,
+        other => panic!("esperava a mensagem guardada, veio {other:?}"),
+    };
+    assert!(offline_id > online_id);
+
+    let (messages, _) = request_history(&mut bob, "alice", None, None).await;
+    let ids: Vec<i64> = messages.iter().map(|m| m.id).collect();
+    assert_eq!(ids, [online_id, offline_id]);
+}
+
+#[tokio::test]
+async fn oversized_acks_are_rejected() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+
+    let ids: Vec<i64> = (1..=MAX_ACK_IDS as i64 + 1).collect();
+
+    send(&mut alice, ClientProtocol::AckMessages { ids: ids.clone() }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error { error: ProtocolError::TooManyIds { max: MAX_ACK_IDS } }
+    ));
+
+    send(&mut alice, ClientProtocol::AckRoomMessages { ids }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error { error: ProtocolError::TooManyIds { max: MAX_ACK_IDS } }
+    ));
+}
+
+#[tokio::test]
+async fn unacked_messages_are_redelivered_until_acked() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "importante".into(),
+    }).await;
+    let id = match recv(&mut bob).await {
+        ServerProtocol::Message { id, .. } => id,
+        other => panic!("esperava uma mensagem, veio {other:?}"),
+    };
+
+    // Só o destinatário confirma o recebimento.
+    ack(&mut alice, id).await;
+
+    bob.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut bob = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut bob, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Message { id: again, .. } if again == id
+    ));
+
+    ack(&mut bob, id).await;
+    match recv(&mut alice).await {
+        ServerProtocol::MessageDelivered { id: delivered, to, .. } => {
+            assert_eq!(delivered, id);
+            assert_eq!(to, "bob");
+        },
+        other => panic!("esperava MessageDelivered, veio {other:?}"),
+    }
+
+    // Confirmar de novo não gera outro aviso nem traz a
+    // mensagem de volta.
+    ack(&mut bob, id).await;
+    bob.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let mut bob = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut bob, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+    let (messages, _) = request_history(&mut bob, "alice", None, None).await;
+    assert_eq!(messages.len(), 1);
+
+    // Se houvesse outro MessageDelivered ele chegaria
+    // antes da resposta a este pedido.
+    send(&mut alice, ClientProtocol::ListOnlineUsers).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error { error: ProtocolError::PermissionDenied }
+    ));
+}
+
+#[tokio::test]
+async fn room_messages_reach_online_and_offline_members() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    let mut carol = login(addr, "carol", "senha-1234").await;
+    let mut dave = login(addr, "dave", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::CreateRoom { name: "Geral".into() }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::RoomCreated { room } if room == "geral"
+    ));
+
+    send(&mut bob, ClientProtocol::JoinRoom { name: "geral".into() }).await;
+    match recv(&mut bob).await {
+        ServerProtocol::RoomJoined { room, members } => {
+            assert_eq!(room, "geral");
+            assert_eq!(members, ["alice", "bob"]);
+        },
+        other => panic!("esperava RoomJoined, veio {other:?}"),
+    }
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::MemberJoined { username, .. } if username == "bob"
+    ));
+
+    send(&mut carol, ClientProtocol::JoinRoom { name: "geral".into() }).await;
+    assert!(matches!(recv(&mut carol).await, ServerProtocol::RoomJoined { .. }));
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::MemberJoined { .. }));
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::MemberJoined { .. }));
+
+    carol.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    send(&mut alice, ClientProtocol::SendRoomMessage {
+        room: "geral".into(),
+        text: "bom dia".into(),
+    }).await;
+    match recv(&mut bob).await {
+        ServerProtocol::RoomMessage { room, from, text, .. } => {
+            assert_eq!(room, "geral");
+            assert_eq!(from, "alice");
+            assert_eq!(text, "bom dia");
+        },
+        other => panic!("esperava RoomMessage, veio {other:?}"),
+    }
+
+    // Quem não participa da sala não envia mensagens a ela.
+    send(&mut dave, ClientProtocol::SendRoomMessage {
+        room: "geral".into(),
+        text: "oi?".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut dave).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::NotRoomMember),
+        }
+    ));
+
+    let mut carol = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut carol, "carol", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+    match recv(&mut carol).await {
+        ServerProtocol::RoomMessage { id, text, .. } => {
+            assert_eq!(text, "bom dia");
+            send(&mut carol, ClientProtocol::AckRoomMessages { ids: vec![id] }).await;
+        },
+        other => panic!("esperava RoomMessage, veio {other:?}"),
+    }
+
+    carol.close(None).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    // Confirmada, a mensagem não é enviada de novo: a
+    // primeira coisa depois do login é a resposta ao pedido.
+    let mut carol = connect(addr).await;
+    assert!(matches!(
+        authenticate(&mut carol, "carol", "senha-1234").await,
+        ServerProtocol::Authenticated { .. }
+    ));
+    send(&mut carol, ClientProtocol::ListRooms).await;
+    assert!(matches!(recv(&mut carol).await, ServerProtocol::Rooms { .. }));
+}
+
+#[tokio::test]
+async fn members_can_leave_rooms() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::CreateRoom { name: "geral".into() }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::RoomCreated { .. }));
+
+    send(&mut bob, ClientProtocol::CreateRoom { name: "GERAL".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::RoomAlreadyExists),
+        }
+    ));
+
+    send(&mut bob, ClientProtocol::CreateRoom { name: "sala com espaço".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidRoomName { .. }),
+        }
+    ));
+
+    send(&mut bob, ClientProtocol::JoinRoom { name: "outra".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::RoomNotFound),
+        }
+    ));
+
+    send(&mut bob, ClientProtocol::JoinRoom { name: "geral".into() }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::RoomJoined { .. }));
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::MemberJoined { .. }));
+
+    send(&mut bob, ClientProtocol::LeaveRoom { name: "geral".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::RoomLeft { room } if room == "geral"
+    ));
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::MemberLeft { username, .. } if username == "bob"
+    ));
+
+    send(&mut bob, ClientProtocol::LeaveRoom { name: "geral".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::NotRoomMember),
+        }
+    ));
+
+    send(&mut bob, ClientProtocol::ListRooms).await;
+    match recv(&mut bob).await {
+        ServerProtocol::Rooms { rooms } => {
+            assert_eq!(rooms, [RoomInfo {
+                name: "geral".into(),
+                members: 1,
+                joined: false,
+            }]);
+        },
+        other => panic!("esperava Rooms, veio {other:?}"),
+    }
+}
+
+async fn request_presence(socket: &mut Socket, usernames: &[&str]) -> Vec<UserPresence> {
+    send(socket, ClientProtocol::RequestPresence {
+        usernames: usernames.iter().map(|u| u.to_string()).collect(),
+    }).await;
+
+    match recv(socket).await {
+        ServerProtocol::Presence { users } => users,
+        other => panic!("esperava Presence, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn presence_query_reports_current_state() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    let _carol = login(addr, "carol", "senha-1234").await;
+
+    let before = unix_now();
+    send(&mut bob, ClientProtocol::Logout { token: None }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::LoggedOut));
+
+    let presence = request_presence(&mut alice, &["bob"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Offline);
+    assert!(presence[0].last_seen.unwrap() >= before);
+
+    // Desconhecidos e repetidos ficam de fora.
+    let presence = request_presence(&mut alice, &["CAROL", "ninguem", "carol"]).await;
+    assert_eq!(presence, [UserPresence {
+        username: "carol".into(),
+        status: PresenceStatus::Online,
+        last_seen: None,
+    }]);
+}
+
+#[tokio::test]
+async fn watchers_are_told_when_users_come_and_go() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let (mut bob, token) = login_with_token(addr, "bob", "senha-1234").await;
+
+    let presence = request_presence(&mut alice, &["bob"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Online);
+
+    // Uma segunda sessão não muda a presença de bob...
+    let mut second = connect(addr).await;
+    send(&mut second, ClientProtocol::AuthenticateWithToken { token }).await;
+    assert!(matches!(recv(&mut second).await, ServerProtocol::Authenticated { .. }));
+    drop(second);
+
+    // ...e ele só fica offline quando a última é encerrada.
+    send(&mut bob, ClientProtocol::Logout { token: None }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::LoggedOut));
+
+    let last_seen = match recv(&mut alice).await {
+        ServerProtocol::PresenceChanged { username, status, last_seen } => {
+            assert_eq!(username, "bob");
+            assert_eq!(status, PresenceStatus::Offline);
+            last_seen.unwrap()
+        },
+        other => panic!("esperava PresenceChanged, veio {other:?}"),
+    };
+
+    authenticate(&mut bob, "bob", "senha-1234").await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::PresenceChanged { status: PresenceStatus::Online, last_seen: Some(t), .. }
+            if t == last_seen
+    ));
+
+    // Fechar a socket também conta como sair.
+    drop(bob);
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::PresenceChanged { status: PresenceStatus::Offline, .. }
+    ));
+}
+
+#[tokio::test]
+async fn watchers_are_told_when_accounts_are_deleted() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    let presence = request_presence(&mut alice, &["bob"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Online);
+
+    send(&mut bob, ClientProtocol::DeleteAccount { password: "senha-1234".into() }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::SessionEnded { reason: SessionEndReason::AccountDeleted }
+    ));
+
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::PresenceChanged { username, status: PresenceStatus::Offline, .. }
+            if username == "bob"
+    ));
+}
+
+#[tokio::test]
+async fn logging_in_again_ends_the_previous_session() {
+    let addr = spawn_server().await;
+    let mut socket = login(addr, "alice", "senha-1234").await;
+    let _bob = login(addr, "bob", "senha-1234").await;
+    let mut carol = login(addr, "carol", "senha-1234").await;
+
+    let presence = request_presence(&mut carol, &["alice"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Online);
+
+    // A mesma conexão entra com outra conta.
+    assert!(matches!(
+        authenticate(&mut socket, "bob", "senha-1234").await,
+        ServerProtocol::Authenticated { username, .. } if username == "bob"
+    ));
+
+    assert!(matches!(
+        recv(&mut carol).await,
+        ServerProtocol::PresenceChanged { username, status: PresenceStatus::Offline, .. }
+            if username == "alice"
+    ));
+
+    // O que é mandado para alice não chega mais nessa conexão:
+    // a próxima coisa que ela recebe é a resposta do próprio pedido.
+    send(&mut carol, ClientProtocol::SendMessage {
+        to: "alice".into(),
+        text: "ainda aí?".into(),
+    }).await;
+    tokio::time::sleep(Duration::from_millis(100)).await;
+
+    let presence = request_presence(&mut socket, &["alice"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Offline);
+}
+
+async fn create_post(socket: &mut Socket, text: &str) -> FeedPost {
+    send(socket, ClientProtocol::CreatePost { text: text.into() }).await;
+
+    match recv(socket).await {
+        ServerProtocol::PostCreated { post } => post,
+        other => panic!("esperava PostCreated, veio {other:?}"),
+    }
+}
+
+async fn request_feed(socket: &mut Socket, before: Option<i64>, limit: u32) -> (Vec<FeedPost>, bool) {
+    send(socket, ClientProtocol::RequestFeed { before, limit: Some(limit) }).await;
+
+    match recv(socket).await {
+        ServerProtocol::Feed { posts, has_more } => (posts, has_more),
+        other => panic!("esperava Feed, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn feed_is_paginated_from_the_newest() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    for i in 0..3 {
+        create_post(&mut alice, &format!("alice {i}")).await;
+        create_post(&mut bob, &format!("bob {i}")).await;
+    }
+
+    let texts = |posts: &[FeedPost]| -> Vec<String> {
+        posts.iter().map(|p| p.text.clone()).collect()
+    };
+
+    let (page, has_more) = request_feed(&mut alice, None, 4).await;
+    assert_eq!(texts(&page), ["bob 2", "alice 2", "bob 1", "alice 1"]);
+    assert!(has_more);
+
+    let (page, has_more) = request_feed(&mut alice, Some(page[3].id), 4).await;
+    assert_eq!(texts(&page), ["bob 0", "alice 0"]);
+    assert_eq!(page[0].author, "bob");
+    assert!(!has_more);
+
+    for text in ["", "   ", &"a".repeat(501)] {
+        send(&mut alice, ClientProtocol::CreatePost { text: text.into() }).await;
+        assert!(matches!(
+            recv(&mut alice).await,
+            ServerProtocol::Error {
+                error: ProtocolError::AuthenticateError(AuthenticateErrorType::InvalidPost { max: 500 }),
+            }
+        ));
+    }
+}
+
+#[tokio::test]
+async fn subscribers_receive_new_posts() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    send(&mut bob, ClientProtocol::SubscribeFeed).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::FeedSubscribed));
+
+    let post = create_post(&mut alice, "olá, feed").await;
+    assert_eq!(post.author, "alice");
+
+    match recv(&mut bob).await {
+        ServerProtocol::NewPost { post: received } => assert_eq!(received, post),
+        other => panic!("esperava NewPost, veio {other:?}"),
+    }
+
+    send(&mut bob, ClientProtocol::UnsubscribeFeed).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::FeedUnsubscribed));
+
+    create_post(&mut alice, "ninguém inscrito").await;
+
+    // Sem inscrição o post só aparece pedindo o feed.
+    let (page, _) = request_feed(&mut bob, None, 1).await;
+    assert_eq!(page[0].text, "ninguém inscrito");
+}
+
+#[tokio::test]
+async fn typing_is_relayed_throttled_and_expires() {
+    let addr = spawn_server_with(users().with_typing(TypingConfig {
+        interval: Duration::from_secs(60),
+        timeout: Duration::from_millis(300),
+    })).await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    // Para quem não está online nada é enviado, nem erro.
+    send(&mut alice, ClientProtocol::Typing { to: "ninguem".into() }).await;
+    let presence = request_presence(&mut alice, &["bob"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Online);
+
+    for _ in 0..5 {
+        send(&mut alice, ClientProtocol::Typing { to: "BOB".into() }).await;
+    }
+
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Typing { from } if from == "alice"
+    ));
+
+    // Os outros avisos foram segurados, então o próximo é
+    // o de que alice parou de digitar.
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::StoppedTyping { from } if from == "alice"
+    ));
+}
+
+#[tokio::test]
+async fn read_receipts_move_the_unread_counters() {
+    let addr = spawn_server().await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+
+    let mut ids = Vec::new();
+
+    for text in ["um", "dois", "três"] {
+        send(&mut alice, ClientProtocol::SendMessage {
+            to: "bob".into(),
+            text: text.into(),
+        }).await;
+
+        match recv(&mut bob).await {
+            ServerProtocol::Message { id, .. } => {
+                ack(&mut bob, id).await;
+                ids.push(id);
+            },
+            other => panic!("esperava Message, veio {other:?}"),
+        }
+
+        assert!(matches!(recv(&mut alice).await, ServerProtocol::MessageDelivered { .. }));
+    }
+
+    send(&mut bob, ClientProtocol::MarkRead { with: "ALICE".into(), up_to: ids[1] }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::MarkedRead { with, up_to } if with == "alice" && up_to == ids[1]
+    ));
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::MessagesRead { by, up_to, .. } if by == "bob" && up_to == ids[1]
+    ));
+
+    // Voltar o cursor não muda nada nem avisa alice.
+    send(&mut bob, ClientProtocol::MarkRead { with: "alice".into(), up_to: ids[0] }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::MarkedRead { .. }));
+
+    send(&mut bob, ClientProtocol::MarkRead { with: "alice".into(), up_to: ids[2] + 100 }).await;
+    assert!(matches!(
+        recv(&mut bob).await,
+        ServerProtocol::Error {
+            error: ProtocolError::AuthenticateError(AuthenticateErrorType::MessageNotFound),
+        }
+    ));
+
+    // Nada chegou para alice depois do primeiro recibo.
+    let presence = request_presence(&mut alice, &["bob"]).await;
+    assert_eq!(presence[0].status, PresenceStatus::Online);
+
+    // Só "três" continua não lida.
+    let mut socket = connect(addr).await;
+    match authenticate(&mut socket, "bob", "senha-1234").await {
+        ServerProtocol::Authenticated { unread, .. } => {
+            assert_eq!(unread, [UnreadCount {
+                with: "alice".into(),
+                count: 1,
+            }]);
+        },
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+
+    match authenticate(&mut connect(addr).await, "alice", "senha-1234").await {
+        ServerProtocol::Authenticated { unread, .. } => assert!(unread.is_empty()),
+        other => panic!("esperava Authenticated, veio {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn banned_users_with_open_sessions_get_no_messages() {
+    let users = users();
+    let addr = spawn_server_with(users.clone()).await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let _bob = login(addr, "bob", "senha-1234").await;
+
+    // Simula um login que terminou depois do banimento: a
+    // sessão de bob continua em on_users.
+    let sessions = users.clone()
+        .ban_user("bob", "spam", None)
+        .await
+        .unwrap();
+    users.on_users.lock().await.insert(User::new("bob"), sessions);
+
+    send(&mut alice, ClientProtocol::SendMessage {
+        to: "bob".into(),
+        text: "oi".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut alice).await,
+        ServerProtocol::Error { error: ProtocolError::UserBanned }
+    ));
+}
+
+#[tokio::test]
+async fn banned_members_get_no_room_messages() {
+    let users = users();
+    let addr = spawn_server_with(users.clone()).await;
+    let mut alice = login(addr, "alice", "senha-1234").await;
+    let mut bob = login(addr, "bob", "senha-1234").await;
+    let mut carol = login(addr, "carol", "senha-1234").await;
+
+    send(&mut alice, ClientProtocol::CreateRoom { name: "geral".into() }).await;
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::RoomCreated { .. }));
+
+    send(&mut bob, ClientProtocol::JoinRoom { name: "geral".into() }).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::RoomJoined { .. }));
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::MemberJoined { .. }));
+
+    send(&mut carol, ClientProtocol::JoinRoom { name: "geral".into() }).await;
+    assert!(matches!(recv(&mut carol).await, ServerProtocol::RoomJoined { .. }));
+    assert!(matches!(recv(&mut alice).await, ServerProtocol::MemberJoined { .. }));
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::MemberJoined { .. }));
+
+    // Como em banned_users_with_open_sessions_get_no_messages,
+    // a sessão de bob sobrevive ao banimento.
+    let sessions = users.clone()
+        .ban_user("bob", "spam", None)
+        .await
+        .unwrap();
+    users.on_users.lock().await.insert(User::new("bob"), sessions);
+
+    send(&mut alice, ClientProtocol::SendRoomMessage {
+        room: "geral".into(),
+        text: "bom dia".into(),
+    }).await;
+    assert!(matches!(
+        recv(&mut carol).await,
+        ServerProtocol::RoomMessage { text, .. } if text == "bom dia"
+    ));
+
+    // Nada foi entregue a bob nem ficou pendente para ele.
+    send(&mut bob, ClientProtocol::ListRooms).await;
+    assert!(matches!(recv(&mut bob).await, ServerProtocol::Rooms { .. }));
+    assert!(users.get_stored_room_messages("bob").await.unwrap().is_empty());
+}
@@ -22,7 +22,8 @@ use futures_util::stream::{SplitSink, SplitStream};
 
 pub type ArcWriter = Arc<Mutex<SplitSink<WebSocket, Message>>>;
 pub type ArcReader = Arc<Mutex<SplitStream<WebSocket>>>;
-pub type ArcUser = Arc<Mutex<User>>;
+// None enquanto a conexão não tiver se autenticado.
+pub type ArcUser = Arc<Mutex<Option<User>>>;
 pub type ArcUsers = Arc<Mutex<Users>>;
 pub type Tx = UnboundedSender<Message>;
 pub type Rx = UnboundedReceiver<Message>;
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS offline_messages;
+DROP TABLE IF EXISTS users;
@@ -0,0 +1,8 @@
+-- Sessões abertas por tokens de reconexão (veja /users/src/tokens.rs).
+CREATE TABLE sessions (
+    id CHAR(32) PRIMARY KEY,
+    username VARCHAR(255) NOT NULL,
+    expires_at BIGINT NOT NULL,
+    revoked BOOLEAN NOT NULL DEFAULT FALSE,
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
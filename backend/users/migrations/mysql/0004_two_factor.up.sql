@@ -0,0 +1,20 @@
+-- Segredo TOTP de cada usuário com 2FA. enabled só fica
+-- verdadeiro depois que o usuário confirma um código;
+-- last_step é o último passo TOTP aceito, para que um
+-- código não seja usado duas vezes.
+CREATE TABLE two_factor (
+    username VARCHAR(255) PRIMARY KEY,
+    secret VARCHAR(64) NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT FALSE,
+    last_step BIGINT NOT NULL DEFAULT 0,
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
+
+-- Códigos de recuperação de uso único. Só o hash sha256
+-- de cada código é guardado.
+CREATE TABLE recovery_codes (
+    username VARCHAR(255) NOT NULL,
+    code_hash CHAR(64) NOT NULL,
+    PRIMARY KEY (username, code_hash),
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
@@ -0,0 +1,3 @@
+ALTER TABLE users
+    DROP COLUMN banned,
+    DROP COLUMN role;
@@ -0,0 +1,16 @@
+-- Histórico de todas as mensagens diretas, entregues ou
+-- não. sent_at é um timestamp unix (segundos) definido
+-- pelo servidor.
+CREATE TABLE messages (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    sender VARCHAR(255) NOT NULL,
+    receiver VARCHAR(255) NOT NULL,
+    body TEXT NOT NULL,
+    sent_at BIGINT NOT NULL,
+    FOREIGN KEY (sender) REFERENCES users(username) ON DELETE CASCADE,
+    FOREIGN KEY (receiver) REFERENCES users(username) ON DELETE CASCADE
+);
+
+-- Uma conversa é buscada nos dois sentidos, da mensagem
+-- mais nova para a mais antiga.
+CREATE INDEX messages_conversation ON messages (sender, receiver, id);
@@ -0,0 +1,4 @@
+DROP TABLE IF EXISTS pending_room_messages;
+DROP TABLE IF EXISTS room_messages;
+DROP TABLE IF EXISTS room_members;
+DROP TABLE IF EXISTS rooms;
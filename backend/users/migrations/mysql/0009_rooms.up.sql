@@ -0,0 +1,40 @@
+-- Salas de conversa em grupo. O nome já está na forma
+-- canônica (minúsculas).
+CREATE TABLE rooms (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    name VARCHAR(255) NOT NULL UNIQUE,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE room_members (
+    room_id BIGINT NOT NULL,
+    username VARCHAR(255) NOT NULL,
+    joined_at BIGINT NOT NULL,
+    PRIMARY KEY (room_id, username),
+    FOREIGN KEY (room_id) REFERENCES rooms(id) ON DELETE CASCADE,
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
+
+CREATE INDEX room_members_username ON room_members (username);
+
+CREATE TABLE room_messages (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    room_id BIGINT NOT NULL,
+    sender VARCHAR(255) NOT NULL,
+    body TEXT NOT NULL,
+    sent_at BIGINT NOT NULL,
+    FOREIGN KEY (room_id) REFERENCES rooms(id) ON DELETE CASCADE,
+    FOREIGN KEY (sender) REFERENCES users(username) ON DELETE CASCADE
+);
+
+CREATE INDEX room_messages_room ON room_messages (room_id, id);
+
+-- Como pending_messages: uma linha por membro que ainda
+-- não confirmou o recebimento da mensagem.
+CREATE TABLE pending_room_messages (
+    message_id BIGINT NOT NULL,
+    receiver VARCHAR(255) NOT NULL,
+    PRIMARY KEY (receiver, message_id),
+    FOREIGN KEY (message_id) REFERENCES room_messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (receiver) REFERENCES users(username) ON DELETE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Posts do feed público. ids são crescentes, então o feed
+-- (do mais novo para o mais antigo) é paginado pelo id.
+CREATE TABLE posts (
+    id BIGINT AUTO_INCREMENT PRIMARY KEY,
+    author VARCHAR(255) NOT NULL,
+    body TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    FOREIGN KEY (author) REFERENCES users(username) ON DELETE CASCADE
+);
+
+CREATE INDEX posts_author ON posts (author);
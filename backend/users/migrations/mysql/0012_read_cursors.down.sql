@@ -0,0 +1,6 @@
+-- O MySQL descartou o índice criado sozinho para a foreign
+-- key de receiver quando messages_receiver foi criado, e
+-- não deixa apagar o único índice que a atende.
+CREATE INDEX receiver ON messages (receiver);
+DROP INDEX messages_receiver ON messages;
+DROP TABLE IF EXISTS read_cursors;
@@ -0,0 +1,3 @@
+-- Os nomes antigos não são guardados: as contas renomeadas
+-- continuam na forma canônica.
+DROP TABLE IF EXISTS username_conflicts;
@@ -0,0 +1,89 @@
+-- Usernames passaram a ser guardados na forma canônica
+-- (sem espaços nas pontas, NFKC, minúsculas). Contas criadas
+-- antes disso são renomeadas aqui, junto de tudo que aponta
+-- para elas.
+--
+-- O SQL não sabe fazer NFKC, então nomes com caracteres
+-- fora do ASCII não são tocados; também não são renomeadas
+-- as contas cuja forma canônica já pertence a outra. As duas
+-- ficam em username_conflicts, para serem resolvidas à mão.
+--
+-- A collation padrão ignora maiúsculas, então as comparações
+-- que precisam distinguir 'Alice' de 'alice' são feitas em
+-- binário.
+CREATE TABLE IF NOT EXISTS username_conflicts (
+    username VARCHAR(255) PRIMARY KEY,
+    -- 'collision' ou 'unicode'.
+    reason VARCHAR(16) NOT NULL,
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
+
+INSERT IGNORE INTO username_conflicts (username, reason)
+SELECT username, 'unicode' FROM users
+WHERE LENGTH(username) <> CHAR_LENGTH(username);
+
+INSERT IGNORE INTO username_conflicts (username, reason)
+SELECT u.username, 'collision' FROM users u
+WHERE CAST(u.username AS BINARY) <> CAST(LOWER(TRIM(u.username)) AS BINARY)
+    AND EXISTS (
+        SELECT 1 FROM users o
+        WHERE CAST(o.username AS BINARY) <> CAST(u.username AS BINARY)
+            AND LOWER(TRIM(o.username)) = LOWER(TRIM(u.username))
+    );
+
+-- Tabela comum, e não TEMPORARY, para que uma tentativa
+-- retomada em outra conexão ainda a encontre.
+CREATE TABLE username_renames AS
+SELECT username AS from_name, LOWER(TRIM(username)) AS to_name FROM users
+WHERE CAST(username AS BINARY) <> CAST(LOWER(TRIM(username)) AS BINARY)
+    AND username NOT IN (SELECT username FROM username_conflicts);
+
+-- As foreign keys não têm ON UPDATE CASCADE: ficam
+-- desligadas enquanto as tabelas são renomeadas uma a uma.
+SET FOREIGN_KEY_CHECKS = 0;
+
+UPDATE users t JOIN username_renames r ON t.username = r.from_name
+SET t.username = r.to_name;
+
+UPDATE sessions t JOIN username_renames r ON t.username = r.from_name
+SET t.username = r.to_name;
+
+UPDATE password_resets t JOIN username_renames r ON t.username = r.from_name
+SET t.username = r.to_name;
+
+UPDATE two_factor t JOIN username_renames r ON t.username = r.from_name
+SET t.username = r.to_name;
+
+UPDATE recovery_codes t JOIN username_renames r ON t.username = r.from_name
+SET t.username = r.to_name;
+
+UPDATE messages t JOIN username_renames r ON t.sender = r.from_name
+SET t.sender = r.to_name;
+
+UPDATE messages t JOIN username_renames r ON t.receiver = r.from_name
+SET t.receiver = r.to_name;
+
+UPDATE pending_messages t JOIN username_renames r ON t.receiver = r.from_name
+SET t.receiver = r.to_name;
+
+UPDATE room_members t JOIN username_renames r ON t.username = r.from_name
+SET t.username = r.to_name;
+
+UPDATE room_messages t JOIN username_renames r ON t.sender = r.from_name
+SET t.sender = r.to_name;
+
+UPDATE pending_room_messages t JOIN username_renames r ON t.receiver = r.from_name
+SET t.receiver = r.to_name;
+
+UPDATE posts t JOIN username_renames r ON t.author = r.from_name
+SET t.author = r.to_name;
+
+UPDATE read_cursors t JOIN username_renames r ON t.reader = r.from_name
+SET t.reader = r.to_name;
+
+UPDATE read_cursors t JOIN username_renames r ON t.peer = r.from_name
+SET t.peer = r.to_name;
+
+SET FOREIGN_KEY_CHECKS = 1;
+
+DROP TABLE username_renames;
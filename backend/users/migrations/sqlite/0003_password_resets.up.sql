@@ -0,0 +1,9 @@
+-- Códigos de uso único para redefinir a senha, gerados por
+-- um administrador (veja `utils reset-password`). Só o hash
+-- sha256 do código é guardado.
+CREATE TABLE password_resets (
+    username VARCHAR(255) PRIMARY KEY,
+    code_hash CHAR(64) NOT NULL,
+    expires_at BIGINT NOT NULL,
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
@@ -0,0 +1,4 @@
+-- Papel de cada conta (veja /users/src/roles.rs) e se ela
+-- foi banida por um admin.
+ALTER TABLE users ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'user';
+ALTER TABLE users ADD COLUMN banned BOOLEAN NOT NULL DEFAULT FALSE;
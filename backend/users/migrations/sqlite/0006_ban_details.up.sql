@@ -0,0 +1,5 @@
+-- Motivo e fim de um banimento. banned_until NULL com
+-- banned verdadeiro é um banimento permanente; com um
+-- timestamp é uma suspensão, que deixa de valer sozinha.
+ALTER TABLE users ADD COLUMN banned_until BIGINT NULL;
+ALTER TABLE users ADD COLUMN ban_reason TEXT NULL;
@@ -0,0 +1,17 @@
+CREATE TABLE IF NOT EXISTS offline_messages (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sender VARCHAR(255) NOT NULL,
+    receiver VARCHAR(255) NOT NULL,
+    message TEXT NOT NULL,
+    sent_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    FOREIGN KEY (sender) REFERENCES users(username) ON DELETE CASCADE,
+    FOREIGN KEY (receiver) REFERENCES users(username) ON DELETE CASCADE
+);
+
+INSERT INTO offline_messages (sender, receiver, message, sent_at)
+SELECT m.sender, m.receiver, m.body, datetime(m.sent_at, 'unixepoch')
+FROM pending_messages p
+JOIN messages m ON m.id = p.message_id
+ORDER BY m.id;
+
+DROP TABLE IF EXISTS pending_messages;
@@ -0,0 +1,100 @@
+-- Mensagens ainda não entregues passam a ser referências
+-- para a tabela messages, então chegam ao destinatário com o
+-- mesmo id e horário que têm no histórico.
+CREATE TABLE pending_messages (
+    message_id INTEGER PRIMARY KEY,
+    receiver VARCHAR(255) NOT NULL,
+    FOREIGN KEY (message_id) REFERENCES messages(id) ON DELETE CASCADE,
+    FOREIGN KEY (receiver) REFERENCES users(username) ON DELETE CASCADE
+);
+
+CREATE INDEX pending_messages_receiver ON pending_messages (receiver, message_id);
+
+-- Mensagens de offline_messages guardadas depois de 0007 já
+-- estão no histórico, com um sent_at que pode diferir em até
+-- um segundo; as guardadas antes ainda não estão. Cada linha
+-- de offline_messages precisa virar exatamente uma pendente,
+-- mesmo que haja outras iguais no mesmo segundo, então as
+-- gêmeas dos dois lados são pareadas pela ordem do id.
+CREATE TEMP TABLE offline_twins AS
+SELECT o.id, o.sender, o.receiver, o.message, CAST(strftime('%s', o.sent_at) AS INTEGER) AS sent_at
+FROM offline_messages o
+WHERE EXISTS (
+    SELECT 1 FROM messages m
+    WHERE m.sender = o.sender
+        AND m.receiver = o.receiver
+        AND m.body = o.message
+        AND ABS(m.sent_at - CAST(strftime('%s', o.sent_at) AS INTEGER)) <= 1
+);
+
+CREATE TEMP TABLE message_twins AS
+SELECT m.id, m.sender, m.receiver, m.body, m.sent_at
+FROM messages m
+WHERE EXISTS (
+    SELECT 1 FROM offline_twins o
+    WHERE o.sender = m.sender
+        AND o.receiver = m.receiver
+        AND o.message = m.body
+        AND ABS(m.sent_at - o.sent_at) <= 1
+);
+
+-- message_id fica NULL para as que ainda não estão no
+-- histórico.
+CREATE TEMP TABLE offline_backfill AS
+SELECT o.id AS offline_id, o.receiver, (
+    SELECT m.id FROM message_twins m
+    WHERE m.sender = o.sender
+        AND m.receiver = o.receiver
+        AND m.body = o.message
+        AND ABS(m.sent_at - CAST(strftime('%s', o.sent_at) AS INTEGER)) <= 1
+        AND (
+            SELECT COUNT(*) FROM message_twins m2
+            WHERE m2.sender = m.sender
+                AND m2.receiver = m.receiver
+                AND m2.body = m.body
+                AND m2.id <= m.id
+        ) = (
+            SELECT COUNT(*) FROM offline_twins o2
+            WHERE o2.sender = o.sender
+                AND o2.receiver = o.receiver
+                AND o2.message = o.message
+                AND o2.id <= o.id
+        )
+) AS message_id
+FROM offline_messages o;
+
+-- As que faltam entram no histórico com ids novos, depois
+-- de todos os já usados, na ordem em que foram guardadas.
+CREATE TEMP TABLE backfill_start AS
+SELECT MAX(
+    COALESCE((SELECT MAX(id) FROM messages), 0),
+    COALESCE((SELECT seq FROM sqlite_sequence WHERE name = 'messages'), 0)
+) AS id;
+
+CREATE TEMP TABLE backfill_new AS
+SELECT offline_id FROM offline_backfill WHERE message_id IS NULL;
+
+UPDATE offline_backfill
+SET message_id = (SELECT id FROM backfill_start) + (
+    SELECT COUNT(*) FROM backfill_new n
+    WHERE n.offline_id <= offline_backfill.offline_id
+)
+WHERE message_id IS NULL;
+
+INSERT INTO messages (id, sender, receiver, body, sent_at)
+SELECT b.message_id, o.sender, o.receiver, o.message, CAST(strftime('%s', o.sent_at) AS INTEGER)
+FROM offline_backfill b
+JOIN offline_messages o ON o.id = b.offline_id
+WHERE b.offline_id IN (SELECT offline_id FROM backfill_new)
+ORDER BY b.message_id;
+
+INSERT INTO pending_messages (message_id, receiver)
+SELECT message_id, receiver FROM offline_backfill;
+
+DROP TABLE backfill_new;
+DROP TABLE backfill_start;
+DROP TABLE offline_backfill;
+DROP TABLE message_twins;
+DROP TABLE offline_twins;
+
+DROP TABLE offline_messages;
@@ -0,0 +1,3 @@
+-- Quando o usuário ficou offline pela última vez
+-- (timestamp unix). NULL se ele nunca se conectou.
+ALTER TABLE users ADD COLUMN last_seen BIGINT NULL;
@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS messages_receiver;
+DROP TABLE IF EXISTS read_cursors;
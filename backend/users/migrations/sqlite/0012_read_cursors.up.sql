@@ -0,0 +1,15 @@
+-- Até onde cada usuário leu cada conversa: last_read_id é
+-- o id (em messages) da última mensagem lida na conversa
+-- entre reader e peer. Só anda para frente.
+CREATE TABLE read_cursors (
+    reader VARCHAR(255) NOT NULL,
+    peer VARCHAR(255) NOT NULL,
+    last_read_id BIGINT NOT NULL,
+    read_at BIGINT NOT NULL,
+    PRIMARY KEY (reader, peer),
+    FOREIGN KEY (reader) REFERENCES users(username) ON DELETE CASCADE,
+    FOREIGN KEY (peer) REFERENCES users(username) ON DELETE CASCADE
+);
+
+-- As mensagens não lidas são contadas por destinatário.
+CREATE INDEX messages_receiver ON messages (receiver, sender, id);
@@ -0,0 +1,82 @@
+-- Usernames passaram a ser guardados na forma canônica
+-- (sem espaços nas pontas, NFKC, minúsculas). Contas criadas
+-- antes disso são renomeadas aqui, junto de tudo que aponta
+-- para elas.
+--
+-- O SQL só sabe fazer trim e minúsculas ASCII, então nomes
+-- com outros caracteres não são tocados; também não são
+-- renomeadas as contas cuja forma canônica já pertence a
+-- outra. As duas ficam em username_conflicts, para serem
+-- resolvidas à mão.
+CREATE TABLE username_conflicts (
+    username VARCHAR(255) PRIMARY KEY,
+    -- 'collision' ou 'unicode'.
+    reason VARCHAR(16) NOT NULL,
+    FOREIGN KEY (username) REFERENCES users(username) ON DELETE CASCADE
+);
+
+INSERT INTO username_conflicts (username, reason)
+SELECT username, 'unicode' FROM users
+WHERE LENGTH(CAST(username AS BLOB)) <> LENGTH(username);
+
+INSERT OR IGNORE INTO username_conflicts (username, reason)
+SELECT u.username, 'collision' FROM users u
+WHERE u.username <> LOWER(TRIM(u.username))
+    AND EXISTS (
+        SELECT 1 FROM users o
+        WHERE o.username <> u.username
+            AND LOWER(TRIM(o.username)) = LOWER(TRIM(u.username))
+    );
+
+CREATE TEMP TABLE username_renames AS
+SELECT username AS from_name, LOWER(TRIM(username)) AS to_name FROM users
+WHERE username <> LOWER(TRIM(username))
+    AND username NOT IN (SELECT username FROM username_conflicts);
+
+-- As foreign keys não têm ON UPDATE CASCADE: elas só são
+-- conferidas no commit, quando tudo já foi renomeado.
+PRAGMA defer_foreign_keys = ON;
+
+UPDATE users SET username = (SELECT to_name FROM username_renames WHERE from_name = users.username)
+WHERE username IN (SELECT from_name FROM username_renames);
+
+UPDATE sessions SET username = (SELECT to_name FROM username_renames WHERE from_name = sessions.username)
+WHERE username IN (SELECT from_name FROM username_renames);
+
+UPDATE password_resets SET username = (SELECT to_name FROM username_renames WHERE from_name = password_resets.username)
+WHERE username IN (SELECT from_name FROM username_renames);
+
+UPDATE two_factor SET username = (SELECT to_name FROM username_renames WHERE from_name = two_factor.username)
+WHERE username IN (SELECT from_name FROM username_renames);
+
+UPDATE recovery_codes SET username = (SELECT to_name FROM username_renames WHERE from_name = recovery_codes.username)
+WHERE username IN (SELECT from_name FROM username_renames);
+
+UPDATE messages SET sender = (SELECT to_name FROM username_renames WHERE from_name = messages.sender)
+WHERE sender IN (SELECT from_name FROM username_renames);
+
+UPDATE messages SET receiver = (SELECT to_name FROM username_renames WHERE from_name = messages.receiver)
+WHERE receiver IN (SELECT from_name FROM username_renames);
+
+UPDATE pending_messages SET receiver = (SELECT to_name FROM username_renames WHERE from_name = pending_messages.receiver)
+WHERE receiver IN (SELECT from_name FROM username_renames);
+
+UPDATE room_members SET username = (SELECT to_name FROM username_renames WHERE from_name = room_members.username)
+WHERE username IN (SELECT from_name FROM username_renames);
+
+UPDATE room_messages SET sender = (SELECT to_name FROM username_renames WHERE from_name = room_messages.sender)
+WHERE sender IN (SELECT from_name FROM username_renames);
+
+UPDATE pending_room_messages SET receiver = (SELECT to_name FROM username_renames WHERE from_name = pending_room_messages.receiver)
+WHERE receiver IN (SELECT from_name FROM username_renames);
+
+UPDATE posts SET author = (SELECT to_name FROM username_renames WHERE from_name = posts.author)
+WHERE author IN (SELECT from_name FROM username_renames);
+
+UPDATE read_cursors SET reader = (SELECT to_name FROM username_renames WHERE from_name = read_cursors.reader)
+WHERE reader IN (SELECT from_name FROM username_renames);
+
+UPDATE read_cursors SET peer = (SELECT to_name FROM username_renames WHERE from_name = read_cursors.peer)
+WHERE peer IN (SELECT from_name FROM username_renames);
+
+DROP TABLE username_renames;
@@ -0,0 +1,46 @@
+/*
+Sessões inscritas no feed público. Cada post novo é enviado
+na hora a todas elas; quem não está inscrito só vê os posts
+pedindo o feed (ClientProtocol::RequestFeed).
+
+A inscrição é da sessão, não do usuário, e dura até a
+conexão fechar, o logout ou ClientProtocol::UnsubscribeFeed.
+*/
+
+use std::sync::Mutex;
+
+use crate::Tx;
+
+// O Mutex é o da std, pois nunca é mantido travado
+// através de um .await.
+#[derive(Default)]
+pub struct FeedSubscribers {
+    sessions: Mutex<Vec<Tx>>,
+}
+
+impl FeedSubscribers {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Inscrever uma sessão já inscrita não faz nada.
+    pub fn subscribe(&self, session: &Tx) {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        if !sessions.iter().any(|tx| tx.same_channel(session)) {
+            sessions.push(session.clone());
+        }
+    }
+
+    pub fn unsubscribe(&self, session: &Tx) {
+        self.sessions.lock().unwrap().retain(|tx| !tx.same_channel(session));
+    }
+
+    // Sessões inscritas, descartando as que já fecharam.
+    pub fn sessions(&self) -> Vec<Tx> {
+        let mut sessions = self.sessions.lock().unwrap();
+
+        sessions.retain(|tx| !tx.is_closed());
+        sessions.clone()
+    }
+}
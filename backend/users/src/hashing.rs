@@ -0,0 +1,158 @@
+/*
+Hash de senhas com argon2id. Os custos (memória, tempo e
+paralelismo) são configuráveis e ficam registrados no próprio
+hash (formato PHC), então aumentá-los não invalida as senhas
+antigas: elas continuam sendo conferidas com os custos com que
+foram geradas e, no próximo login, são refeitas com os novos.
+
+Opcionalmente um pepper (segredo do servidor, que não fica na
+database) entra no cálculo do hash. Hashes feitos com pepper
+levam no campo keyid do PHC os 4 primeiros bytes do sha256 do
+pepper, o que permite distinguir hashes sem pepper (que são
+refeitos com ele no próximo login) de hashes feitos com um
+pepper diferente do atual (que não podem ser conferidos).
+*/
+
+use argon2::{
+    Algorithm,
+    Argon2,
+    KeyId,
+    Params,
+    ParamsBuilder,
+    PasswordHash,
+    PasswordHasher,
+    PasswordVerifier,
+    Version,
+    password_hash::{self, SaltString},
+};
+
+use rand::rngs::OsRng;
+
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, Clone)]
+pub struct HashConfig {
+    // Em KiB.
+    pub memory_cost: u32,
+    // Número de iterações.
+    pub time_cost: u32,
+    pub parallelism: u32,
+    pub pepper: Option<Vec<u8>>,
+}
+
+impl Default for HashConfig {
+    fn default() -> Self {
+        Self {
+            memory_cost: Params::DEFAULT_M_COST,
+            time_cost: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+            pepper: None,
+        }
+    }
+}
+
+// Resultado de conferir uma senha.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verification {
+    Mismatch,
+    Match,
+    // A senha confere, mas o hash foi feito com parâmetros
+    // diferentes dos atuais e deveria ser refeito.
+    Outdated,
+}
+
+pub struct PasswordHashing {
+    // Parâmetros atuais, com o keyid do pepper se houver.
+    params: Params,
+    pepper: Option<Vec<u8>>,
+}
+
+impl PasswordHashing {
+    // Falha se os custos forem inválidos para o argon2
+    // (ex: memory_cost menor que 8 * parallelism) ou se o
+    // pepper for grande demais.
+    pub fn new(config: HashConfig) -> Result<Self, argon2::Error> {
+        let mut builder = ParamsBuilder::new();
+
+        builder
+            .m_cost(config.memory_cost)
+            .t_cost(config.time_cost)
+            .p_cost(config.parallelism);
+
+        if let Some(pepper) = &config.pepper {
+            builder.keyid(Self::pepper_id(pepper)?);
+        }
+
+        let hashing = Self {
+            params: builder.build()?,
+            pepper: config.pepper,
+        };
+
+        // Garante que o pepper é aceito pelo argon2.
+        hashing.argon2()?;
+
+        Ok(hashing)
+    }
+
+    fn pepper_id(pepper: &[u8]) -> Result<KeyId, argon2::Error> {
+        KeyId::new(&Sha256::digest(pepper)[..4])
+    }
+
+    fn argon2(&self) -> Result<Argon2<'_>, argon2::Error> {
+        let (algorithm, version, params) = (Algorithm::Argon2id, Version::V0x13, self.params.clone());
+
+        match &self.pepper {
+            Some(pepper) => Argon2::new_with_secret(pepper, algorithm, version, params),
+            None => Ok(Argon2::new(algorithm, version, params)),
+        }
+    }
+
+    pub fn hash(&self, password: &str) -> Result<String, password_hash::Error> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = self.argon2()?.hash_password(password.as_bytes(), &salt)?;
+
+        Ok(hash.to_string())
+    }
+
+    pub fn verify
+    (
+        &self,
+        hash: &str,
+        password: &str,
+    ) -> Result<Verification, password_hash::Error>
+    {
+        let parsed = PasswordHash::new(hash)?;
+        let params = Params::try_from(&parsed)?;
+
+        let peppered = !params.keyid().is_empty();
+
+        // Feito com outro pepper: não tem como conferir.
+        if peppered && params.keyid() != self.params.keyid() {
+            return Err(password_hash::Error::Crypto);
+        }
+
+        // O argon2 usa o algoritmo, versão e custos guardados
+        // no hash; só o pepper vem do contexto.
+        let matches = match peppered {
+            true => self.argon2()?.verify_password(password.as_bytes(), &parsed),
+            false => Argon2::default().verify_password(password.as_bytes(), &parsed),
+        }
+        .is_ok();
+
+        if !matches {
+            return Ok(Verification::Mismatch);
+        }
+
+        let outdated = parsed.algorithm != Algorithm::Argon2id.ident()
+            || parsed.version != Some(Version::V0x13.into())
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+            || params.keyid() != self.params.keyid();
+
+        match outdated {
+            true => Ok(Verification::Outdated),
+            false => Ok(Verification::Match),
+        }
+    }
+}
@@ -1,13 +1,27 @@
 /*
 Estruturas e seus métodos lidar com usuários enquanto
-User e enquanto valor guardado no database mysql.
+User e enquanto valor guardado na database (mysql ou sqlite,
+veja /users/src/storage).
 */
 
+pub mod storage;
+pub mod migrations;
+pub mod tokens;
+pub mod throttle;
+pub mod policy;
+pub mod hashing;
+pub mod two_factor;
+pub mod roles;
+pub mod presence;
+pub mod feed;
+pub mod typing;
+
 use std::{
     collections::HashMap,
+    fmt::Write,
+    net::IpAddr,
     sync::Arc,
-    path::PathBuf,
-    env,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 use tokio::{
@@ -18,27 +32,178 @@ use axum::{
     extract::ws::Message,
 };
 
-use sqlx::{
-    mysql::MySqlPool,
-};
-
-use argon2::{
-    {Argon2, PasswordHasher, PasswordHash, PasswordVerifier},
-    password_hash::SaltString,
-};
-
 use rand::{
+    RngCore,
     rngs::OsRng,
 };
 
-use dotenvy::from_path;
+use sha2::{Digest, Sha256};
 
 use error::{
     AuthenticateErrorType,
+    MigrationError,
+};
+
+pub use storage::{
+    Storage,
+    PoolConfig,
+    MemoryStorage,
+    TwoFactorRecord,
+    BanRecord,
+    StoredMessage,
+    RoomSummary,
+    StoredRoomMessage,
+    StoredPost,
+    UsernameConflict,
+};
+
+pub use migrations::Migrator;
+
+pub use tokens::{
+    TokenSigner,
+    IssuedToken,
+};
+
+pub use throttle::{
+    LoginThrottle,
+    ThrottleConfig,
+};
+
+pub use policy::{
+    ValidationPolicy,
+    UsernameCharset,
+};
+
+pub use hashing::{
+    HashConfig,
+    PasswordHashing,
+    Verification,
+};
+
+pub use roles::Role;
+
+pub use presence::PresenceRegistry;
+
+pub use feed::FeedSubscribers;
+
+pub use typing::{
+    TypingConfig,
+    TypingIndicators,
+    Typing,
+};
+
+pub use two_factor::{
+    TwoFactorConfig,
+    LoginChallenges,
+    Totp,
 };
 
 type Tx = UnboundedSender<Message>;
 
+// Tamanho padrão e máximo de uma página do histórico.
+pub const DEFAULT_HISTORY_PAGE: u32 = 50;
+pub const MAX_HISTORY_PAGE: u32 = 100;
+
+// Máximo de ids confirmados por um único AckMessages ou
+// AckRoomMessages.
+pub const MAX_ACK_IDS: usize = 100;
+
+// Tamanho padrão e máximo de uma página do feed.
+pub const DEFAULT_FEED_PAGE: u32 = 20;
+pub const MAX_FEED_PAGE: u32 = 100;
+
+// Validade padrão dos tokens de sessão: 7 dias.
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+// Timestamp unix atual, em segundos.
+pub fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+// Bytes em hexadecimal minúsculo.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::with_capacity(bytes.len() * 2), |mut s, b| {
+        let _ = write!(s, "{b:02x}");
+        s
+    })
+}
+
+// Resultado de um login com senha.
+pub enum Login {
+    Authenticated(IssuedToken),
+    // A conta tem 2FA: o login só termina com
+    // verify_two_factor, respondendo ao desafio dado.
+    TwoFactorRequired { challenge: String, expires_at: i64 },
+}
+
+// Dados para configurar o autenticador do usuário.
+pub struct TwoFactorEnrollment {
+    // Segredo TOTP em base32.
+    pub secret: String,
+    pub uri: String,
+}
+
+// Página do histórico de uma conversa.
+pub struct HistoryPage {
+    // Da mais antiga para a mais nova.
+    pub messages: Vec<StoredMessage>,
+    // Se existem mensagens anteriores à primeira da página.
+    pub has_more: bool,
+}
+
+// Resultado de entrar numa sala.
+pub struct RoomJoin {
+    // Nome canônico da sala.
+    pub room: String,
+    // Membros, já incluindo quem entrou.
+    pub members: Vec<String>,
+    // false se o usuário já participava da sala.
+    pub joined: bool,
+}
+
+// Resultado de marcar uma conversa como lida.
+pub struct ReadReceipt {
+    // Username canônico do outro participante.
+    pub with: String,
+    pub up_to: i64,
+    pub read_at: i64,
+    // false se o cursor já estava em up_to ou adiante, e
+    // nada mudou.
+    pub advanced: bool,
+}
+
+// Conversa com mensagens ainda não lidas.
+pub struct UnreadConversation {
+    pub with: String,
+    pub count: i64,
+}
+
+// Página do feed.
+pub struct FeedPage {
+    // Do mais novo para o mais antigo.
+    pub posts: Vec<StoredPost>,
+    // Se existem posts anteriores ao último da página.
+    pub has_more: bool,
+}
+
+// Presença atual de um usuário.
+pub struct UserPresence {
+    pub username: String,
+    pub online: bool,
+    // Quando ficou offline pela última vez, se já se conectou.
+    pub last_seen: Option<i64>,
+}
+
+// Mudança de presença a ser avisada.
+pub struct PresenceChange {
+    pub presence: UserPresence,
+    // Sessões que acompanham a presença do usuário.
+    pub watchers: Vec<Tx>,
+}
+
 // Tipo de usuário para tornar o código idiomático
 #[derive(Eq, Hash, PartialEq, Clone)]
 pub struct User {
@@ -53,117 +218,308 @@ impl User {
     }
 }
 
-// Tipo que contêm uma coleção de usuários o sender
-// associado a suas conexões ao channel.
+// Tipo que contêm uma coleção de usuários e os senders
+// associados a suas conexões ao channel. Um usuário pode
+// estar logado em várias conexões ao mesmo tempo (uma
+// sessão por conexão) e cada sessão é identificada pelo
+// próprio sender, comparado com Tx::same_channel.
 // É responsável, além de conter todos os usuário conectados
 // ao client, ie, onlines, por adicionar um novo
-// usuário na database mysql, remover um usuario do HashMap,
-// hash de senhas para usuários, conectar na database etc.
+// usuário na database, remover um usuario do HashMap,
+// hash de senhas para usuários etc.
+// O backend de armazenamento (e seu pool de conexões) é criado
+// uma única vez, na inicialização do servidor, e compartilhado
+// por todos os clones de Users.
 #[derive(Clone)]
 pub struct Users {
-    pub on_users: Arc<Mutex<HashMap<User, Tx>>>,
+    pub on_users: Arc<Mutex<HashMap<User, Vec<Tx>>>>,
+    storage: Arc<dyn Storage>,
+    tokens: Arc<TokenSigner>,
+    throttle: Arc<LoginThrottle>,
+    policy: Arc<ValidationPolicy>,
+    hashing: Arc<PasswordHashing>,
+    two_factor: Arc<TwoFactorConfig>,
+    challenges: Arc<LoginChallenges>,
+    presence: Arc<PresenceRegistry>,
+    feed: Arc<FeedSubscribers>,
+    typing: Arc<TypingIndicators>,
 }
 
 impl Users {
-    pub fn new() -> Self {
+    // O segredo dos tokens de sessão é aleatório até que
+    // with_token_signer seja chamado.
+    pub fn new(storage: Arc<dyn Storage>) -> Self {
         Self {
             on_users: Arc::new(Mutex::new(HashMap::new())),
+            storage,
+            tokens: Arc::new(TokenSigner::random(DEFAULT_TOKEN_TTL)),
+            throttle: Arc::new(LoginThrottle::new(ThrottleConfig::default())),
+            policy: Arc::new(ValidationPolicy::default()),
+            hashing: Arc::new(PasswordHashing::new(HashConfig::default())
+                .expect("os parâmetros padrão do argon2 são válidos")),
+            two_factor: Arc::new(TwoFactorConfig::default()),
+            challenges: Arc::new(LoginChallenges::new(TwoFactorConfig::default().challenge_ttl)),
+            presence: Arc::new(PresenceRegistry::new()),
+            feed: Arc::new(FeedSubscribers::new()),
+            typing: Arc::new(TypingIndicators::new(TypingConfig::default())),
         }
     }
 
-    // Conecta na database registrada em .env
-    async fn connect_to_database() -> Result<MySqlPool, AuthenticateErrorType> {
-        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-        path.pop();
-        path.push(".env");
+    pub fn with_token_signer(mut self, signer: TokenSigner) -> Self {
+        self.tokens = Arc::new(signer);
+        self
+    }
+
+    pub fn with_login_throttle(mut self, config: ThrottleConfig) -> Self {
+        self.throttle = Arc::new(LoginThrottle::new(config));
+        self
+    }
+
+    pub fn with_validation_policy(mut self, policy: ValidationPolicy) -> Self {
+        self.policy = Arc::new(policy);
+        self
+    }
+
+    pub fn with_password_hashing(mut self, hashing: PasswordHashing) -> Self {
+        self.hashing = Arc::new(hashing);
+        self
+    }
+
+    pub fn with_two_factor(mut self, config: TwoFactorConfig) -> Self {
+        self.challenges = Arc::new(LoginChallenges::new(config.challenge_ttl));
+        self.two_factor = Arc::new(config);
+        self
+    }
 
-        from_path(path.as_path()).unwrap();
+    pub fn with_typing(mut self, config: TypingConfig) -> Self {
+        self.typing = Arc::new(TypingIndicators::new(config));
+        self
+    }
 
-        let db_url = env::var("DATABASE_URL").expect("DATABASE_URL não definida");
-        let pool = MySqlPool::connect(&db_url).await?;
+    // Forma canônica de um username vindo do client (veja
+    // /users/src/policy.rs). Todo username recebido precisa
+    // passar por aqui antes de ser procurado.
+    pub fn canonical_username(&self, username: &str) -> String {
+        self.policy.canonical_username(username)
+    }
 
-        Ok(pool)    
+    // Users sem nenhuma database por trás, usado
+    // em testes e no modo --demo do servidor.
+    pub fn in_memory() -> Self {
+        Self::new(Arc::new(MemoryStorage::new()))
     }
 
-    // Retorna o sender do channel associado ao usuário.
-    pub async fn get_user
+    // Cria o backend de armazenamento correspondente ao
+    // esquema de db_url (mysql://, sqlite:// ou memory://).
+    pub async fn connect_with
+    (
+        db_url: &str,
+        config: PoolConfig,
+    ) -> Result<Self, AuthenticateErrorType>
+    {
+        let storage = storage::connect(db_url, config).await?;
+        Ok(Self::new(storage))
+    }
+
+    // Migrator ligado ao backend atual, usado por /utils
+    // para aplicar, desfazer e listar migrations.
+    pub fn migrator(&self) -> Result<Migrator, MigrationError> {
+        self.storage.migrator().ok_or(MigrationError::Unsupported)
+    }
+
+    // Aplica as migrations pendentes no backend atual.
+    // Backends sem schema (memory://) não têm o que migrar.
+    pub async fn migrate(&self) -> Result<Vec<i64>, MigrationError> {
+        match self.storage.migrator() {
+            Some(migrator) => migrator.up(None).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // Contas antigas que as migrations não conseguiram
+    // passar para a forma canônica do username.
+    pub async fn username_conflicts(&self) -> Result<Vec<UsernameConflict>, AuthenticateErrorType> {
+        self.storage.username_conflicts().await
+    }
+
+    // Retorna os senders de todas as sessões do usuário,
+    // vazio se ele estiver offline.
+    pub async fn get_sessions
     (
         &self,
         user: User,
-    ) -> Option<Tx>
+    ) -> Vec<Tx>
     {
         let on_users = self.on_users.lock().await;
-        on_users.get(&user).cloned()
+        on_users.get(&user).cloned().unwrap_or_default()
+    }
+
+    // Senders de todas as sessões abertas dos usuários.
+    pub async fn get_sessions_of
+    (
+        &self,
+        usernames: &[String],
+    ) -> Vec<Tx>
+    {
+        let on_users = self.on_users.lock().await;
+
+        usernames
+            .iter()
+            .filter_map(|username| on_users.get(&User::new(username)))
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    // Adiciona uma sessão para o usuário, sem afetar as
+    // sessões que ele já tenha abertas em outras conexões.
+    // O banimento é conferido com on_users travado: como
+    // ban_user grava o banimento antes de remover as sessões
+    // (também com on_users travado), ou esta sessão vê o
+    // banimento, ou é removida por ban_user.
+    async fn add_session
+    (
+        &self,
+        username: &str,
+        sender: Tx,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        let mut on_users = self.on_users.lock().await;
+
+        self.check_ban(username).await?;
+
+        let sessions = on_users.entry(User::new(username)).or_default();
+
+        if !sessions.iter().any(|tx| tx.same_channel(&sender)) {
+            sessions.push(sender);
+        }
+
+        Ok(())
+    }
+
+    // Abre a sessão e só então emite o token, para que um
+    // banimento no meio do login não deixe um token novo
+    // para trás.
+    async fn open_session
+    (
+        &mut self,
+        username: &str,
+        sender: Tx,
+    ) -> Result<IssuedToken, AuthenticateErrorType>
+    {
+        self.add_session(username, sender.clone()).await?;
+
+        match self.issue_token(username).await {
+            Ok(token) => Ok(token),
+            Err(e) => {
+                self.remove_session(username, &sender).await;
+                Err(e)
+            }
+        }
     }
 
     // Cria um novo usuário, se não existir, na database.
+    // Username e senha precisam seguir a política de validação.
+    // Retorna o username na forma canônica, que é como ele
+    // fica guardado.
     pub async fn add_user
     (
+        &self,
         username: &str,
         password: &str,
-    ) -> Result<(), AuthenticateErrorType>
+    ) -> Result<String, AuthenticateErrorType>
     {
-        let pool = Self::connect_to_database().await?;
-        let password_hash = Self::hash_password(password)?;
-
-        match sqlx::query!(
-            "INSERT INTO users (username, password_hash) VALUES (?, ?)",
-            username,
-            password_hash,
-        )
-        .fetch_optional(&pool)
-        .await {
-            Ok(_) => {},
-            Err(_) => return Err(AuthenticateErrorType::UserAlreadyExists),
-        };
+        let username = self.canonical_username(username);
 
-        Ok(())
+        self.policy.validate_username(&username)?;
+        self.policy.validate_password(&username, password)?;
+
+        let password_hash = self.hash_password(password)?;
+        self.storage.insert_user(&username, &password_hash).await?;
+
+        Ok(username)
     }
 
-    // Remove um usuário de on_users.
+    // Remove um usuário, com todas as suas sessões,
+    // de on_users.
     pub async fn remove_user
     (
         &mut self,
         username: &str,
-    ) -> Option<Tx>
+    ) -> Vec<Tx>
     {
         let mut on_users = self.on_users.lock().await;
-        on_users.remove(&User::new(username))        
+        on_users.remove(&User::new(username)).unwrap_or_default()
+    }
+
+    // Remove todas as sessões do usuário menos a de keep,
+    // retornando os senders removidos.
+    pub async fn remove_other_sessions
+    (
+        &mut self,
+        username: &str,
+        keep: &Tx,
+    ) -> Vec<Tx>
+    {
+        let mut on_users = self.on_users.lock().await;
+
+        let Some(sessions) = on_users.get_mut(&User::new(username)) else {
+            return Vec::new();
+        };
+
+        let (kept, removed) = sessions
+            .drain(..)
+            .partition(|tx| tx.same_channel(keep));
+
+        *sessions = kept;
+
+        if sessions.is_empty() {
+            on_users.remove(&User::new(username));
+        }
+
+        removed
+    }
+
+    // Remove apenas a sessão de sender. O usuário só sai de
+    // on_users quando a última sessão dele é removida.
+    pub async fn remove_session
+    (
+        &mut self,
+        username: &str,
+        sender: &Tx,
+    )
+    {
+        let mut on_users = self.on_users.lock().await;
+        let user = User::new(username);
+
+        if let Some(sessions) = on_users.get_mut(&user) {
+            sessions.retain(|tx| !tx.same_channel(sender));
+
+            if sessions.is_empty() {
+                on_users.remove(&user);
+            }
+        }
     }
 
     pub async fn user_exists
     (
+        &self,
         username: &str
     ) -> Result<bool, AuthenticateErrorType>
     {
-        let pool = Self::connect_to_database().await?;
-
-        let result = sqlx::query_scalar!(
-            r#"
-            SELECT EXISTS(
-                SELECT 1 FROM users WHERE username = ?
-            ) AS "exists!"
-            "#,
-            username,            
-        )
-        .fetch_one(&pool)
-        .await?;
-
-        Ok(result == 1)
+        self.storage.user_exists(username).await
     }
 
     // Faz o hash da senha passada pelo usuário
-    // na hora da criação da conta.
+    // na hora da criação da conta (ou de uma troca de senha),
+    // com os parâmetros atuais.
     fn hash_password
     (
+        &self,
         password: &str,
     ) -> Result<String, argon2::password_hash::Error>
     {
-        let salt = SaltString::generate(&mut OsRng);
-        let argon2 = Argon2::default();
-
-        let password_hash = argon2.hash_password(password.as_bytes(), &salt)?;
-        Ok(password_hash.to_string())
+        self.hashing.hash(password)
     }
 
     // Verifica se, ao tentar logar, a senha
@@ -171,120 +527,1001 @@ impl Users {
     // no database.
     fn check_password
     (
+        &self,
         hash_found: &str,
         password_given: &str,
-    ) -> Result<bool, argon2::password_hash::Error>
+    ) -> Result<Verification, argon2::password_hash::Error>
     {
-        let parsed_hash = PasswordHash::new(hash_found)?;
-        Ok(Argon2::default().verify_password(password_given.as_bytes(),
-            &parsed_hash)
-            .is_ok()
-        )
+        self.hashing.verify(hash_found, password_given)
     }
 
+    // Confere a senha do usuário. Falhas são contadas por
+    // conta e pelo ip de origem, e passado o limite a senha
+    // nem é conferida: o pedido é recusado com TooManyAttempts.
+    // O contador da conta não é zerado aqui, e sim por quem
+    // chama, quando a operação toda (inclusive um eventual
+    // segundo fator) tiver dado certo.
+    async fn verify_password
+    (
+        &self,
+        username: &str,
+        password: &str,
+        ip: IpAddr,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        self.throttle
+            .check(username, ip, unix_now())
+            .map_err(|retry_after| AuthenticateErrorType::TooManyAttempts { retry_after })?;
+
+        let verification = match self.storage.password_hash(username).await? {
+            Some(hash_found) => self.check_password(&hash_found, password)?,
+            None => {
+                self.throttle.failure(username, ip, unix_now());
+                return Err(AuthenticateErrorType::UserNotFound);
+            },
+        };
+
+        if verification == Verification::Mismatch {
+            self.throttle.failure(username, ip, unix_now());
+            return Err(AuthenticateErrorType::PasswordMismatch);
+        }
+
+        // Hash feito com parâmetros antigos: aproveita que a
+        // senha em claro está disponível para refazê-lo. Uma
+        // falha aqui não impede o login, o hash antigo continua
+        // valendo e a troca é tentada de novo no próximo.
+        if verification == Verification::Outdated
+            && let Ok(password_hash) = self.hash_password(password) {
+            let _ = self.storage.update_password(username, &password_hash).await;
+        }
+
+        Ok(())
+    }
 
     // Função responsável por autenticar/autorizar a entrada
-    // do usuário na rede.
+    // do usuário na rede. Retorna um token de sessão que
+    // pode ser usado para reconectar sem a senha ou, se a
+    // conta tiver 2FA, um desafio a ser respondido com
+    // verify_two_factor.
     pub async fn authenticate_user
     (
         &mut self,
         username: &str,
         password: &str,
+        ip: IpAddr,
         sender: Tx
+    ) -> Result<Login, AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+        self.verify_password(username, password, ip).await?;
+
+        self.check_ban(username).await?;
+
+        if let Some(record) = self.storage.get_two_factor(username).await?
+            && record.enabled {
+            let (challenge, expires_at) = self.challenges.issue(username, unix_now());
+            return Ok(Login::TwoFactorRequired { challenge, expires_at });
+        }
+
+        self.throttle.success(username);
+
+        let token = self.open_session(username, sender).await?;
+
+        Ok(Login::Authenticated(token))
+    }
+
+    // Segunda etapa do login de uma conta com 2FA. code é
+    // um código TOTP ou de recuperação; códigos errados
+    // contam como falhas de login.
+    pub async fn verify_two_factor
+    (
+        &mut self,
+        challenge: &str,
+        code: &str,
+        ip: IpAddr,
+        sender: Tx,
+    ) -> Result<IssuedToken, AuthenticateErrorType>
+    {
+        let username = &self.challenges
+            .owner(challenge, unix_now())
+            .ok_or(AuthenticateErrorType::InvalidChallenge)?;
+
+        // O 2FA pode ter sido desativado enquanto isso.
+        let record = self.storage
+            .get_two_factor(username)
+            .await?
+            .filter(|r| r.enabled)
+            .ok_or(AuthenticateErrorType::InvalidChallenge)?;
+
+        self.verify_second_factor(username, &record, code, ip).await?;
+
+        self.challenges.remove(challenge);
+        self.throttle.success(username);
+
+        self.open_session(username, sender).await
+    }
+
+    // Confere um código TOTP ou de recuperação, que deixa
+    // de valer depois de aceito.
+    async fn verify_second_factor
+    (
+        &self,
+        username: &str,
+        record: &TwoFactorRecord,
+        code: &str,
+        ip: IpAddr,
     ) -> Result<(), AuthenticateErrorType>
     {
-        let pool = Self::connect_to_database().await?;
-
-        if let Some(row) = sqlx::query!(
-            "SELECT id, username, password_hash FROM users WHERE username = ?",
-            username,
-        )
-        .fetch_optional(&pool)
-        .await? {
-            let hash_found = row.password_hash;
-            let valid_user = Self::check_password(&hash_found, password)?;
-
-            match valid_user {
-                true => {
-                    let mut on_users = self.on_users.lock().await;
-                    on_users.insert(User::new(username), sender);
-                    Ok(())
-                },
-                false => Err(AuthenticateErrorType::PasswordMismatch),
-            }        
-        } else {
-            Err(AuthenticateErrorType::UserNotFound)
+        let now = unix_now();
+
+        self.throttle
+            .check(username, ip, now)
+            .map_err(|retry_after| AuthenticateErrorType::TooManyAttempts { retry_after })?;
+
+        let valid = match two_factor::is_totp_code(code) {
+            true => match Totp::from_base32(&record.secret)
+                .and_then(|totp| totp.matching_step(code, now)) {
+                Some(step) => self.storage.use_two_factor_step(username, step).await?,
+                None => false,
+            },
+            false => {
+                let code_hash = two_factor::hash_recovery_code(code);
+                self.storage.use_recovery_code(username, &code_hash).await?
+            },
+        };
+
+        if !valid {
+            self.throttle.failure(username, ip, now);
+            return Err(AuthenticateErrorType::InvalidTwoFactorCode);
         }
+
+        Ok(())
     }
 
-    pub async fn store_message
+    // Começa a ativação do 2FA gerando um novo segredo, que
+    // só passa a ser exigido no login depois de confirmado
+    // com confirm_two_factor.
+    pub async fn begin_two_factor
     (
-        sender: &str,
-        receiver: &str,
-        message: &str,
+        &self,
+        username: &str,
+    ) -> Result<TwoFactorEnrollment, AuthenticateErrorType>
+    {
+        if self.storage.get_two_factor(username).await?.is_some_and(|r| r.enabled) {
+            return Err(AuthenticateErrorType::TwoFactorAlreadyEnabled);
+        }
+
+        let totp = Totp::generate();
+        let secret = totp.secret_base32();
+
+        self.storage.set_two_factor_secret(username, &secret).await?;
+
+        Ok(TwoFactorEnrollment {
+            uri: totp.uri(&self.two_factor.issuer, username),
+            secret,
+        })
+    }
+
+    // Ativa o 2FA se code for um código válido para o
+    // segredo gerado em begin_two_factor. Retorna os
+    // códigos de recuperação, que só existem em claro aqui.
+    pub async fn confirm_two_factor
+    (
+        &self,
+        username: &str,
+        code: &str,
+        ip: IpAddr,
+    ) -> Result<Vec<String>, AuthenticateErrorType>
+    {
+        let record = match self.storage.get_two_factor(username).await? {
+            Some(record) if record.enabled =>
+                return Err(AuthenticateErrorType::TwoFactorAlreadyEnabled),
+            Some(record) => record,
+            None => return Err(AuthenticateErrorType::TwoFactorNotEnabled),
+        };
+
+        // Ainda não há códigos de recuperação, então só um
+        // código TOTP pode confirmar.
+        if !two_factor::is_totp_code(code) {
+            return Err(AuthenticateErrorType::InvalidTwoFactorCode);
+        }
+
+        self.verify_second_factor(username, &record, code, ip).await?;
+
+        let codes: Vec<String> = (0..self.two_factor.recovery_codes)
+            .map(|_| two_factor::generate_recovery_code())
+            .collect();
+
+        let hashes: Vec<String> = codes
+            .iter()
+            .map(|c| two_factor::hash_recovery_code(c))
+            .collect();
+
+        self.storage.enable_two_factor(username, &hashes).await?;
+
+        Ok(codes)
+    }
+
+    // Desativa o 2FA, o que exige um código TOTP ou de
+    // recuperação válido.
+    pub async fn disable_two_factor
+    (
+        &self,
+        username: &str,
+        code: &str,
+        ip: IpAddr,
     ) -> Result<(), AuthenticateErrorType>
     {
-        let pool = Self::connect_to_database().await?;
-
-        match sqlx::query!(
-            r#"
-            INSERT INTO offline_messages (sender, receiver, message)
-            VALUES (?, ?, ?)
-            "#,
-            sender,
-            receiver,
-            message,
-        )
-        .fetch_optional(&pool)
-        .await {
-            Ok(_) => Ok(()),
-            Err(_) => Err(AuthenticateErrorType::OfflineMessageError)
+        let record = self.storage
+            .get_two_factor(username)
+            .await?
+            .filter(|r| r.enabled)
+            .ok_or(AuthenticateErrorType::TwoFactorNotEnabled)?;
+
+        self.verify_second_factor(username, &record, code, ip).await?;
+        self.storage.delete_two_factor(username).await
+    }
+
+    // Troca a senha de um usuário, que precisa confirmar a
+    // senha atual. Todos os tokens dele são revogados e um
+    // novo é emitido para a conexão que fez o pedido.
+    pub async fn change_password
+    (
+        &self,
+        username: &str,
+        current_password: &str,
+        new_password: &str,
+        ip: IpAddr,
+    ) -> Result<IssuedToken, AuthenticateErrorType>
+    {
+        self.verify_password(username, current_password, ip).await?;
+        self.throttle.success(username);
+        self.policy.validate_password(username, new_password)?;
+
+        let password_hash = self.hash_password(new_password)?;
+        self.storage.update_password(username, &password_hash).await?;
+        self.storage.revoke_user_sessions(username).await?;
+
+        self.issue_token(username).await
+    }
+
+    // Gera um código de uso único, válido por ttl, para o
+    // usuário redefinir a senha sem saber a atual. Usado
+    // por administradores (veja /utils); o código em claro
+    // só existe no retorno desta função.
+    pub async fn issue_reset_code
+    (
+        &self,
+        username: &str,
+        ttl: Duration,
+    ) -> Result<String, AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+
+        if !self.storage.user_exists(username).await? {
+            return Err(AuthenticateErrorType::UserNotFound);
         }
+
+        let mut bytes = [0u8; 8];
+        OsRng.fill_bytes(&mut bytes);
+        let code = hex(&bytes);
+
+        let expires_at = unix_now() + ttl.as_secs() as i64;
+        self.storage
+            .insert_reset_code(username, &Self::hash_reset_code(&code), expires_at)
+            .await?;
+
+        Ok(code)
+    }
+
+    fn hash_reset_code(code: &str) -> String {
+        hex(&Sha256::digest(code.as_bytes()))
+    }
+
+    // Redefine a senha usando um código gerado por
+    // issue_reset_code. O código deixa de valer depois de
+    // usado, e tentativas erradas contam como falhas de login.
+    // Todas as sessões abertas do usuário são removidas e
+    // seus senders retornados, para que sejam encerradas.
+    pub async fn reset_password
+    (
+        &mut self,
+        username: &str,
+        code: &str,
+        new_password: &str,
+        ip: IpAddr,
+    ) -> Result<Vec<Tx>, AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+        let now = unix_now();
+
+        self.throttle
+            .check(username, ip, now)
+            .map_err(|retry_after| AuthenticateErrorType::TooManyAttempts { retry_after })?;
+
+        let valid = match self.storage.get_reset_code(username).await? {
+            Some((code_hash, expires_at)) =>
+                expires_at > now && code_hash == Self::hash_reset_code(code),
+            None => false,
+        };
+
+        if !valid {
+            self.throttle.failure(username, ip, now);
+            return Err(AuthenticateErrorType::InvalidResetCode);
+        }
+
+        self.policy.validate_password(username, new_password)?;
+
+        let password_hash = self.hash_password(new_password)?;
+
+        self.storage.delete_reset_code(username).await?;
+        self.storage.update_password(username, &password_hash).await?;
+        self.storage.revoke_user_sessions(username).await?;
+        self.throttle.success(username);
+
+        Ok(self.remove_user(username).await)
+    }
+
+    // Apaga a conta do usuário, que precisa confirmar a
+    // senha. As mensagens guardadas e os tokens somem junto
+    // com ela. Retorna os senders das sessões que estavam
+    // abertas, para que elas possam ser encerradas.
+    pub async fn delete_account
+    (
+        &mut self,
+        username: &str,
+        password: &str,
+        ip: IpAddr,
+    ) -> Result<Vec<Tx>, AuthenticateErrorType>
+    {
+        self.verify_password(username, password, ip).await?;
+        self.throttle.success(username);
+        self.storage.delete_user(username).await?;
+
+        Ok(self.remove_user(username).await)
+    }
+
+    // Autentica o usuário a partir de um token emitido
+    // anteriormente. O token precisa ter sido assinado por
+    // este servidor, não pode estar expirado e a sessão
+    // correspondente não pode ter sido revogada.
+    // Retorna o username dono do token.
+    pub async fn authenticate_with_token
+    (
+        &mut self,
+        token: &str,
+        sender: Tx,
+    ) -> Result<String, AuthenticateErrorType>
+    {
+        let parsed = TokenSigner::parse(token)
+            .ok_or(AuthenticateErrorType::InvalidToken)?;
+
+        let session = self.storage
+            .get_session(parsed.id)
+            .await?
+            .ok_or(AuthenticateErrorType::InvalidToken)?;
+
+        if session.revoked
+            || session.expires_at != parsed.expires_at
+            || !self.tokens.verify(&parsed, &session.username) {
+            return Err(AuthenticateErrorType::InvalidToken);
+        }
+
+        if session.expires_at <= unix_now() {
+            return Err(AuthenticateErrorType::TokenExpired);
+        }
+
+        self.add_session(&session.username, sender).await?;
+
+        Ok(session.username)
+    }
+
+    // Emite um token e guarda a sessão correspondente.
+    async fn issue_token
+    (
+        &self,
+        username: &str,
+    ) -> Result<IssuedToken, AuthenticateErrorType>
+    {
+        let token = self.tokens.issue(username, unix_now());
+
+        self.storage
+            .insert_session(&token.id, username, token.expires_at)
+            .await?;
+
+        Ok(token)
+    }
+
+    // Revoga um token do próprio usuário. Tokens de outros
+    // usuários são tratados como inválidos.
+    pub async fn revoke_token
+    (
+        &self,
+        username: &str,
+        token: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        let parsed = TokenSigner::parse(token)
+            .ok_or(AuthenticateErrorType::InvalidToken)?;
+
+        if !self.tokens.verify(&parsed, username) {
+            return Err(AuthenticateErrorType::InvalidToken);
+        }
+
+        self.storage.revoke_session(parsed.id).await
+    }
+
+    // Papel atual do usuário, lido da database a cada
+    // chamada para que mudanças valham na hora.
+    pub async fn role
+    (
+        &self,
+        username: &str,
+    ) -> Result<Role, AuthenticateErrorType>
+    {
+        self.storage
+            .get_role(username)
+            .await?
+            .ok_or(AuthenticateErrorType::UserNotFound)
+    }
+
+    pub async fn set_role
+    (
+        &self,
+        username: &str,
+        role: Role,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+        self.storage.set_role(username, role).await
+    }
+
+    // (username, número de sessões) de quem está online,
+    // em ordem alfabética.
+    pub async fn online_users(&self) -> Vec<(String, usize)> {
+        let on_users = self.on_users.lock().await;
+
+        let mut online: Vec<(String, usize)> = on_users
+            .iter()
+            .map(|(user, sessions)| (user.username.clone(), sessions.len()))
+            .collect();
+
+        online.sort();
+        online
+    }
+
+    // Derruba todas as sessões do usuário e revoga os tokens
+    // dele, para que ele precise da senha para voltar.
+    // Retorna os senders das sessões removidas.
+    pub async fn kick_user
+    (
+        &mut self,
+        username: &str,
+    ) -> Result<Vec<Tx>, AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+
+        if !self.storage.user_exists(username).await? {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        self.storage.revoke_user_sessions(username).await?;
+
+        Ok(self.remove_user(username).await)
+    }
+
+    // Banimento em vigor do usuário, se houver. Suspensões
+    // que já acabaram são ignoradas.
+    pub async fn active_ban
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<BanRecord>, AuthenticateErrorType>
+    {
+        let ban = self.storage.get_ban(username).await?;
+        let now = unix_now();
+
+        Ok(ban.filter(|b| b.until.is_none_or(|until| until > now)))
+    }
+
+    // AccountBanned, com motivo e fim, se o usuário estiver
+    // banido.
+    async fn check_ban
+    (
+        &self,
+        username: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        match self.active_ban(username).await? {
+            Some(ban) => Err(AuthenticateErrorType::AccountBanned {
+                reason: ban.reason,
+                until: ban.until,
+            }),
+            None => Ok(()),
+        }
+    }
+
+    // Bane o usuário até until (timestamp unix), ou para
+    // sempre se until for None: ele não consegue mais
+    // autenticar, deixa de receber mensagens e as sessões
+    // abertas são removidas (e retornadas).
+    pub async fn ban_user
+    (
+        &mut self,
+        username: &str,
+        reason: &str,
+        until: Option<i64>,
+    ) -> Result<Vec<Tx>, AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+
+        if until.is_some_and(|until| until <= unix_now()) {
+            return Err(AuthenticateErrorType::InvalidBanExpiry);
+        }
+
+        let ban = BanRecord {
+            reason: reason.to_string(),
+            until,
+        };
+
+        self.storage.set_ban(username, Some(&ban)).await?;
+        self.storage.revoke_user_sessions(username).await?;
+
+        Ok(self.remove_user(username).await)
+    }
+
+    pub async fn unban_user
+    (
+        &self,
+        username: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        let username = &self.canonical_username(username);
+        self.storage.set_ban(username, None).await
+    }
+
+    // Deixa a mensagem, já registrada com record_message,
+    // pendente até o destinatário confirmar o recebimento.
+    // Pendentes são reenviadas a cada login dele.
+    pub async fn store_message
+    (
+        &self,
+        message: &StoredMessage,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        self.storage.store_message(message.id, &message.receiver).await
     }
 
     pub async fn get_stored_messages
     (
+        &self,
+        receiver: &str,
+    ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+    {
+        self.storage.get_stored_messages(receiver).await
+    }
+
+    // Confirma o recebimento das mensagens ids por receiver,
+    // que deixam de ser reenviadas. Retorna as que estavam
+    // pendentes para ele; ids desconhecidos, de outro
+    // destinatário ou já confirmados são ignorados.
+    pub async fn ack_messages
+    (
+        &self,
         receiver: &str,
-    ) -> Result<Vec<(String, String)>, AuthenticateErrorType>
-    {
-        let pool = Self::connect_to_database().await?;
-
-        let rows = sqlx::query!(
-            r#"
-            SELECT id, sender, message FROM offline_messages
-            WHERE receiver = ?
-            ORDER BY sent_at ASC
-            "#,
-            receiver
-        )
-        .fetch_all(&pool)
-        .await?;
-
-        let result = rows
+        ids: &[i64],
+    ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+    {
+        self.storage.delete_stored_messages(ids, receiver).await
+    }
+
+    // Guarda a mensagem no histórico, com o horário atual.
+    pub async fn record_message
+    (
+        &self,
+        sender: &str,
+        receiver: &str,
+        text: &str,
+    ) -> Result<StoredMessage, AuthenticateErrorType>
+    {
+        let sent_at = unix_now();
+        let id = self.storage.insert_message(sender, receiver, text, sent_at).await?;
+
+        Ok(StoredMessage {
+            id,
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            body: text.to_string(),
+            sent_at,
+        })
+    }
+
+    // Mensagens trocadas entre username e with, paginadas da
+    // mais nova para a mais antiga: a primeira página traz as
+    // últimas limit mensagens e as seguintes são pedidas com
+    // before = id da mais antiga já recebida.
+    pub async fn history
+    (
+        &self,
+        username: &str,
+        with: &str,
+        before: Option<i64>,
+        limit: Option<u32>,
+    ) -> Result<HistoryPage, AuthenticateErrorType>
+    {
+        let with = &self.canonical_username(with);
+
+        if !self.storage.user_exists(with).await? {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        let limit = limit
+            .unwrap_or(DEFAULT_HISTORY_PAGE)
+            .clamp(1, MAX_HISTORY_PAGE);
+
+        // Uma a mais, só para saber se há outra página.
+        let mut messages = self.storage
+            .get_conversation(username, with, before, limit + 1)
+            .await?;
+
+        let has_more = messages.len() > limit as usize;
+        messages.truncate(limit as usize);
+        messages.reverse();
+
+        Ok(HistoryPage {
+            messages,
+            has_more,
+        })
+    }
+
+    // Marca como lidas, para reader, as mensagens da conversa
+    // com with até up_to (o id de uma mensagem dela, em
+    // qualquer sentido).
+    pub async fn mark_read
+    (
+        &self,
+        reader: &str,
+        with: &str,
+        up_to: i64,
+    ) -> Result<ReadReceipt, AuthenticateErrorType>
+    {
+        let with = self.canonical_username(with);
+
+        if !self.storage.user_exists(&with).await? {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        let in_conversation = |m: &StoredMessage| {
+            (m.sender == reader && m.receiver == with) || (m.sender == with && m.receiver == reader)
+        };
+
+        match self.storage.get_message(up_to).await? {
+            Some(message) if in_conversation(&message) => {},
+            _ => return Err(AuthenticateErrorType::MessageNotFound),
+        }
+
+        let read_at = unix_now();
+        let advanced = self.storage
+            .advance_read_cursor(reader, &with, up_to, read_at)
+            .await?;
+
+        Ok(ReadReceipt {
+            with,
+            up_to,
+            read_at,
+            advanced,
+        })
+    }
+
+    // Mensagens recebidas e ainda não lidas, por conversa.
+    pub async fn unread_counts
+    (
+        &self,
+        username: &str,
+    ) -> Result<Vec<UnreadConversation>, AuthenticateErrorType>
+    {
+        let counts = self.storage.get_unread_counts(username).await?;
+
+        Ok(counts
             .into_iter()
-            .map(|row| (row.sender, row.message))
-            .collect::<Vec<_>>();
+            .map(|(with, count)| UnreadConversation { with, count })
+            .collect())
+    }
+
+    pub fn canonical_room_name(&self, name: &str) -> String {
+        self.policy.canonical_room_name(name)
+    }
+
+    // Cria a sala, com creator como primeiro membro.
+    // Retorna o nome canônico dela.
+    pub async fn create_room
+    (
+        &self,
+        creator: &str,
+        name: &str,
+    ) -> Result<String, AuthenticateErrorType>
+    {
+        let name = self.canonical_room_name(name);
+        self.policy.validate_room_name(&name)?;
+
+        self.storage.insert_room(&name, creator, unix_now()).await?;
+
+        Ok(name)
+    }
+
+    pub async fn join_room
+    (
+        &self,
+        username: &str,
+        name: &str,
+    ) -> Result<RoomJoin, AuthenticateErrorType>
+    {
+        let name = self.canonical_room_name(name);
+
+        let room = self.storage
+            .get_room(&name)
+            .await?
+            .ok_or(AuthenticateErrorType::RoomNotFound)?;
+
+        let joined = self.storage.insert_room_member(room.id, username, unix_now()).await?;
+        let members = self.storage.get_room_members(room.id).await?;
+
+        Ok(RoomJoin {
+            room: room.name,
+            members,
+            joined,
+        })
+    }
+
+    // Tira username da sala; as mensagens dela que ainda
+    // não tinham sido entregues a ele são descartadas.
+    // Retorna os membros que ficaram.
+    pub async fn leave_room
+    (
+        &self,
+        username: &str,
+        name: &str,
+    ) -> Result<(String, Vec<String>), AuthenticateErrorType>
+    {
+        let name = self.canonical_room_name(name);
+
+        let room = self.storage
+            .get_room(&name)
+            .await?
+            .ok_or(AuthenticateErrorType::RoomNotFound)?;
+
+        if !self.storage.delete_room_member(room.id, username).await? {
+            return Err(AuthenticateErrorType::NotRoomMember);
+        }
+
+        let members = self.storage.get_room_members(room.id).await?;
+
+        Ok((room.name, members))
+    }
+
+    pub async fn list_rooms
+    (
+        &self,
+        username: &str,
+    ) -> Result<Vec<RoomSummary>, AuthenticateErrorType>
+    {
+        self.storage.list_rooms(username).await
+    }
+
+    // Registra a mensagem de sender na sala, pendente para
+    // os outros membros não banidos até cada um confirmar o
+    // recebimento. Retorna a mensagem e esses membros.
+    pub async fn send_room_message
+    (
+        &self,
+        sender: &str,
+        name: &str,
+        text: &str,
+    ) -> Result<(StoredRoomMessage, Vec<String>), AuthenticateErrorType>
+    {
+        let name = self.canonical_room_name(name);
+
+        let room = self.storage
+            .get_room(&name)
+            .await?
+            .ok_or(AuthenticateErrorType::RoomNotFound)?;
+
+        let members = self.storage.get_room_members(room.id).await?;
 
-        Ok(result)
+        if !members.iter().any(|m| m == sender) {
+            return Err(AuthenticateErrorType::NotRoomMember);
+        }
+
+        // Membros banidos continuam na sala, mas não recebem
+        // nem acumulam mensagens dela.
+        let sent_at = unix_now();
+        let (id, recipients) = self.storage.insert_room_message(room.id, sender, text, sent_at).await?;
+
+        let message = StoredRoomMessage {
+            id,
+            room: room.name,
+            sender: sender.to_string(),
+            body: text.to_string(),
+            sent_at,
+        };
+
+        Ok((message, recipients))
+    }
+
+    pub async fn get_stored_room_messages
+    (
+        &self,
+        receiver: &str,
+    ) -> Result<Vec<StoredRoomMessage>, AuthenticateErrorType>
+    {
+        self.storage.get_stored_room_messages(receiver).await
     }
 
-    pub async fn delete_stored_messages
+    // Como ack_messages, para mensagens de sala.
+    pub async fn ack_room_messages
     (
+        &self,
         receiver: &str,
+        ids: &[i64],
     ) -> Result<(), AuthenticateErrorType>
     {
-        let pool = Self::connect_to_database().await?;
+        self.storage.delete_stored_room_messages(ids, receiver).await?;
+
+        Ok(())
+    }
+
+    // Presença atual de cada usuário em usernames, passando
+    // a avisar watcher das mudanças deles. Usernames que não
+    // existem são ignorados.
+    pub async fn watch_presence
+    (
+        &self,
+        watcher: &Tx,
+        usernames: &[String],
+    ) -> Result<Vec<UserPresence>, AuthenticateErrorType>
+    {
+        let mut presences = Vec::new();
+
+        for username in usernames {
+            let username = self.canonical_username(username);
+
+            if presences.iter().any(|p: &UserPresence| p.username == username)
+                || !self.storage.user_exists(&username).await?
+            {
+                continue;
+            }
+
+            presences.push(UserPresence {
+                online: self.on_users.lock().await.contains_key(&User::new(&username)),
+                last_seen: self.storage.get_last_seen(&username).await?,
+                username,
+            });
+        }
+
+        let watched: Vec<String> = presences.iter().map(|p| p.username.clone()).collect();
+        self.presence.watch(watcher, &watched);
 
-        sqlx::query!(
-            r#"
-            DELETE FROM offline_messages
-            WHERE receiver = ?
-            "#,
-            receiver
-        )
-        .execute(&pool)
-        .await?;
+        Ok(presences)
+    }
 
-        Ok(())  
+    // Para de avisar watcher das mudanças de presença, ao
+    // fechar a conexão ou fazer logout.
+    pub fn unwatch_presence(&self, watcher: &Tx) {
+        self.presence.unwatch(watcher);
     }
-}
\ No newline at end of file
+
+    // Confere se username ficou online ou offline desde o
+    // último aviso, retornando a mudança e quem precisa
+    // saber dela. Deve ser chamado sempre que uma sessão é
+    // aberta ou fechada. Ao ficar offline o last_seen dele
+    // é guardado.
+    pub async fn update_presence
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<PresenceChange>, AuthenticateErrorType>
+    {
+        // A transição acontece ainda com on_users travado:
+        // senão uma sessão aberta ou fechada no meio poderia
+        // ser anunciada antes, e este aviso, já velho, a
+        // desfaria.
+        let on_users = self.on_users.lock().await;
+        let online = on_users.contains_key(&User::new(username));
+        let transition = self.presence.transition(username, online);
+        drop(on_users);
+
+        let Some(watchers) = transition else {
+            return Ok(None);
+        };
+
+        let last_seen = match online {
+            true => self.storage.get_last_seen(username).await?,
+            false => {
+                let now = unix_now();
+                self.storage.set_last_seen(username, now).await?;
+                Some(now)
+            }
+        };
+
+        Ok(Some(PresenceChange {
+            presence: UserPresence {
+                username: username.to_string(),
+                online,
+                last_seen,
+            },
+            watchers,
+        }))
+    }
+
+    // Publica um post de author no feed, com o horário atual.
+    pub async fn create_post
+    (
+        &self,
+        author: &str,
+        text: &str,
+    ) -> Result<StoredPost, AuthenticateErrorType>
+    {
+        self.policy.validate_post(text)?;
+
+        let created_at = unix_now();
+        let id = self.storage.insert_post(author, text, created_at).await?;
+
+        Ok(StoredPost {
+            id,
+            author: author.to_string(),
+            body: text.to_string(),
+            created_at,
+        })
+    }
+
+    // Posts de todos os usuários, do mais novo para o mais
+    // antigo. Como no histórico, as páginas seguintes são
+    // pedidas com before = id do mais antigo já recebido.
+    pub async fn feed
+    (
+        &self,
+        before: Option<i64>,
+        limit: Option<u32>,
+    ) -> Result<FeedPage, AuthenticateErrorType>
+    {
+        let limit = limit
+            .unwrap_or(DEFAULT_FEED_PAGE)
+            .clamp(1, MAX_FEED_PAGE);
+
+        // Um a mais, só para saber se há outra página.
+        let mut posts = self.storage.get_posts(before, limit + 1).await?;
+
+        let has_more = posts.len() > limit as usize;
+        posts.truncate(limit as usize);
+
+        Ok(FeedPage {
+            posts,
+            has_more,
+        })
+    }
+
+    pub fn subscribe_feed(&self, session: &Tx) {
+        self.feed.subscribe(session);
+    }
+
+    pub fn unsubscribe_feed(&self, session: &Tx) {
+        self.feed.unsubscribe(session);
+    }
+
+    // Sessões que recebem os posts novos na hora.
+    pub fn feed_subscribers(&self) -> Vec<Tx> {
+        self.feed.sessions()
+    }
+
+    // Registra que from está digitando para to (veja
+    // /users/src/typing.rs).
+    pub fn typing(&self, from: &str, to: &str) -> Typing {
+        self.typing.touch(from, to, Instant::now())
+    }
+
+    // Quando o indicador de from para to expira, ou None se
+    // ele já expirou (e foi descartado).
+    pub fn typing_deadline(&self, from: &str, to: &str) -> Option<Instant> {
+        self.typing.deadline(from, to, Instant::now())
+    }
+}
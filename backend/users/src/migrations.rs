@@ -0,0 +1,495 @@
+/*
+Migrations versionadas da database. Cada migration é
+um par de arquivos em /users/migrations/<dialeto>/:
+
+NNNN_nome.up.sql    -> aplica a mudança
+NNNN_nome.down.sql  -> desfaz a mudança
+
+Os arquivos são embutidos no binário e precisam ser
+registrados, em ordem, nas listas MYSQL e SQLITE abaixo.
+As migrations aplicadas ficam registradas na tabela
+schema_migrations junto do checksum (sha256) do .up.sql,
+então alterar uma migration já aplicada é detectado em
+vez de silenciosamente ignorado.
+
+No sqlite cada migration roda numa transação. No MySQL
+isso não é possível: todo DDL (CREATE, ALTER, DROP...)
+faz commit implícito, então uma migration que falha no
+meio deixa aplicado o que veio antes. Lá os statements
+rodam um a um e o progresso fica em
+schema_migrations_progress, para que a próxima tentativa
+continue de onde a anterior parou (veja apply_by_statement).
+O down não tem esse cuidado: se falhar no MySQL, o que
+sobrou precisa ser desfeito à mão.
+*/
+
+use async_trait::async_trait;
+
+use sha2::{Digest, Sha256};
+
+use sqlx::{
+    mysql::MySqlPool,
+    sqlite::SqlitePool,
+    Connection,
+    Executor,
+};
+
+use error::{
+    MigrationError,
+};
+
+use crate::{hex, unix_now};
+
+pub struct Migration {
+    pub version: i64,
+    pub name: &'static str,
+    pub up: &'static str,
+    pub down: &'static str,
+}
+
+impl Migration {
+    // sha256, em hexadecimal, do script de up.
+    pub fn checksum(&self) -> String {
+        hex(&Sha256::digest(self.up.as_bytes()))
+    }
+}
+
+macro_rules! migration {
+    ($dialect:literal, $version:literal, $file:literal) => {
+        Migration {
+            version: $version,
+            name: $file,
+            up: include_str!(concat!("../migrations/", $dialect, "/", $file, ".up.sql")),
+            down: include_str!(concat!("../migrations/", $dialect, "/", $file, ".down.sql")),
+        }
+    };
+}
+
+pub const MYSQL: &[Migration] = &[
+    migration!("mysql", 1, "0001_initial"),
+    migration!("mysql", 2, "0002_sessions"),
+    migration!("mysql", 3, "0003_password_resets"),
+    migration!("mysql", 4, "0004_two_factor"),
+    migration!("mysql", 5, "0005_roles"),
+    migration!("mysql", 6, "0006_ban_details"),
+    migration!("mysql", 7, "0007_messages"),
+    migration!("mysql", 8, "0008_pending_messages"),
+    migration!("mysql", 9, "0009_rooms"),
+    migration!("mysql", 10, "0010_last_seen"),
+    migration!("mysql", 11, "0011_posts"),
+    migration!("mysql", 12, "0012_read_cursors"),
+    migration!("mysql", 13, "0013_canonical_usernames"),
+];
+
+pub const SQLITE: &[Migration] = &[
+    migration!("sqlite", 1, "0001_initial"),
+    migration!("sqlite", 2, "0002_sessions"),
+    migration!("sqlite", 3, "0003_password_resets"),
+    migration!("sqlite", 4, "0004_two_factor"),
+    migration!("sqlite", 5, "0005_roles"),
+    migration!("sqlite", 6, "0006_ban_details"),
+    migration!("sqlite", 7, "0007_messages"),
+    migration!("sqlite", 8, "0008_pending_messages"),
+    migration!("sqlite", 9, "0009_rooms"),
+    migration!("sqlite", 10, "0010_last_seen"),
+    migration!("sqlite", 11, "0011_posts"),
+    migration!("sqlite", 12, "0012_read_cursors"),
+    migration!("sqlite", 13, "0013_canonical_usernames"),
+];
+
+const CREATE_SCHEMA_MIGRATIONS: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version BIGINT PRIMARY KEY,
+        name VARCHAR(255) NOT NULL,
+        checksum CHAR(64) NOT NULL,
+        applied_at BIGINT NOT NULL
+    )
+"#;
+
+const INSERT_SCHEMA_MIGRATION: &str = r#"
+    INSERT INTO schema_migrations (version, name, checksum, applied_at)
+    VALUES (?, ?, ?, ?)
+"#;
+
+// Só no MySQL: quantos statements de uma migration que
+// parou no meio já foram aplicados.
+const CREATE_SCHEMA_MIGRATIONS_PROGRESS: &str = r#"
+    CREATE TABLE IF NOT EXISTS schema_migrations_progress (
+        version BIGINT PRIMARY KEY,
+        checksum CHAR(64) NOT NULL,
+        statements INT NOT NULL
+    )
+"#;
+
+// Registro de uma migration em schema_migrations.
+pub struct AppliedMigration {
+    pub version: i64,
+    pub checksum: String,
+    pub applied_at: i64,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MigrationState {
+    Applied { applied_at: i64 },
+    Pending,
+    // Aplicada, mas o arquivo mudou desde então.
+    Modified,
+}
+
+pub struct MigrationStatus {
+    pub version: i64,
+    pub name: &'static str,
+    pub state: MigrationState,
+}
+
+// O que cada database precisa saber fazer para que o
+// Migrator funcione. Implementado para os pools do sqlx.
+#[async_trait]
+pub trait MigrationExecutor: Send + Sync {
+    async fn ensure_table(&self) -> Result<(), MigrationError>;
+
+    async fn applied(&self) -> Result<Vec<AppliedMigration>, MigrationError>;
+
+    // Roda o script e registra a migration, numa
+    // transação quando a database permitir (veja
+    // apply_by_statement).
+    async fn apply
+    (
+        &self,
+        migration: &Migration,
+        applied_at: i64,
+    ) -> Result<(), MigrationError>;
+
+    // Roda o script de down e apaga o registro.
+    async fn revert
+    (
+        &self,
+        migration: &Migration,
+    ) -> Result<(), MigrationError>;
+}
+
+pub struct Migrator {
+    executor: Box<dyn MigrationExecutor>,
+    migrations: &'static [Migration],
+}
+
+impl Migrator {
+    pub fn mysql(pool: MySqlPool) -> Self {
+        Self {
+            executor: Box::new(pool),
+            migrations: MYSQL,
+        }
+    }
+
+    pub fn sqlite(pool: SqlitePool) -> Self {
+        Self {
+            executor: Box::new(pool),
+            migrations: SQLITE,
+        }
+    }
+
+    fn find(&self, version: i64) -> Result<&'static Migration, MigrationError> {
+        self.migrations
+            .iter()
+            .find(|m| m.version == version)
+            .ok_or(MigrationError::UnknownVersion { version })
+    }
+
+    // Estado de todas as migrations conhecidas.
+    pub async fn status(&self) -> Result<Vec<MigrationStatus>, MigrationError> {
+        self.executor.ensure_table().await?;
+        let applied = self.executor.applied().await?;
+
+        for a in &applied {
+            self.find(a.version)?;
+        }
+
+        let status = self.migrations
+            .iter()
+            .map(|m| {
+                let state = match applied.iter().find(|a| a.version == m.version) {
+                    Some(a) if a.checksum != m.checksum() => MigrationState::Modified,
+                    Some(a) => MigrationState::Applied { applied_at: a.applied_at },
+                    None => MigrationState::Pending,
+                };
+
+                MigrationStatus {
+                    version: m.version,
+                    name: m.name,
+                    state,
+                }
+            })
+            .collect();
+
+        Ok(status)
+    }
+
+    // Aplica, em ordem, todas as migrations pendentes até
+    // target (inclusive), ou todas se target for None.
+    // Retorna as versões aplicadas.
+    pub async fn up(&self, target: Option<i64>) -> Result<Vec<i64>, MigrationError> {
+        let status = self.status().await?;
+
+        if let Some(s) = status.iter().find(|s| s.state == MigrationState::Modified) {
+            return Err(MigrationError::ChecksumMismatch { version: s.version });
+        }
+
+        let mut done = Vec::new();
+
+        for s in status {
+            if s.state != MigrationState::Pending {
+                continue;
+            }
+
+            if target.is_some_and(|target| s.version > target) {
+                break;
+            }
+
+            self.executor.apply(self.find(s.version)?, unix_now()).await?;
+            done.push(s.version);
+        }
+
+        Ok(done)
+    }
+
+    // Desfaz as últimas steps migrations aplicadas, da mais
+    // nova para a mais antiga. Retorna as versões desfeitas.
+    pub async fn down(&self, steps: usize) -> Result<Vec<i64>, MigrationError> {
+        self.executor.ensure_table().await?;
+
+        let mut applied = self.executor.applied().await?;
+        applied.sort_by_key(|a| std::cmp::Reverse(a.version));
+
+        let mut done = Vec::new();
+
+        for a in applied.into_iter().take(steps) {
+            self.executor.revert(self.find(a.version)?).await?;
+            done.push(a.version);
+        }
+
+        Ok(done)
+    }
+}
+
+// Separa o script nos statements dele, terminados por ';'.
+// ';' dentro de comentários (--) e de strings não conta, e
+// trechos só com comentários são descartados.
+pub fn statements(script: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let mut start = 0;
+    let mut quote = None;
+    let mut chars = script.char_indices().peekable();
+
+    while let Some((i, c)) = chars.next() {
+        match (quote, c) {
+            // '' dentro de uma string fecha e reabre a
+            // string, o que dá no mesmo.
+            (Some(q), c) if c == q => quote = None,
+            (Some(_), _) => {},
+            (None, '\'' | '"' | '`') => quote = Some(c),
+            (None, '-') if chars.peek().is_some_and(|&(_, next)| next == '-') => {
+                while chars.next_if(|&(_, next)| next != '\n').is_some() {}
+            },
+            (None, ';') => {
+                statements.push(&script[start..i]);
+                start = i + 1;
+            },
+            _ => {},
+        }
+    }
+
+    statements.push(&script[start..]);
+
+    statements
+        .into_iter()
+        .filter(|s| code(s).next().is_some())
+        .map(str::trim)
+        .collect()
+}
+
+// Linhas do statement que não são só comentário.
+fn code(statement: &str) -> impl Iterator<Item = &str> {
+    statement
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with("--"))
+}
+
+// SET muda só a conexão em que roda (ex: FOREIGN_KEY_CHECKS).
+fn is_session_statement(statement: &str) -> bool {
+    code(statement)
+        .next()
+        .and_then(|line| line.get(..4))
+        .is_some_and(|start| start.eq_ignore_ascii_case("set "))
+}
+
+async fn apply_in_transaction
+(
+    pool: &SqlitePool,
+    migration: &Migration,
+    applied_at: i64,
+) -> Result<(), MigrationError>
+{
+    let mut tx = pool.begin().await?;
+
+    tx.execute(sqlx::raw_sql(migration.up)).await?;
+
+    sqlx::query(INSERT_SCHEMA_MIGRATION)
+        .bind(migration.version)
+        .bind(migration.name)
+        .bind(migration.checksum())
+        .bind(applied_at)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// Roda os statements da migration um a um, todos na mesma
+// conexão, pulando os que uma tentativa anterior já aplicou.
+// Os SET pulados são repetidos, já que a conexão é outra.
+//
+// Cada statement é registrado em schema_migrations_progress
+// na mesma transação em que roda. Um DDL faz commit antes do
+// registro, então se a conexão cair bem nesse intervalo ele
+// roda de novo na próxima tentativa e o erro precisa ser
+// resolvido à mão.
+async fn apply_by_statement
+(
+    pool: &MySqlPool,
+    migration: &Migration,
+    applied_at: i64,
+) -> Result<(), MigrationError>
+{
+    let mut conn = pool.acquire().await?;
+
+    conn.execute(sqlx::raw_sql(CREATE_SCHEMA_MIGRATIONS_PROGRESS)).await?;
+
+    let progress: Option<(String, i64)> = sqlx::query_as(
+        "SELECT checksum, statements FROM schema_migrations_progress WHERE version = ?",
+    )
+    .bind(migration.version)
+    .fetch_optional(&mut *conn)
+    .await?;
+
+    let done = match progress {
+        Some((checksum, _)) if checksum != migration.checksum() => {
+            return Err(MigrationError::ChecksumMismatch { version: migration.version });
+        },
+        Some((_, done)) => done as usize,
+        None => 0,
+    };
+
+    let statements = statements(migration.up);
+
+    for statement in statements.iter().take(done).filter(|s| is_session_statement(s)) {
+        conn.execute(sqlx::raw_sql(statement)).await?;
+    }
+
+    for (i, statement) in statements.iter().enumerate().skip(done) {
+        let mut tx = conn.begin().await?;
+
+        tx.execute(sqlx::raw_sql(statement)).await?;
+
+        sqlx::query(
+            r#"
+            INSERT INTO schema_migrations_progress (version, checksum, statements)
+            VALUES (?, ?, ?)
+            ON DUPLICATE KEY UPDATE statements = VALUES(statements)
+            "#,
+        )
+        .bind(migration.version)
+        .bind(migration.checksum())
+        .bind(i as i64 + 1)
+        .execute(&mut *tx)
+        .await?;
+
+        tx.commit().await?;
+    }
+
+    let mut tx = conn.begin().await?;
+
+    sqlx::query(INSERT_SCHEMA_MIGRATION)
+        .bind(migration.version)
+        .bind(migration.name)
+        .bind(migration.checksum())
+        .bind(applied_at)
+        .execute(&mut *tx)
+        .await?;
+
+    sqlx::query("DELETE FROM schema_migrations_progress WHERE version = ?")
+        .bind(migration.version)
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+    Ok(())
+}
+
+// As duas implementações só diferem no tipo do pool e em
+// como a migration é aplicada, então são geradas pela
+// mesma macro.
+macro_rules! impl_executor {
+    ($pool:ty, $apply:ident) => {
+        #[async_trait]
+        impl MigrationExecutor for $pool {
+            async fn ensure_table(&self) -> Result<(), MigrationError> {
+                sqlx::raw_sql(CREATE_SCHEMA_MIGRATIONS).execute(self).await?;
+                Ok(())
+            }
+
+            async fn applied(&self) -> Result<Vec<AppliedMigration>, MigrationError> {
+                let rows: Vec<(i64, String, i64)> = sqlx::query_as(
+                    r#"
+                    SELECT version, checksum, applied_at FROM schema_migrations
+                    ORDER BY version ASC
+                    "#,
+                )
+                .fetch_all(self)
+                .await?;
+
+                Ok(rows
+                    .into_iter()
+                    .map(|(version, checksum, applied_at)| AppliedMigration {
+                        version,
+                        checksum,
+                        applied_at,
+                    })
+                    .collect())
+            }
+
+            async fn apply
+            (
+                &self,
+                migration: &Migration,
+                applied_at: i64,
+            ) -> Result<(), MigrationError>
+            {
+                $apply(self, migration, applied_at).await
+            }
+
+            async fn revert
+            (
+                &self,
+                migration: &Migration,
+            ) -> Result<(), MigrationError>
+            {
+                let mut tx = self.begin().await?;
+
+                tx.execute(sqlx::raw_sql(migration.down)).await?;
+
+                sqlx::query("DELETE FROM schema_migrations WHERE version = ?")
+                    .bind(migration.version)
+                    .execute(&mut *tx)
+                    .await?;
+
+                tx.commit().await?;
+                Ok(())
+            }
+        }
+    };
+}
+
+impl_executor!(MySqlPool, apply_by_statement);
+impl_executor!(SqlitePool, apply_in_transaction);
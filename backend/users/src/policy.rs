@@ -0,0 +1,197 @@
+/*
+Regras para usernames e senhas de contas novas.
+
+Usernames passam por uma forma canônica antes de qualquer
+uso: normalização unicode NFKC (para que, por exemplo, "ｂｏｂ"
+e "bob" sejam o mesmo nome) e, se case_insensitive, letras
+minúsculas. É a forma canônica que é guardada na database e
+comparada no login, então "Alice" e "alice" são a mesma conta.
+
+Senhas precisam ter um tamanho mínimo e máximo (o máximo
+evita que senhas enormes sejam usadas para sobrecarregar o
+argon2) e um número mínimo de classes de caracteres
+diferentes entre minúsculas, maiúsculas, dígitos e símbolos.
+*/
+
+use std::str::FromStr;
+
+use unicode_normalization::UnicodeNormalization;
+
+use error::AuthenticateErrorType;
+
+// Tamanho máximo do nome de uma sala. Nomes de sala só
+// aceitam letras e dígitos ascii, além de '_', '.' e '-'.
+pub const ROOM_NAME_MAX_LENGTH: usize = 32;
+
+// Tamanho máximo de um post do feed.
+pub const POST_MAX_LENGTH: usize = 500;
+
+// Caracteres aceitos em usernames, além de '_', '.' e '-'.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsernameCharset {
+    // Apenas letras e dígitos ascii.
+    Ascii,
+    // Qualquer letra ou dígito unicode.
+    Unicode,
+}
+
+impl FromStr for UsernameCharset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ascii" => Ok(Self::Ascii),
+            "unicode" => Ok(Self::Unicode),
+            _ => Err(format!("{s:?} não é um charset (use ascii ou unicode)")),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ValidationPolicy {
+    pub username_min_length: usize,
+    pub username_max_length: usize,
+    pub username_charset: UsernameCharset,
+    pub case_insensitive: bool,
+    // Comparados com a forma canônica do username.
+    pub reserved_usernames: Vec<String>,
+    pub password_min_length: usize,
+    pub password_max_length: usize,
+    // Entre 0 e 4.
+    pub password_min_classes: usize,
+}
+
+impl Default for ValidationPolicy {
+    fn default() -> Self {
+        Self {
+            username_min_length: 3,
+            username_max_length: 32,
+            username_charset: UsernameCharset::Ascii,
+            case_insensitive: true,
+            reserved_usernames: ["admin", "root", "server", "system"]
+                .map(String::from)
+                .to_vec(),
+            password_min_length: 8,
+            password_max_length: 256,
+            password_min_classes: 2,
+        }
+    }
+}
+
+impl ValidationPolicy {
+    // Forma canônica do username, usada para guardar e
+    // procurar contas. Não valida nada.
+    pub fn canonical_username(&self, username: &str) -> String {
+        let normalized: String = username.trim().nfkc().collect();
+
+        match self.case_insensitive {
+            true => normalized.to_lowercase(),
+            false => normalized,
+        }
+    }
+
+    // Valida um username já na forma canônica.
+    pub fn validate_username(&self, username: &str) -> Result<(), AuthenticateErrorType> {
+        let length = username.chars().count();
+
+        if length < self.username_min_length {
+            return Err(AuthenticateErrorType::UsernameTooShort { min: self.username_min_length });
+        }
+
+        if length > self.username_max_length {
+            return Err(AuthenticateErrorType::UsernameTooLong { max: self.username_max_length });
+        }
+
+        let allowed = |c: char| match self.username_charset {
+            UsernameCharset::Ascii => c.is_ascii_alphanumeric(),
+            UsernameCharset::Unicode => c.is_alphanumeric(),
+        };
+
+        // O primeiro caractere precisa ser uma letra ou dígito,
+        // para evitar nomes como "..." ou "-_-".
+        let starts_well = username.chars().next().is_some_and(allowed);
+
+        if !starts_well || !username.chars().all(|c| allowed(c) || "_.-".contains(c)) {
+            return Err(AuthenticateErrorType::UsernameInvalidCharacters);
+        }
+
+        if self.reserved_usernames.iter().any(|r| self.canonical_username(r) == username) {
+            return Err(AuthenticateErrorType::UsernameReserved);
+        }
+
+        Ok(())
+    }
+
+    // Forma canônica do nome de uma sala: como a dos
+    // usernames, mas sempre sem diferenciar maiúsculas.
+    pub fn canonical_room_name(&self, name: &str) -> String {
+        name.trim().nfkc().collect::<String>().to_lowercase()
+    }
+
+    // Valida um nome de sala já na forma canônica.
+    pub fn validate_room_name(&self, name: &str) -> Result<(), AuthenticateErrorType> {
+        let invalid = AuthenticateErrorType::InvalidRoomName { max: ROOM_NAME_MAX_LENGTH };
+
+        if name.chars().count() > ROOM_NAME_MAX_LENGTH {
+            return Err(invalid);
+        }
+
+        let starts_well = name.chars().next().is_some_and(|c| c.is_ascii_alphanumeric());
+
+        if !starts_well || !name.chars().all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c)) {
+            return Err(invalid);
+        }
+
+        Ok(())
+    }
+
+    // Posts não podem ser vazios (ou só espaços) nem passar
+    // de POST_MAX_LENGTH caracteres.
+    pub fn validate_post(&self, text: &str) -> Result<(), AuthenticateErrorType> {
+        if text.trim().is_empty() || text.chars().count() > POST_MAX_LENGTH {
+            return Err(AuthenticateErrorType::InvalidPost { max: POST_MAX_LENGTH });
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_password
+    (
+        &self,
+        username: &str,
+        password: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        let length = password.chars().count();
+
+        if length < self.password_min_length {
+            return Err(AuthenticateErrorType::PasswordTooShort { min: self.password_min_length });
+        }
+
+        if length > self.password_max_length {
+            return Err(AuthenticateErrorType::PasswordTooLong { max: self.password_max_length });
+        }
+
+        let classes = [
+            password.chars().any(char::is_lowercase),
+            password.chars().any(char::is_uppercase),
+            password.chars().any(|c| c.is_numeric()),
+            password.chars().any(|c| !c.is_alphanumeric()),
+        ]
+        .into_iter()
+        .filter(|&has| has)
+        .count();
+
+        if classes < self.password_min_classes {
+            return Err(AuthenticateErrorType::PasswordTooWeak {
+                min_classes: self.password_min_classes,
+            });
+        }
+
+        if password.to_lowercase().contains(&username.to_lowercase()) {
+            return Err(AuthenticateErrorType::PasswordContainsUsername);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+/*
+Presença dos usuários: quem está online e quem quer saber.
+
+Uma sessão passa a acompanhar a presença de outros usuários
+ao perguntar por ela (ClientProtocol::RequestPresence) e
+continua acompanhando até a conexão fechar ou fazer logout.
+
+Para não avisar a mesma mudança várias vezes (ex: um usuário
+com várias sessões derrubadas ao mesmo tempo), o registro
+guarda o último estado anunciado de cada usuário e só há
+aviso quando o estado atual é diferente dele.
+*/
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Mutex,
+};
+
+use crate::Tx;
+
+#[derive(Default)]
+struct Registry {
+    // username -> sessões que acompanham a presença dele
+    watchers: HashMap<String, Vec<Tx>>,
+    // Usuários anunciados como online.
+    online: HashSet<String>,
+}
+
+// O Mutex é o da std, pois nunca é mantido travado
+// através de um .await.
+#[derive(Default)]
+pub struct PresenceRegistry {
+    inner: Mutex<Registry>,
+}
+
+impl PresenceRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watch(&self, watcher: &Tx, usernames: &[String]) {
+        let mut inner = self.inner.lock().unwrap();
+
+        for username in usernames {
+            let watchers = inner.watchers.entry(username.clone()).or_default();
+
+            if !watchers.iter().any(|tx| tx.same_channel(watcher)) {
+                watchers.push(watcher.clone());
+            }
+        }
+    }
+
+    // Para de avisar watcher de qualquer mudança.
+    pub fn unwatch(&self, watcher: &Tx) {
+        let mut inner = self.inner.lock().unwrap();
+
+        inner.watchers.retain(|_, watchers| {
+            watchers.retain(|tx| !tx.same_channel(watcher));
+            !watchers.is_empty()
+        });
+    }
+
+    // Registra o estado atual de username. Retorna quem
+    // precisa ser avisado se ele for diferente do último
+    // anunciado, ou None se nada mudou.
+    pub fn transition(&self, username: &str, online: bool) -> Option<Vec<Tx>> {
+        let mut inner = self.inner.lock().unwrap();
+
+        let changed = match online {
+            true => inner.online.insert(username.to_string()),
+            false => inner.online.remove(username),
+        };
+
+        if !changed {
+            return None;
+        }
+
+        // Sessões já fechadas são descartadas aqui.
+        let Some(watchers) = inner.watchers.get_mut(username) else {
+            return Some(Vec::new());
+        };
+
+        watchers.retain(|tx| !tx.is_closed());
+        let watchers = watchers.clone();
+
+        if watchers.is_empty() {
+            inner.watchers.remove(username);
+        }
+
+        Some(watchers)
+    }
+}
@@ -0,0 +1,65 @@
+/*
+Papéis das contas. Cada papel tem todas as permissões do
+anterior:
+
+user      -> conta comum
+moderator -> lista quem está online e derruba sessões, bane
+             e desbane contas comuns
+admin     -> faz o mesmo com qualquer conta e muda o papel
+             de outras contas
+
+O papel fica guardado na coluna role da tabela users; contas
+novas são sempre user. O primeiro admin precisa ser definido
+fora do chat, com `utils set-role`.
+*/
+
+use std::{
+    fmt,
+    str::FromStr,
+};
+
+use serde::{
+    Deserialize,
+    Serialize,
+};
+
+// A ordem das variantes é a hierarquia: Role::Admin >
+// Role::Moderator > Role::User.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    #[default]
+    User,
+    Moderator,
+    Admin,
+}
+
+impl Role {
+    // Como o papel é guardado na database.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::User => "user",
+            Role::Moderator => "moderator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "user" => Ok(Self::User),
+            "moderator" => Ok(Self::Moderator),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format!("{s:?} não é um papel (use user, moderator ou admin)")),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
@@ -0,0 +1,891 @@
+/*
+Backend de armazenamento inteiramente em memória. Nada
+sobrevive ao fim do processo, então ele serve apenas para
+testes e para o modo --demo do servidor, que rodam sem
+nenhuma database.
+*/
+
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    sync::{
+        Mutex,
+        atomic::{AtomicI64, Ordering},
+    },
+};
+
+use async_trait::async_trait;
+
+use error::{
+    AuthenticateErrorType,
+};
+
+use crate::migrations::Migrator;
+use crate::roles::Role;
+use crate::storage::{
+    Storage,
+    SessionRecord,
+    TwoFactorRecord,
+    BanRecord,
+    StoredMessage,
+    RoomRecord,
+    RoomSummary,
+    StoredRoomMessage,
+    StoredPost,
+    UsernameConflict,
+};
+
+// Os Mutex aqui são os da std, pois nenhum deles é
+// mantido travado através de um .await.
+#[derive(Default)]
+pub struct MemoryStorage {
+    // username -> hash da senha
+    accounts: Mutex<HashMap<String, String>>,
+    // id da sessão -> sessão
+    sessions: Mutex<HashMap<String, SessionRecord>>,
+    // username -> (hash do código de reset, expira_em)
+    reset_codes: Mutex<HashMap<String, (String, i64)>>,
+    two_factor: Mutex<HashMap<String, TwoFactorRecord>>,
+    // username -> hashes dos códigos de recuperação
+    recovery_codes: Mutex<HashMap<String, Vec<String>>>,
+    // username -> papel; quem não está aqui é Role::User.
+    roles: Mutex<HashMap<String, Role>>,
+    bans: Mutex<HashMap<String, BanRecord>>,
+    // username -> quando ficou offline pela última vez
+    last_seen: Mutex<HashMap<String, i64>>,
+    // Em ordem de id.
+    messages: Mutex<Vec<StoredMessage>>,
+    last_message_id: AtomicI64,
+    // ids das mensagens ainda não entregues.
+    pending_messages: Mutex<BTreeSet<i64>>,
+    rooms: Mutex<Vec<RoomRecord>>,
+    last_room_id: AtomicI64,
+    // (id da sala, username)
+    room_members: Mutex<BTreeSet<(i64, String)>>,
+    room_messages: Mutex<Vec<StoredRoomMessage>>,
+    last_room_message_id: AtomicI64,
+    // (receiver, id da mensagem)
+    pending_room_messages: Mutex<BTreeSet<(String, i64)>>,
+    // (reader, peer) -> (last_read_id, read_at)
+    read_cursors: Mutex<HashMap<(String, String), (i64, i64)>>,
+    // Em ordem de id.
+    posts: Mutex<Vec<StoredPost>>,
+    last_post_id: AtomicI64,
+}
+
+impl MemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for MemoryStorage {
+    fn migrator(&self) -> Option<Migrator> {
+        None
+    }
+
+    async fn insert_user
+    (
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        let mut accounts = self.accounts.lock().unwrap();
+
+        if accounts.contains_key(username) {
+            return Err(AuthenticateErrorType::UserAlreadyExists);
+        }
+
+        accounts.insert(username.to_string(), password_hash.to_string());
+        Ok(())
+    }
+
+    async fn user_exists
+    (
+        &self,
+        username: &str,
+    ) -> Result<bool, AuthenticateErrorType>
+    {
+        Ok(self.accounts.lock().unwrap().contains_key(username))
+    }
+
+    async fn password_hash
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<String>, AuthenticateErrorType>
+    {
+        Ok(self.accounts.lock().unwrap().get(username).cloned())
+    }
+
+    async fn store_message
+    (
+        &self,
+        message_id: i64,
+        receiver: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        // Mesmo comportamento das FOREIGN KEY nas databases:
+        // a mensagem precisa existir.
+        let exists = self.messages.lock().unwrap()
+            .iter()
+            .any(|m| m.id == message_id && m.receiver == receiver);
+
+        if !exists {
+            return Err(AuthenticateErrorType::OfflineMessageError);
+        }
+
+        self.pending_messages.lock().unwrap().insert(message_id);
+
+        Ok(())
+    }
+
+    async fn get_stored_messages
+    (
+        &self,
+        receiver: &str,
+    ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+    {
+        // Sempre messages antes de pending_messages, para
+        // que dois Mutex nunca sejam travados em ordens diferentes.
+        let messages = self.messages.lock().unwrap();
+        let pending = self.pending_messages.lock().unwrap();
+
+        let messages = messages
+            .iter()
+            .filter(|m| m.receiver == receiver && pending.contains(&m.id))
+            .cloned()
+            .collect();
+
+        Ok(messages)
+    }
+
+    async fn delete_stored_messages
+    (
+        &self,
+        message_ids: &[i64],
+        receiver: &str,
+    ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+    {
+        let messages = self.messages.lock().unwrap();
+        let mut pending = self.pending_messages.lock().unwrap();
+
+        // messages está em ordem de id.
+        let deleted: Vec<StoredMessage> = messages
+            .iter()
+            .filter(|m| message_ids.contains(&m.id) && m.receiver == receiver)
+            .filter(|m| pending.remove(&m.id))
+            .cloned()
+            .collect();
+
+        Ok(deleted)
+    }
+
+    async fn insert_session
+    (
+        &self,
+        id: &str,
+        username: &str,
+        expires_at: i64,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        self.sessions.lock().unwrap().insert(id.to_string(), SessionRecord {
+            username: username.to_string(),
+            expires_at,
+            revoked: false,
+        });
+
+        Ok(())
+    }
+
+    async fn get_session
+    (
+        &self,
+        id: &str,
+    ) -> Result<Option<SessionRecord>, AuthenticateErrorType>
+    {
+        Ok(self.sessions.lock().unwrap().get(id).cloned())
+    }
+
+    async fn revoke_session
+    (
+        &self,
+        id: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if let Some(session) = self.sessions.lock().unwrap().get_mut(id) {
+            session.revoked = true;
+        }
+
+        Ok(())
+    }
+
+    async fn revoke_user_sessions
+    (
+        &self,
+        username: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        for session in self.sessions.lock().unwrap().values_mut() {
+            if session.username == username {
+                session.revoked = true;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn update_password
+    (
+        &self,
+        username: &str,
+        password_hash: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        match self.accounts.lock().unwrap().get_mut(username) {
+            Some(hash) => {
+                *hash = password_hash.to_string();
+                Ok(())
+            },
+            None => Err(AuthenticateErrorType::UserNotFound),
+        }
+    }
+
+    async fn delete_user
+    (
+        &self,
+        username: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if self.accounts.lock().unwrap().remove(username).is_none() {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        // Mesmo comportamento do ON DELETE CASCADE.
+        self.sessions.lock().unwrap()
+            .retain(|_, s| s.username != username);
+        self.reset_codes.lock().unwrap().remove(username);
+        self.two_factor.lock().unwrap().remove(username);
+        self.recovery_codes.lock().unwrap().remove(username);
+        self.roles.lock().unwrap().remove(username);
+        self.bans.lock().unwrap().remove(username);
+        self.last_seen.lock().unwrap().remove(username);
+
+        let mut messages = self.messages.lock().unwrap();
+        messages.retain(|m| m.sender != username && m.receiver != username);
+        self.pending_messages.lock().unwrap()
+            .retain(|id| messages.iter().any(|m| m.id == *id));
+        self.read_cursors.lock().unwrap()
+            .retain(|(reader, peer), _| reader != username && peer != username);
+
+        self.room_members.lock().unwrap()
+            .retain(|(_, member)| member != username);
+
+        let mut room_messages = self.room_messages.lock().unwrap();
+        room_messages.retain(|m| m.sender != username);
+        self.pending_room_messages.lock().unwrap()
+            .retain(|(receiver, id)| receiver != username && room_messages.iter().any(|m| m.id == *id));
+
+        self.posts.lock().unwrap()
+            .retain(|p| p.author != username);
+
+        Ok(())
+    }
+
+    async fn insert_reset_code
+    (
+        &self,
+        username: &str,
+        code_hash: &str,
+        expires_at: i64,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        self.reset_codes.lock().unwrap()
+            .insert(username.to_string(), (code_hash.to_string(), expires_at));
+
+        Ok(())
+    }
+
+    async fn get_reset_code
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<(String, i64)>, AuthenticateErrorType>
+    {
+        Ok(self.reset_codes.lock().unwrap().get(username).cloned())
+    }
+
+    async fn delete_reset_code
+    (
+        &self,
+        username: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        self.reset_codes.lock().unwrap().remove(username);
+        Ok(())
+    }
+
+    async fn set_two_factor_secret
+    (
+        &self,
+        username: &str,
+        secret: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        self.two_factor.lock().unwrap().insert(username.to_string(), TwoFactorRecord {
+            secret: secret.to_string(),
+            enabled: false,
+            last_step: 0,
+        });
+
+        Ok(())
+    }
+
+    async fn get_two_factor
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<TwoFactorRecord>, AuthenticateErrorType>
+    {
+        Ok(self.two_factor.lock().unwrap().get(username).cloned())
+    }
+
+    async fn enable_two_factor
+    (
+        &self,
+        username: &str,
+        recovery_hashes: &[String],
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if let Some(record) = self.two_factor.lock().unwrap().get_mut(username) {
+            record.enabled = true;
+        }
+
+        self.recovery_codes.lock().unwrap()
+            .insert(username.to_string(), recovery_hashes.to_vec());
+
+        Ok(())
+    }
+
+    async fn use_two_factor_step
+    (
+        &self,
+        username: &str,
+        step: i64,
+    ) -> Result<bool, AuthenticateErrorType>
+    {
+        match self.two_factor.lock().unwrap().get_mut(username) {
+            Some(record) if record.last_step < step => {
+                record.last_step = step;
+                Ok(true)
+            },
+            _ => Ok(false),
+        }
+    }
+
+    async fn use_recovery_code
+    (
+        &self,
+        username: &str,
+        code_hash: &str,
+    ) -> Result<bool, AuthenticateErrorType>
+    {
+        let mut recovery_codes = self.recovery_codes.lock().unwrap();
+
+        let Some(codes) = recovery_codes.get_mut(username) else {
+            return Ok(false);
+        };
+
+        let before = codes.len();
+        codes.retain(|c| c != code_hash);
+
+        Ok(codes.len() < before)
+    }
+
+    async fn delete_two_factor
+    (
+        &self,
+        username: &str,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        self.two_factor.lock().unwrap().remove(username);
+        self.recovery_codes.lock().unwrap().remove(username);
+        Ok(())
+    }
+
+    async fn get_role
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<Role>, AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Ok(None);
+        }
+
+        let role = self.roles.lock().unwrap().get(username).copied();
+        Ok(Some(role.unwrap_or_default()))
+    }
+
+    async fn set_role
+    (
+        &self,
+        username: &str,
+        role: Role,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        self.roles.lock().unwrap().insert(username.to_string(), role);
+        Ok(())
+    }
+
+    async fn set_ban
+    (
+        &self,
+        username: &str,
+        ban: Option<&BanRecord>,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        let mut bans = self.bans.lock().unwrap();
+
+        match ban {
+            Some(ban) => bans.insert(username.to_string(), ban.clone()),
+            None => bans.remove(username),
+        };
+
+        Ok(())
+    }
+
+    async fn get_ban
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<BanRecord>, AuthenticateErrorType>
+    {
+        Ok(self.bans.lock().unwrap().get(username).cloned())
+    }
+
+    async fn set_last_seen
+    (
+        &self,
+        username: &str,
+        last_seen: i64,
+    ) -> Result<(), AuthenticateErrorType>
+    {
+        if self.accounts.lock().unwrap().contains_key(username) {
+            self.last_seen.lock().unwrap().insert(username.to_string(), last_seen);
+        }
+
+        Ok(())
+    }
+
+    async fn get_last_seen
+    (
+        &self,
+        username: &str,
+    ) -> Result<Option<i64>, AuthenticateErrorType>
+    {
+        Ok(self.last_seen.lock().unwrap().get(username).copied())
+    }
+
+    async fn insert_message
+    (
+        &self,
+        sender: &str,
+        receiver: &str,
+        body: &str,
+        sent_at: i64,
+    ) -> Result<i64, AuthenticateErrorType>
+    {
+        let accounts = self.accounts.lock().unwrap();
+
+        if !accounts.contains_key(sender) || !accounts.contains_key(receiver) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        drop(accounts);
+
+        // Como o AUTOINCREMENT, ids apagados não são reusados.
+        let mut messages = self.messages.lock().unwrap();
+        let id = self.last_message_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        messages.push(StoredMessage {
+            id,
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            body: body.to_string(),
+            sent_at,
+        });
+
+        Ok(id)
+    }
+
+    async fn get_conversation
+    (
+        &self,
+        a: &str,
+        b: &str,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+    {
+        let before = before.unwrap_or(i64::MAX);
+
+        let page = self.messages.lock().unwrap()
+            .iter()
+            .rev()
+            .filter(|m| m.id < before)
+            .filter(|m| (m.sender == a && m.receiver == b) || (m.sender == b && m.receiver == a))
+            .take(limit as usize)
+            .cloned()
+            .collect();
+
+        Ok(page)
+    }
+
+    async fn get_message
+    (
+        &self,
+        id: i64,
+    ) -> Result<Option<StoredMessage>, AuthenticateErrorType>
+    {
+        let message = self.messages.lock().unwrap()
+            .iter()
+            .find(|m| m.id == id)
+            .cloned();
+
+        Ok(message)
+    }
+
+    async fn advance_read_cursor
+    (
+        &self,
+        reader: &str,
+        peer: &str,
+        last_read_id: i64,
+        read_at: i64,
+    ) -> Result<bool, AuthenticateErrorType>
+    {
+        let accounts = self.accounts.lock().unwrap();
+
+        if !accounts.contains_key(reader) || !accounts.contains_key(peer) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        drop(accounts);
+
+        let mut cursors = self.read_cursors.lock().unwrap();
+        let cursor = cursors.entry((reader.to_string(), peer.to_string())).or_insert((0, 0));
+
+        if cursor.0 >= last_read_id {
+            return Ok(false);
+        }
+
+        *cursor = (last_read_id, read_at);
+        Ok(true)
+    }
+
+    async fn get_unread_counts
+    (
+        &self,
+        reader: &str,
+    ) -> Result<Vec<(String, i64)>, AuthenticateErrorType>
+    {
+        let messages = self.messages.lock().unwrap();
+        let cursors = self.read_cursors.lock().unwrap();
+
+        let mut counts: BTreeMap<String, i64> = BTreeMap::new();
+
+        for m in messages.iter().filter(|m| m.receiver == reader) {
+            let last_read = cursors
+                .get(&(reader.to_string(), m.sender.clone()))
+                .map(|(id, _)| *id)
+                .unwrap_or(0);
+
+            if m.id > last_read {
+                *counts.entry(m.sender.clone()).or_default() += 1;
+            }
+        }
+
+        Ok(counts.into_iter().collect())
+    }
+
+    async fn insert_room
+    (
+        &self,
+        name: &str,
+        creator: &str,
+        created_at: i64,
+    ) -> Result<i64, AuthenticateErrorType>
+    {
+        let mut rooms = self.rooms.lock().unwrap();
+
+        if rooms.iter().any(|r| r.name == name) {
+            return Err(AuthenticateErrorType::RoomAlreadyExists);
+        }
+
+        if !self.accounts.lock().unwrap().contains_key(creator) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        let id = self.last_room_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        rooms.push(RoomRecord {
+            id,
+            name: name.to_string(),
+            created_at,
+        });
+
+        self.room_members.lock().unwrap().insert((id, creator.to_string()));
+
+        Ok(id)
+    }
+
+    async fn get_room
+    (
+        &self,
+        name: &str,
+    ) -> Result<Option<RoomRecord>, AuthenticateErrorType>
+    {
+        let room = self.rooms.lock().unwrap()
+            .iter()
+            .find(|r| r.name == name)
+            .cloned();
+
+        Ok(room)
+    }
+
+    async fn list_rooms
+    (
+        &self,
+        username: &str,
+    ) -> Result<Vec<RoomSummary>, AuthenticateErrorType>
+    {
+        let rooms = self.rooms.lock().unwrap();
+        let members = self.room_members.lock().unwrap();
+
+        let mut summaries: Vec<RoomSummary> = rooms
+            .iter()
+            .map(|room| RoomSummary {
+                name: room.name.clone(),
+                members: members.iter().filter(|(id, _)| *id == room.id).count() as i64,
+                joined: members.contains(&(room.id, username.to_string())),
+            })
+            .collect();
+
+        summaries.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(summaries)
+    }
+
+    async fn insert_room_member
+    (
+        &self,
+        room_id: i64,
+        username: &str,
+        _joined_at: i64,
+    ) -> Result<bool, AuthenticateErrorType>
+    {
+        let rooms = self.rooms.lock().unwrap();
+
+        if !rooms.iter().any(|r| r.id == room_id) {
+            return Err(AuthenticateErrorType::RoomNotFound);
+        }
+
+        if !self.accounts.lock().unwrap().contains_key(username) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        Ok(self.room_members.lock().unwrap().insert((room_id, username.to_string())))
+    }
+
+    async fn delete_room_member
+    (
+        &self,
+        room_id: i64,
+        username: &str,
+    ) -> Result<bool, AuthenticateErrorType>
+    {
+        let removed = self.room_members.lock().unwrap()
+            .remove(&(room_id, username.to_string()));
+
+        let Some(room) = self.rooms.lock().unwrap().iter().find(|r| r.id == room_id).cloned() else {
+            return Ok(removed);
+        };
+
+        let room_messages = self.room_messages.lock().unwrap();
+
+        self.pending_room_messages.lock().unwrap().retain(|(receiver, id)| {
+            receiver != username || !room_messages.iter().any(|m| m.id == *id && m.room == room.name)
+        });
+
+        Ok(removed)
+    }
+
+    async fn get_room_members
+    (
+        &self,
+        room_id: i64,
+    ) -> Result<Vec<String>, AuthenticateErrorType>
+    {
+        let members = self.room_members.lock().unwrap()
+            .iter()
+            .filter(|(id, _)| *id == room_id)
+            .map(|(_, username)| username.clone())
+            .collect();
+
+        Ok(members)
+    }
+
+    async fn insert_room_message
+    (
+        &self,
+        room_id: i64,
+        sender: &str,
+        body: &str,
+        sent_at: i64,
+    ) -> Result<(i64, Vec<String>), AuthenticateErrorType>
+    {
+        let Some(room) = self.rooms.lock().unwrap().iter().find(|r| r.id == room_id).cloned() else {
+            return Err(AuthenticateErrorType::RoomNotFound);
+        };
+
+        if !self.accounts.lock().unwrap().contains_key(sender) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        let mut room_messages = self.room_messages.lock().unwrap();
+        let id = self.last_room_message_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        let bans = self.bans.lock().unwrap();
+
+        let recipients: Vec<String> = self.room_members.lock().unwrap()
+            .iter()
+            .filter(|(member_room, username)| *member_room == room_id && username != sender)
+            .filter(|(_, username)| bans.get(username).is_none_or(|b| b.until.is_some_and(|until| until <= sent_at)))
+            .map(|(_, username)| username.clone())
+            .collect();
+
+        room_messages.push(StoredRoomMessage {
+            id,
+            room: room.name,
+            sender: sender.to_string(),
+            body: body.to_string(),
+            sent_at,
+        });
+
+        self.pending_room_messages.lock().unwrap()
+            .extend(recipients.iter().map(|username| (username.clone(), id)));
+
+        Ok((id, recipients))
+    }
+
+    async fn get_stored_room_messages
+    (
+        &self,
+        receiver: &str,
+    ) -> Result<Vec<StoredRoomMessage>, AuthenticateErrorType>
+    {
+        let room_messages = self.room_messages.lock().unwrap();
+        let pending = self.pending_room_messages.lock().unwrap();
+
+        let messages = room_messages
+            .iter()
+            .filter(|m| pending.contains(&(receiver.to_string(), m.id)))
+            .cloned()
+            .collect();
+
+        Ok(messages)
+    }
+
+    async fn delete_stored_room_messages
+    (
+        &self,
+        message_ids: &[i64],
+        receiver: &str,
+    ) -> Result<u64, AuthenticateErrorType>
+    {
+        let mut pending = self.pending_room_messages.lock().unwrap();
+
+        let removed = message_ids
+            .iter()
+            .filter(|&&id| pending.remove(&(receiver.to_string(), id)))
+            .count();
+
+        Ok(removed as u64)
+    }
+
+    async fn insert_post
+    (
+        &self,
+        author: &str,
+        body: &str,
+        created_at: i64,
+    ) -> Result<i64, AuthenticateErrorType>
+    {
+        if !self.accounts.lock().unwrap().contains_key(author) {
+            return Err(AuthenticateErrorType::UserNotFound);
+        }
+
+        let mut posts = self.posts.lock().unwrap();
+        let id = self.last_post_id.fetch_add(1, Ordering::Relaxed) + 1;
+
+        posts.push(StoredPost {
+            id,
+            author: author.to_string(),
+            body: body.to_string(),
+            created_at,
+        });
+
+        Ok(id)
+    }
+
+    async fn get_posts
+    (
+        &self,
+        before: Option<i64>,
+        limit: u32,
+    ) -> Result<Vec<StoredPost>, AuthenticateErrorType>
+    {
+        let before = before.unwrap_or(i64::MAX);
+
+        let page = self.posts.lock().unwrap()
+            .iter()
+            .rev()
+            .filter(|p| p.id < before)
+            .take(limit as usize)
+            .cloned()
+            .collect();
+
+        Ok(page)
+    }
+
+    // Contas em memória já nascem com o username canônico.
+    async fn username_conflicts
+    (
+        &self,
+    ) -> Result<Vec<UsernameConflict>, AuthenticateErrorType>
+    {
+        Ok(Vec::new())
+    }
+}
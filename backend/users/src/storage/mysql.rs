@@ -0,0 +1,53 @@
+/*
+Backend de armazenamento em MySQL. As tabelas são criadas
+pelas migrations em /users/migrations/mysql.
+*/
+
+use sqlx::{
+    mysql::{MySqlPool, MySqlPoolOptions},
+};
+
+use error::{
+    AuthenticateErrorType,
+};
+
+use crate::storage::{
+    PoolConfig,
+    sql::impl_sql_storage,
+};
+
+#[derive(Clone)]
+pub struct MySqlStorage {
+    pool: MySqlPool,
+}
+
+impl MySqlStorage {
+    // Cria o pool de conexões com a database em db_url
+    // seguindo a configuração dada.
+    pub async fn connect
+    (
+        db_url: &str,
+        config: PoolConfig,
+    ) -> Result<Self, AuthenticateErrorType>
+    {
+        let pool = MySqlPoolOptions::new()
+            .max_connections(config.max_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(config.idle_timeout)
+            .connect(db_url)
+            .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+// As consultas são as de sql.rs; aqui ficam só as
+// diferenças do dialeto.
+impl_sql_storage!(
+    MySqlStorage,
+    sqlx::MySql,
+    mysql,
+    "INSERT IGNORE",
+    "FOR UPDATE",
+    |result| result.last_insert_id() as i64,
+);
@@ -0,0 +1,1118 @@
+/*
+Implementação de Storage compartilhada pelos backends sql
+(MySqlStorage e SqliteStorage). As consultas são as mesmas
+nos dois; cada backend só diz como conectar e passa para
+impl_sql_storage! o que muda entre os dialetos:
+
+- o tipo de Database do sqlx e o construtor do Migrator;
+- como se escreve um insert que ignora linhas repetidas
+  (INSERT OR IGNORE no sqlite, INSERT IGNORE no mysql);
+- como travar as linhas de um SELECT até o fim da
+  transação (FOR UPDATE no mysql; o sqlite não precisa,
+  já que só uma transação escreve por vez e a outra
+  falha em vez de apagar de novo);
+- como ler o id gerado por um insert.
+*/
+
+use crate::storage::{
+    StoredMessage,
+    StoredPost,
+};
+
+macro_rules! impl_sql_storage {
+    (
+        $storage:ty,
+        $db:ty,
+        $migrator:ident,
+        $insert_ignore:literal,
+        $lock_rows:literal,
+        |$result:ident| $last_id:expr $(,)?
+    ) => {
+        const _: () = {
+            use async_trait::async_trait;
+
+            use sqlx::Database;
+
+            use error::{
+                AuthenticateErrorType,
+            };
+
+            use $crate::{
+                migrations::Migrator,
+                roles::Role,
+            };
+
+            use $crate::storage::{
+                Storage,
+                SessionRecord,
+                TwoFactorRecord,
+                BanRecord,
+                StoredMessage,
+                RoomRecord,
+                RoomSummary,
+                StoredRoomMessage,
+                StoredPost,
+                UsernameConflict,
+                is_unique_violation,
+                sql::{placeholders, stored_message, stored_post},
+            };
+
+            fn last_insert_id($result: &<$db as Database>::QueryResult) -> i64 {
+                $last_id
+            }
+
+            #[async_trait]
+            impl Storage for $storage {
+                fn migrator(&self) -> Option<Migrator> {
+                    Some(Migrator::$migrator(self.pool.clone()))
+                }
+
+                async fn insert_user
+                (
+                    &self,
+                    username: &str,
+                    password_hash: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    match sqlx::query(
+                        "INSERT INTO users (username, password_hash) VALUES (?, ?)",
+                    )
+                    .bind(username)
+                    .bind(password_hash)
+                    .execute(&self.pool)
+                    .await {
+                        Ok(_) => Ok(()),
+                        Err(e) if is_unique_violation(&e) => Err(AuthenticateErrorType::UserAlreadyExists),
+                        Err(_) => Err(AuthenticateErrorType::UserNotAdded),
+                    }
+                }
+
+                async fn user_exists
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<bool, AuthenticateErrorType>
+                {
+                    let result: i64 = sqlx::query_scalar(
+                        r#"
+                        SELECT EXISTS(
+                            SELECT 1 FROM users WHERE username = ?
+                        )
+                        "#,
+                    )
+                    .bind(username)
+                    .fetch_one(&self.pool)
+                    .await?;
+
+                    Ok(result == 1)
+                }
+
+                async fn password_hash
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Option<String>, AuthenticateErrorType>
+                {
+                    let hash = sqlx::query_scalar(
+                        "SELECT password_hash FROM users WHERE username = ?",
+                    )
+                    .bind(username)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(hash)
+                }
+
+                async fn store_message
+                (
+                    &self,
+                    message_id: i64,
+                    receiver: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    match sqlx::query(
+                        r#"
+                        INSERT INTO pending_messages (message_id, receiver)
+                        VALUES (?, ?)
+                        "#,
+                    )
+                    .bind(message_id)
+                    .bind(receiver)
+                    .execute(&self.pool)
+                    .await {
+                        Ok(_) => Ok(()),
+                        Err(_) => Err(AuthenticateErrorType::OfflineMessageError)
+                    }
+                }
+
+                async fn get_stored_messages
+                (
+                    &self,
+                    receiver: &str,
+                ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+                {
+                    let rows: Vec<(i64, String, String, String, i64)> = sqlx::query_as(
+                        r#"
+                        SELECT m.id, m.sender, m.receiver, m.body, m.sent_at
+                        FROM pending_messages p
+                        JOIN messages m ON m.id = p.message_id
+                        WHERE p.receiver = ?
+                        ORDER BY m.id ASC
+                        "#,
+                    )
+                    .bind(receiver)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(rows.into_iter().map(stored_message).collect())
+                }
+
+                async fn delete_stored_messages
+                (
+                    &self,
+                    message_ids: &[i64],
+                    receiver: &str,
+                ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+                {
+                    if message_ids.is_empty() {
+                        return Ok(Vec::new());
+                    }
+
+                    let ids = placeholders(message_ids.len());
+                    let mut tx = self.pool.begin().await?;
+
+                    // As linhas lidas ficam travadas até o fim da
+                    // transação, então dois acks simultâneos não
+                    // geram dois recibos da mesma mensagem.
+                    let select = format!(
+                        r#"
+                        SELECT m.id, m.sender, m.receiver, m.body, m.sent_at
+                        FROM pending_messages p
+                        JOIN messages m ON m.id = p.message_id
+                        WHERE p.receiver = ? AND p.message_id IN ({ids})
+                        ORDER BY m.id ASC {}
+                        "#,
+                        $lock_rows,
+                    );
+
+                    let mut query = sqlx::query_as(&select).bind(receiver);
+
+                    for id in message_ids {
+                        query = query.bind(id);
+                    }
+
+                    let rows: Vec<(i64, String, String, String, i64)> = query
+                        .fetch_all(&mut *tx)
+                        .await?;
+
+                    if rows.is_empty() {
+                        return Ok(Vec::new());
+                    }
+
+                    let delete = format!(
+                        "DELETE FROM pending_messages WHERE receiver = ? AND message_id IN ({})",
+                        placeholders(rows.len()),
+                    );
+
+                    let mut query = sqlx::query(&delete).bind(receiver);
+
+                    for (id, ..) in &rows {
+                        query = query.bind(id);
+                    }
+
+                    query.execute(&mut *tx).await?;
+                    tx.commit().await?;
+
+                    Ok(rows.into_iter().map(stored_message).collect())
+                }
+
+                async fn insert_session
+                (
+                    &self,
+                    id: &str,
+                    username: &str,
+                    expires_at: i64,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query(
+                        "INSERT INTO sessions (id, username, expires_at) VALUES (?, ?, ?)",
+                    )
+                    .bind(id)
+                    .bind(username)
+                    .bind(expires_at)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(())
+                }
+
+                async fn get_session
+                (
+                    &self,
+                    id: &str,
+                ) -> Result<Option<SessionRecord>, AuthenticateErrorType>
+                {
+                    let row: Option<(String, i64, bool)> = sqlx::query_as(
+                        "SELECT username, expires_at, revoked FROM sessions WHERE id = ?",
+                    )
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(row.map(|(username, expires_at, revoked)| SessionRecord {
+                        username,
+                        expires_at,
+                        revoked,
+                    }))
+                }
+
+                async fn revoke_session
+                (
+                    &self,
+                    id: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query("UPDATE sessions SET revoked = TRUE WHERE id = ?")
+                        .bind(id)
+                        .execute(&self.pool)
+                        .await?;
+
+                    Ok(())
+                }
+
+                async fn revoke_user_sessions
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query("UPDATE sessions SET revoked = TRUE WHERE username = ?")
+                        .bind(username)
+                        .execute(&self.pool)
+                        .await?;
+
+                    Ok(())
+                }
+
+                async fn update_password
+                (
+                    &self,
+                    username: &str,
+                    password_hash: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    let result = sqlx::query("UPDATE users SET password_hash = ? WHERE username = ?")
+                        .bind(password_hash)
+                        .bind(username)
+                        .execute(&self.pool)
+                        .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Err(AuthenticateErrorType::UserNotFound);
+                    }
+
+                    Ok(())
+                }
+
+                async fn delete_user
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    // As outras tabelas são limpas pelo ON DELETE CASCADE.
+                    let result = sqlx::query("DELETE FROM users WHERE username = ?")
+                        .bind(username)
+                        .execute(&self.pool)
+                        .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Err(AuthenticateErrorType::UserNotFound);
+                    }
+
+                    Ok(())
+                }
+
+                async fn insert_reset_code
+                (
+                    &self,
+                    username: &str,
+                    code_hash: &str,
+                    expires_at: i64,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query(
+                        "REPLACE INTO password_resets (username, code_hash, expires_at) VALUES (?, ?, ?)",
+                    )
+                    .bind(username)
+                    .bind(code_hash)
+                    .bind(expires_at)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(())
+                }
+
+                async fn get_reset_code
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Option<(String, i64)>, AuthenticateErrorType>
+                {
+                    let row = sqlx::query_as(
+                        "SELECT code_hash, expires_at FROM password_resets WHERE username = ?",
+                    )
+                    .bind(username)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(row)
+                }
+
+                async fn delete_reset_code
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query("DELETE FROM password_resets WHERE username = ?")
+                        .bind(username)
+                        .execute(&self.pool)
+                        .await?;
+
+                    Ok(())
+                }
+
+                async fn set_two_factor_secret
+                (
+                    &self,
+                    username: &str,
+                    secret: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query(
+                        "REPLACE INTO two_factor (username, secret, enabled, last_step) VALUES (?, ?, ?, 0)",
+                    )
+                    .bind(username)
+                    .bind(secret)
+                    .bind(false)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(())
+                }
+
+                async fn get_two_factor
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Option<TwoFactorRecord>, AuthenticateErrorType>
+                {
+                    let row: Option<(String, bool, i64)> = sqlx::query_as(
+                        "SELECT secret, enabled, last_step FROM two_factor WHERE username = ?",
+                    )
+                    .bind(username)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(row.map(|(secret, enabled, last_step)| TwoFactorRecord {
+                        secret,
+                        enabled,
+                        last_step,
+                    }))
+                }
+
+                async fn enable_two_factor
+                (
+                    &self,
+                    username: &str,
+                    recovery_hashes: &[String],
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    let mut tx = self.pool.begin().await?;
+
+                    sqlx::query("UPDATE two_factor SET enabled = ? WHERE username = ?")
+                        .bind(true)
+                        .bind(username)
+                        .execute(&mut *tx)
+                        .await?;
+
+                    sqlx::query("DELETE FROM recovery_codes WHERE username = ?")
+                        .bind(username)
+                        .execute(&mut *tx)
+                        .await?;
+
+                    for code_hash in recovery_hashes {
+                        sqlx::query("INSERT INTO recovery_codes (username, code_hash) VALUES (?, ?)")
+                            .bind(username)
+                            .bind(code_hash)
+                            .execute(&mut *tx)
+                            .await?;
+                    }
+
+                    tx.commit().await?;
+                    Ok(())
+                }
+
+                async fn use_two_factor_step
+                (
+                    &self,
+                    username: &str,
+                    step: i64,
+                ) -> Result<bool, AuthenticateErrorType>
+                {
+                    // A condição no WHERE torna a checagem atômica: dois
+                    // logins simultâneos com o mesmo código não passam.
+                    let result = sqlx::query(
+                        "UPDATE two_factor SET last_step = ? WHERE username = ? AND last_step < ?",
+                    )
+                    .bind(step)
+                    .bind(username)
+                    .bind(step)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn use_recovery_code
+                (
+                    &self,
+                    username: &str,
+                    code_hash: &str,
+                ) -> Result<bool, AuthenticateErrorType>
+                {
+                    let result = sqlx::query(
+                        "DELETE FROM recovery_codes WHERE username = ? AND code_hash = ?",
+                    )
+                    .bind(username)
+                    .bind(code_hash)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(result.rows_affected() > 0)
+                }
+
+                async fn delete_two_factor
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    let mut tx = self.pool.begin().await?;
+
+                    sqlx::query("DELETE FROM recovery_codes WHERE username = ?")
+                        .bind(username)
+                        .execute(&mut *tx)
+                        .await?;
+
+                    sqlx::query("DELETE FROM two_factor WHERE username = ?")
+                        .bind(username)
+                        .execute(&mut *tx)
+                        .await?;
+
+                    tx.commit().await?;
+                    Ok(())
+                }
+
+                async fn get_role
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Option<Role>, AuthenticateErrorType>
+                {
+                    let role: Option<String> = sqlx::query_scalar("SELECT role FROM users WHERE username = ?")
+                        .bind(username)
+                        .fetch_optional(&self.pool)
+                        .await?;
+
+                    // Um valor desconhecido na coluna não dá nenhuma
+                    // permissão extra.
+                    Ok(role.map(|r| r.parse().unwrap_or_default()))
+                }
+
+                async fn set_role
+                (
+                    &self,
+                    username: &str,
+                    role: Role,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    let result = sqlx::query("UPDATE users SET role = ? WHERE username = ?")
+                        .bind(role.as_str())
+                        .bind(username)
+                        .execute(&self.pool)
+                        .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Err(AuthenticateErrorType::UserNotFound);
+                    }
+
+                    Ok(())
+                }
+
+                async fn set_ban
+                (
+                    &self,
+                    username: &str,
+                    ban: Option<&BanRecord>,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    let result = sqlx::query(
+                        "UPDATE users SET banned = ?, banned_until = ?, ban_reason = ? WHERE username = ?",
+                    )
+                    .bind(ban.is_some())
+                    .bind(ban.and_then(|b| b.until))
+                    .bind(ban.map(|b| b.reason.as_str()))
+                    .bind(username)
+                    .execute(&self.pool)
+                    .await?;
+
+                    if result.rows_affected() == 0 {
+                        return Err(AuthenticateErrorType::UserNotFound);
+                    }
+
+                    Ok(())
+                }
+
+                async fn get_ban
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Option<BanRecord>, AuthenticateErrorType>
+                {
+                    let row: Option<(bool, Option<i64>, Option<String>)> = sqlx::query_as(
+                        "SELECT banned, banned_until, ban_reason FROM users WHERE username = ?",
+                    )
+                    .bind(username)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(row
+                        .filter(|(banned, _, _)| *banned)
+                        .map(|(_, until, reason)| BanRecord {
+                            reason: reason.unwrap_or_default(),
+                            until,
+                        }))
+                }
+
+                async fn set_last_seen
+                (
+                    &self,
+                    username: &str,
+                    last_seen: i64,
+                ) -> Result<(), AuthenticateErrorType>
+                {
+                    sqlx::query("UPDATE users SET last_seen = ? WHERE username = ?")
+                        .bind(last_seen)
+                        .bind(username)
+                        .execute(&self.pool)
+                        .await?;
+
+                    Ok(())
+                }
+
+                async fn get_last_seen
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Option<i64>, AuthenticateErrorType>
+                {
+                    let last_seen: Option<Option<i64>> = sqlx::query_scalar(
+                        "SELECT last_seen FROM users WHERE username = ?",
+                    )
+                    .bind(username)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(last_seen.flatten())
+                }
+
+                async fn insert_message
+                (
+                    &self,
+                    sender: &str,
+                    receiver: &str,
+                    body: &str,
+                    sent_at: i64,
+                ) -> Result<i64, AuthenticateErrorType>
+                {
+                    let result = sqlx::query(
+                        "INSERT INTO messages (sender, receiver, body, sent_at) VALUES (?, ?, ?, ?)",
+                    )
+                    .bind(sender)
+                    .bind(receiver)
+                    .bind(body)
+                    .bind(sent_at)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(last_insert_id(&result))
+                }
+
+                async fn get_conversation
+                (
+                    &self,
+                    a: &str,
+                    b: &str,
+                    before: Option<i64>,
+                    limit: u32,
+                ) -> Result<Vec<StoredMessage>, AuthenticateErrorType>
+                {
+                    let rows: Vec<(i64, String, String, String, i64)> = sqlx::query_as(
+                        r#"
+                        SELECT id, sender, receiver, body, sent_at FROM messages
+                        WHERE ((sender = ? AND receiver = ?) OR (sender = ? AND receiver = ?))
+                            AND id < ?
+                        ORDER BY id DESC
+                        LIMIT ?
+                        "#,
+                    )
+                    .bind(a)
+                    .bind(b)
+                    .bind(b)
+                    .bind(a)
+                    .bind(before.unwrap_or(i64::MAX))
+                    .bind(limit as i64)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(rows.into_iter().map(stored_message).collect())
+                }
+
+                async fn get_message
+                (
+                    &self,
+                    id: i64,
+                ) -> Result<Option<StoredMessage>, AuthenticateErrorType>
+                {
+                    let row: Option<(i64, String, String, String, i64)> = sqlx::query_as(
+                        "SELECT id, sender, receiver, body, sent_at FROM messages WHERE id = ?",
+                    )
+                    .bind(id)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(row.map(stored_message))
+                }
+
+                async fn advance_read_cursor
+                (
+                    &self,
+                    reader: &str,
+                    peer: &str,
+                    last_read_id: i64,
+                    read_at: i64,
+                ) -> Result<bool, AuthenticateErrorType>
+                {
+                    // Cada comando é atômico, então dois pedidos ao mesmo
+                    // tempo não fazem o cursor voltar.
+                    let inserted = sqlx::query(
+                        concat!($insert_ignore, " INTO read_cursors (reader, peer, last_read_id, read_at) VALUES (?, ?, ?, ?)"),
+                    )
+                    .bind(reader)
+                    .bind(peer)
+                    .bind(last_read_id)
+                    .bind(read_at)
+                    .execute(&self.pool)
+                    .await?;
+
+                    if inserted.rows_affected() == 1 {
+                        return Ok(true);
+                    }
+
+                    let updated = sqlx::query(
+                        r#"
+                        UPDATE read_cursors SET last_read_id = ?, read_at = ?
+                        WHERE reader = ? AND peer = ? AND last_read_id < ?
+                        "#,
+                    )
+                    .bind(last_read_id)
+                    .bind(read_at)
+                    .bind(reader)
+                    .bind(peer)
+                    .bind(last_read_id)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(updated.rows_affected() == 1)
+                }
+
+                async fn get_unread_counts
+                (
+                    &self,
+                    reader: &str,
+                ) -> Result<Vec<(String, i64)>, AuthenticateErrorType>
+                {
+                    let counts = sqlx::query_as(
+                        r#"
+                        SELECT m.sender, COUNT(*) FROM messages m
+                        LEFT JOIN read_cursors c ON c.reader = m.receiver AND c.peer = m.sender
+                        WHERE m.receiver = ? AND m.id > COALESCE(c.last_read_id, 0)
+                        GROUP BY m.sender
+                        ORDER BY m.sender
+                        "#,
+                    )
+                    .bind(reader)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(counts)
+                }
+
+                async fn insert_room
+                (
+                    &self,
+                    name: &str,
+                    creator: &str,
+                    created_at: i64,
+                ) -> Result<i64, AuthenticateErrorType>
+                {
+                    let mut tx = self.pool.begin().await?;
+
+                    let id = match sqlx::query("INSERT INTO rooms (name, created_at) VALUES (?, ?)")
+                        .bind(name)
+                        .bind(created_at)
+                        .execute(&mut *tx)
+                        .await {
+                        Ok(result) => last_insert_id(&result),
+                        Err(e) if is_unique_violation(&e) => return Err(AuthenticateErrorType::RoomAlreadyExists),
+                        Err(e) => return Err(e.into()),
+                    };
+
+                    sqlx::query("INSERT INTO room_members (room_id, username, joined_at) VALUES (?, ?, ?)")
+                        .bind(id)
+                        .bind(creator)
+                        .bind(created_at)
+                        .execute(&mut *tx)
+                        .await?;
+
+                    tx.commit().await?;
+
+                    Ok(id)
+                }
+
+                async fn get_room
+                (
+                    &self,
+                    name: &str,
+                ) -> Result<Option<RoomRecord>, AuthenticateErrorType>
+                {
+                    let row: Option<(i64, String, i64)> = sqlx::query_as(
+                        "SELECT id, name, created_at FROM rooms WHERE name = ?",
+                    )
+                    .bind(name)
+                    .fetch_optional(&self.pool)
+                    .await?;
+
+                    Ok(row.map(|(id, name, created_at)| RoomRecord {
+                        id,
+                        name,
+                        created_at,
+                    }))
+                }
+
+                async fn list_rooms
+                (
+                    &self,
+                    username: &str,
+                ) -> Result<Vec<RoomSummary>, AuthenticateErrorType>
+                {
+                    let rows: Vec<(String, i64, i64)> = sqlx::query_as(
+                        r#"
+                        SELECT r.name,
+                            COUNT(m.username),
+                            MAX(CASE WHEN m.username = ? THEN 1 ELSE 0 END)
+                        FROM rooms r
+                        LEFT JOIN room_members m ON m.room_id = r.id
+                        GROUP BY r.id, r.name
+                        ORDER BY r.name
+                        "#,
+                    )
+                    .bind(username)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(rows
+                        .into_iter()
+                        .map(|(name, members, joined)| RoomSummary {
+                            name,
+                            members,
+                            joined: joined == 1,
+                        })
+                        .collect())
+                }
+
+                async fn insert_room_member
+                (
+                    &self,
+                    room_id: i64,
+                    username: &str,
+                    joined_at: i64,
+                ) -> Result<bool, AuthenticateErrorType>
+                {
+                    let result = sqlx::query(
+                        concat!($insert_ignore, " INTO room_members (room_id, username, joined_at) VALUES (?, ?, ?)"),
+                    )
+                    .bind(room_id)
+                    .bind(username)
+                    .bind(joined_at)
+                    .execute(&self.pool)
+                    .await?;
+
+                    Ok(result.rows_affected() == 1)
+                }
+
+                async fn delete_room_member
+                (
+                    &self,
+                    room_id: i64,
+                    username: &str,
+                ) -> Result<bool, AuthenticateErrorType>
+                {
+                    let mut tx = self.pool.begin().await?;
+
+                    let result = sqlx::query(
+                        "DELETE FROM room_members WHERE room_id = ? AND username = ?",
+                    )
+                    .bind(room_id)
+                    .bind(username)
+                    .execute(&mut *tx)
+                    .await?;
+
+                    sqlx::query(
+                        r#"
+                        DELETE FROM pending_room_messages
+                        WHERE receiver = ?
+                            AND message_id IN (SELECT id FROM room_messages WHERE room_id = ?)
+                        "#,
+                    )
+                    .bind(username)
+                    .bind(room_id)
+                    .execute(&mut *tx)
+                    .await?;
+
+                    tx.commit().await?;
+
+                    Ok(result.rows_affected() == 1)
+                }
+
+                async fn get_room_members
+                (
+                    &self,
+                    room_id: i64,
+                ) -> Result<Vec<String>, AuthenticateErrorType>
+                {
+                    let members = sqlx::query_scalar(
+                        "SELECT username FROM room_members WHERE room_id = ? ORDER BY username",
+                    )
+                    .bind(room_id)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(members)
+                }
+
+                async fn insert_room_message
+                (
+                    &self,
+                    room_id: i64,
+                    sender: &str,
+                    body: &str,
+                    sent_at: i64,
+                ) -> Result<(i64, Vec<String>), AuthenticateErrorType>
+                {
+                    let mut tx = self.pool.begin().await?;
+
+                    let result = sqlx::query(
+                        "INSERT INTO room_messages (room_id, sender, body, sent_at) VALUES (?, ?, ?, ?)",
+                    )
+                    .bind(room_id)
+                    .bind(sender)
+                    .bind(body)
+                    .bind(sent_at)
+                    .execute(&mut *tx)
+                    .await?;
+
+                    let id = last_insert_id(&result);
+
+                    sqlx::query(
+                        r#"
+                        INSERT INTO pending_room_messages (message_id, receiver)
+                        SELECT ?, m.username FROM room_members m
+                        JOIN users u ON u.username = m.username
+                        WHERE m.room_id = ? AND m.username <> ?
+                        AND NOT (u.banned AND (u.banned_until IS NULL OR u.banned_until > ?))
+                        "#,
+                    )
+                    .bind(id)
+                    .bind(room_id)
+                    .bind(sender)
+                    .bind(sent_at)
+                    .execute(&mut *tx)
+                    .await?;
+
+                    let recipients = sqlx::query_scalar(
+                        "SELECT receiver FROM pending_room_messages WHERE message_id = ? ORDER BY receiver",
+                    )
+                    .bind(id)
+                    .fetch_all(&mut *tx)
+                    .await?;
+
+                    tx.commit().await?;
+
+                    Ok((id, recipients))
+                }
+
+                async fn get_stored_room_messages
+                (
+                    &self,
+                    receiver: &str,
+                ) -> Result<Vec<StoredRoomMessage>, AuthenticateErrorType>
+                {
+                    let rows: Vec<(i64, String, String, String, i64)> = sqlx::query_as(
+                        r#"
+                        SELECT m.id, r.name, m.sender, m.body, m.sent_at
+                        FROM pending_room_messages p
+                        JOIN room_messages m ON m.id = p.message_id
+                        JOIN rooms r ON r.id = m.room_id
+                        WHERE p.receiver = ?
+                        ORDER BY m.id ASC
+                        "#,
+                    )
+                    .bind(receiver)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(rows
+                        .into_iter()
+                        .map(|(id, room, sender, body, sent_at)| StoredRoomMessage {
+                            id,
+                            room,
+                            sender,
+                            body,
+                            sent_at,
+                        })
+                        .collect())
+                }
+
+                async fn delete_stored_room_messages
+                (
+                    &self,
+                    message_ids: &[i64],
+                    receiver: &str,
+                ) -> Result<u64, AuthenticateErrorType>
+                {
+                    if message_ids.is_empty() {
+                        return Ok(0);
+                    }
+
+                    let delete = format!(
+                        "DELETE FROM pending_room_messages WHERE receiver = ? AND message_id IN ({})",
+                        placeholders(message_ids.len()),
+                    );
+
+                    let mut query = sqlx::query(&delete).bind(receiver);
+
+                    for id in message_ids {
+                        query = query.bind(id);
+                    }
+
+                    Ok(query.execute(&self.pool).await?.rows_affected())
+                }
+
+                async fn insert_post
+                (
+                    &self,
+                    author: &str,
+                    body: &str,
+                    created_at: i64,
+                ) -> Result<i64, AuthenticateErrorType>
+                {
+                    let result = sqlx::query("INSERT INTO posts (author, body, created_at) VALUES (?, ?, ?)")
+                        .bind(author)
+                        .bind(body)
+                        .bind(created_at)
+                        .execute(&self.pool)
+                        .await?;
+
+                    Ok(last_insert_id(&result))
+                }
+
+                async fn get_posts
+                (
+                    &self,
+                    before: Option<i64>,
+                    limit: u32,
+                ) -> Result<Vec<StoredPost>, AuthenticateErrorType>
+                {
+                    let rows: Vec<(i64, String, String, i64)> = sqlx::query_as(
+                        "SELECT id, author, body, created_at FROM posts WHERE id < ? ORDER BY id DESC LIMIT ?",
+                    )
+                    .bind(before.unwrap_or(i64::MAX))
+                    .bind(limit as i64)
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(rows.into_iter().map(stored_post).collect())
+                }
+
+                async fn username_conflicts
+                (
+                    &self,
+                ) -> Result<Vec<UsernameConflict>, AuthenticateErrorType>
+                {
+                    let rows: Vec<(String, String)> = sqlx::query_as(
+                        "SELECT username, reason FROM username_conflicts ORDER BY username",
+                    )
+                    .fetch_all(&self.pool)
+                    .await?;
+
+                    Ok(rows
+                        .into_iter()
+                        .map(|(username, reason)| UsernameConflict { username, reason })
+                        .collect())
+                }
+            }
+        };
+    };
+}
+
+pub(crate) use impl_sql_storage;
+
+// "?, ?, ..., ?" com n parâmetros, para um IN (...).
+pub(super) fn placeholders
+(
+    n: usize,
+) -> String
+{
+    vec!["?"; n].join(", ")
+}
+
+// Linha (id, sender, receiver, body, sent_at) da tabela messages.
+pub(super) fn stored_message
+(
+    (id, sender, receiver, body, sent_at): (i64, String, String, String, i64),
+) -> StoredMessage
+{
+    StoredMessage {
+        id,
+        sender,
+        receiver,
+        body,
+        sent_at,
+    }
+}
+
+pub(super) fn stored_post
+(
+    (id, author, body, created_at): (i64, String, String, i64),
+) -> StoredPost
+{
+    StoredPost {
+        id,
+        author,
+        body,
+        created_at,
+    }
+}
@@ -0,0 +1,71 @@
+/*
+Backend de armazenamento em SQLite. Serve para rodar o
+servidor (e seus testes) sem precisar de um serviço mysql.
+As tabelas são criadas pelas migrations em
+/users/migrations/sqlite.
+*/
+
+use std::str::FromStr;
+
+use sqlx::{
+    sqlite::{SqlitePool, SqlitePoolOptions, SqliteConnectOptions},
+};
+
+use error::{
+    AuthenticateErrorType,
+};
+
+use crate::storage::{
+    PoolConfig,
+    sql::impl_sql_storage,
+};
+
+#[derive(Clone)]
+pub struct SqliteStorage {
+    pool: SqlitePool,
+}
+
+impl SqliteStorage {
+    // Abre (criando se preciso) o arquivo em db_url.
+    pub async fn connect
+    (
+        db_url: &str,
+        config: PoolConfig,
+    ) -> Result<Self, AuthenticateErrorType>
+    {
+        let options = SqliteConnectOptions::from_str(db_url)?
+            .create_if_missing(true)
+            .foreign_keys(true);
+
+        // Uma database em memória só existe dentro da conexão
+        // que a criou, então o pool precisa manter uma única
+        // conexão viva para sempre.
+        let in_memory = db_url.contains(":memory:") || db_url.contains("mode=memory");
+
+        let (max_connections, idle_timeout) = match in_memory {
+            true => (1, None),
+            false => (config.max_connections, config.idle_timeout),
+        };
+
+        let pool = SqlitePoolOptions::new()
+            .max_connections(max_connections)
+            .acquire_timeout(config.acquire_timeout)
+            .idle_timeout(idle_timeout)
+            .max_lifetime(None)
+            .connect_with(options)
+            .await?;
+
+        Ok(Self { pool })
+    }
+}
+
+// As consultas são as de sql.rs; aqui ficam só as
+// diferenças do dialeto.
+impl_sql_storage!(
+    SqliteStorage,
+    sqlx::Sqlite,
+    sqlite,
+    "INSERT OR IGNORE",
+    "",
+    |result| result.last_insert_rowid(),
+);
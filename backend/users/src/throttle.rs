@@ -0,0 +1,147 @@
+/*
+Proteção contra força bruta no login. Falhas de login são
+contadas por conta (username) e por ip. Ao atingir o limite
+configurado a conta (ou o ip) fica bloqueada por um tempo que
+dobra a cada nova falha, até um máximo:
+
+bloqueio = min(lockout * 2^(falhas - limite), max_lockout)
+
+Os contadores ficam apenas em memória e são zerados depois
+de max_lockout sem nenhuma falha. Um login bem sucedido zera
+apenas o contador da conta; o do ip continua, para que uma
+conta válida não sirva para "limpar" as tentativas de um ip.
+*/
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::Mutex,
+    time::Duration,
+};
+
+// Limites de falhas de login. Um limite igual a 0
+// desativa a contagem correspondente.
+#[derive(Debug, Clone)]
+pub struct ThrottleConfig {
+    pub max_failures_per_account: u32,
+    pub max_failures_per_ip: u32,
+    // Duração do primeiro bloqueio.
+    pub lockout: Duration,
+    // Duração máxima de um bloqueio.
+    pub max_lockout: Duration,
+}
+
+impl Default for ThrottleConfig {
+    fn default() -> Self {
+        Self {
+            max_failures_per_account: 5,
+            max_failures_per_ip: 20,
+            lockout: Duration::from_secs(30),
+            max_lockout: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+#[derive(Default)]
+struct Failures {
+    count: u32,
+    last_failure: i64,
+    locked_until: i64,
+}
+
+// Os Mutex aqui são os da std, pois nenhum deles é
+// mantido travado através de um .await.
+pub struct LoginThrottle {
+    config: ThrottleConfig,
+    accounts: Mutex<HashMap<String, Failures>>,
+    ips: Mutex<HashMap<IpAddr, Failures>>,
+}
+
+// Acima desse número de entradas as já expiradas são
+// descartadas, para que usernames inventados não façam
+// os mapas crescerem sem limite.
+const PRUNE_THRESHOLD: usize = 1024;
+
+impl LoginThrottle {
+    pub fn new(config: ThrottleConfig) -> Self {
+        Self {
+            config,
+            accounts: Mutex::new(HashMap::new()),
+            ips: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Ok se o login pode ser tentado agora, ou Err com
+    // quantos segundos faltam para o fim do bloqueio.
+    pub fn check(&self, username: &str, ip: IpAddr, now: i64) -> Result<(), u64> {
+        let account = remaining(self.accounts.lock().unwrap().get(username), now);
+        let ip = remaining(self.ips.lock().unwrap().get(&ip), now);
+
+        match account.max(ip) {
+            0 => Ok(()),
+            secs => Err(secs),
+        }
+    }
+
+    // Registra uma tentativa de login que falhou.
+    pub fn failure(&self, username: &str, ip: IpAddr, now: i64) {
+        let max = self.config.max_failures_per_account;
+        self.record(&self.accounts, username.to_string(), max, now);
+
+        let max = self.config.max_failures_per_ip;
+        self.record(&self.ips, ip, max, now);
+    }
+
+    // Login bem sucedido: esquece as falhas da conta.
+    pub fn success(&self, username: &str) {
+        self.accounts.lock().unwrap().remove(username);
+    }
+
+    fn record<K>
+    (
+        &self,
+        map: &Mutex<HashMap<K, Failures>>,
+        key: K,
+        max: u32,
+        now: i64,
+    )
+    where
+        K: Eq + Hash,
+    {
+        if max == 0 {
+            return;
+        }
+
+        let window = self.config.max_lockout.as_secs() as i64;
+        let mut map = map.lock().unwrap();
+
+        if map.len() > PRUNE_THRESHOLD {
+            map.retain(|_, f| now - f.last_failure <= window || f.locked_until > now);
+        }
+
+        let failures = map.entry(key).or_default();
+
+        if now - failures.last_failure > window && failures.locked_until <= now {
+            *failures = Failures::default();
+        }
+
+        failures.count += 1;
+        failures.last_failure = now;
+
+        if failures.count >= max {
+            let exponent = (failures.count - max).min(32);
+            let lockout = self.config.lockout.as_secs()
+                .saturating_mul(1u64 << exponent)
+                .min(self.config.max_lockout.as_secs());
+
+            failures.locked_until = now + lockout as i64;
+        }
+    }
+}
+
+fn remaining(failures: Option<&Failures>, now: i64) -> u64 {
+    failures
+        .map(|f| (f.locked_until - now).max(0) as u64)
+        .unwrap_or(0)
+}
@@ -0,0 +1,140 @@
+/*
+Tokens de sessão, usados para reconectar sem reenviar a
+senha. Formato de um token:
+
+<id>.<expira_em>.<assinatura>
+
+id         -> 16 bytes aleatórios em hexadecimal, chave da
+              sessão guardada na database
+expira_em  -> timestamp unix (segundos)
+assinatura -> HMAC-SHA256, em hexadecimal, de
+              "<id>.<username>.<expira_em>" com o segredo
+              do servidor
+
+A assinatura impede que alguém forje ou altere um token
+sem conhecer o segredo; a sessão guardada na database
+permite revogar um token antes de ele expirar.
+*/
+
+use std::time::Duration;
+
+use hmac::{Hmac, Mac};
+
+use sha2::Sha256;
+
+use rand::{
+    RngCore,
+    rngs::OsRng,
+};
+
+use crate::hex;
+
+type HmacSha256 = Hmac<Sha256>;
+
+// Token recém emitido.
+pub struct IssuedToken {
+    pub id: String,
+    // Dono do token.
+    pub username: String,
+    pub token: String,
+    pub expires_at: i64,
+}
+
+// Partes de um token recebido do client, ainda não verificado.
+pub struct ParsedToken<'a> {
+    pub id: &'a str,
+    pub expires_at: i64,
+    signature: &'a str,
+}
+
+pub struct TokenSigner {
+    secret: Vec<u8>,
+    ttl: Duration,
+}
+
+impl TokenSigner {
+    pub fn new(secret: &[u8], ttl: Duration) -> Self {
+        Self {
+            secret: secret.to_vec(),
+            ttl,
+        }
+    }
+
+    // Segredo aleatório, usado quando nenhum foi configurado.
+    // Tokens assinados com ele deixam de valer quando o
+    // servidor reinicia.
+    pub fn random(ttl: Duration) -> Self {
+        let mut secret = [0u8; 32];
+        OsRng.fill_bytes(&mut secret);
+        Self::new(&secret, ttl)
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.secret)
+            .expect("HMAC aceita segredos de qualquer tamanho")
+    }
+
+    fn payload(id: &str, username: &str, expires_at: i64) -> String {
+        format!("{id}.{username}.{expires_at}")
+    }
+
+    pub fn issue(&self, username: &str, now: i64) -> IssuedToken {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+
+        let id = hex(&bytes);
+        let expires_at = now + self.ttl.as_secs() as i64;
+
+        let mut mac = self.mac();
+        mac.update(Self::payload(&id, username, expires_at).as_bytes());
+        let signature = hex(&mac.finalize().into_bytes());
+
+        IssuedToken {
+            username: username.to_string(),
+            token: format!("{id}.{expires_at}.{signature}"),
+            id,
+            expires_at,
+        }
+    }
+
+    pub fn parse(token: &str) -> Option<ParsedToken<'_>> {
+        let mut parts = token.split('.');
+
+        let id = parts.next()?;
+        let expires_at = parts.next()?.parse().ok()?;
+        let signature = parts.next()?;
+
+        if parts.next().is_some() {
+            return None;
+        }
+
+        Some(ParsedToken {
+            id,
+            expires_at,
+            signature,
+        })
+    }
+
+    // Confere, em tempo constante, se a assinatura do token
+    // foi gerada por este servidor para o username dado.
+    pub fn verify(&self, token: &ParsedToken, username: &str) -> bool {
+        let Some(signature) = unhex(token.signature) else {
+            return false;
+        };
+
+        let mut mac = self.mac();
+        mac.update(Self::payload(token.id, username, token.expires_at).as_bytes());
+        mac.verify_slice(&signature).is_ok()
+    }
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
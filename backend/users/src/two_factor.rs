@@ -0,0 +1,253 @@
+/*
+Autenticação em dois fatores com TOTP (RFC 6238), compatível
+com os apps autenticadores comuns: HMAC-SHA1, códigos de 6
+dígitos e passos de 30 segundos.
+
+code = HOTP(segredo, floor(agora / 30)) mod 10^6
+
+Para tolerar relógios levemente dessincronizados o código do
+passo anterior e o do seguinte também são aceitos. O último
+passo usado fica guardado na database, então um mesmo código
+não pode ser usado duas vezes.
+
+Quem perder o autenticador pode entrar com um dos códigos de
+recuperação gerados ao ativar o 2FA. Cada um vale uma única
+vez e só o hash sha256 deles é guardado.
+
+Todas as funções que dependem do horário recebem o timestamp
+como parâmetro, para que possam ser testadas com um relógio fixo.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::Duration,
+};
+
+use data_encoding::BASE32_NOPAD;
+
+use hmac::{Hmac, Mac};
+
+use percent_encoding::{
+    AsciiSet,
+    NON_ALPHANUMERIC,
+    utf8_percent_encode,
+};
+
+use rand::{
+    RngCore,
+    rngs::OsRng,
+};
+
+use sha1::Sha1;
+
+use sha2::{Digest, Sha256};
+
+use crate::hex;
+
+type HmacSha1 = Hmac<Sha1>;
+
+// Duração de um passo, em segundos.
+pub const STEP: i64 = 30;
+
+pub const DIGITS: u32 = 6;
+
+// Quantos passos antes e depois do atual são aceitos.
+const SKEW: i64 = 1;
+
+// Caracteres escapados no label e no issuer do otpauth URI.
+const URI_ESCAPE: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_');
+
+#[derive(Debug, Clone)]
+pub struct TwoFactorConfig {
+    // Nome do serviço mostrado nos apps autenticadores.
+    pub issuer: String,
+    // Tempo para responder ao desafio depois da senha.
+    pub challenge_ttl: Duration,
+    // Quantos códigos de recuperação são gerados.
+    pub recovery_codes: usize,
+}
+
+impl Default for TwoFactorConfig {
+    fn default() -> Self {
+        Self {
+            issuer: String::from("dw_web_server"),
+            challenge_ttl: Duration::from_secs(5 * 60),
+            recovery_codes: 10,
+        }
+    }
+}
+
+// Segredo compartilhado entre o servidor e o autenticador.
+pub struct Totp {
+    secret: Vec<u8>,
+}
+
+impl Totp {
+    // Segredo novo de 160 bits, o tamanho recomendado
+    // pela RFC 4226 para o HMAC-SHA1.
+    pub fn generate() -> Self {
+        let mut secret = vec![0u8; 20];
+        OsRng.fill_bytes(&mut secret);
+        Self { secret }
+    }
+
+    pub fn new(secret: &[u8]) -> Self {
+        Self {
+            secret: secret.to_vec(),
+        }
+    }
+
+    // Segredo em base32, como é guardado na database e
+    // mostrado ao usuário.
+    pub fn from_base32(secret: &str) -> Option<Self> {
+        BASE32_NOPAD
+            .decode(secret.as_bytes())
+            .ok()
+            .map(|secret| Self { secret })
+    }
+
+    pub fn secret_base32(&self) -> String {
+        BASE32_NOPAD.encode(&self.secret)
+    }
+
+    // URI lido (normalmente por um QR code) pelos apps
+    // autenticadores.
+    pub fn uri(&self, issuer: &str, username: &str) -> String {
+        let issuer = utf8_percent_encode(issuer, URI_ESCAPE).to_string();
+        let username = utf8_percent_encode(username, URI_ESCAPE);
+
+        format!(
+            "otpauth://totp/{issuer}:{username}?secret={}&issuer={issuer}\
+            &algorithm=SHA1&digits={DIGITS}&period={STEP}",
+            self.secret_base32(),
+        )
+    }
+
+    // Passo que contém o timestamp now.
+    pub fn step(now: i64) -> i64 {
+        now.div_euclid(STEP)
+    }
+
+    pub fn code_at(&self, step: i64) -> String {
+        let mut mac = HmacSha1::new_from_slice(&self.secret)
+            .expect("HMAC aceita segredos de qualquer tamanho");
+        mac.update(&(step as u64).to_be_bytes());
+        let hash = mac.finalize().into_bytes();
+
+        // Truncamento dinâmico da RFC 4226.
+        let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+        let binary = u32::from_be_bytes([
+            hash[offset] & 0x7f,
+            hash[offset + 1],
+            hash[offset + 2],
+            hash[offset + 3],
+        ]);
+
+        format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+    }
+
+    // Código válido no instante now.
+    pub fn code(&self, now: i64) -> String {
+        self.code_at(Self::step(now))
+    }
+
+    // Passo ao qual code corresponde, se ele for válido em
+    // now (considerando a tolerância de SKEW passos).
+    pub fn matching_step(&self, code: &str, now: i64) -> Option<i64> {
+        let current = Self::step(now);
+
+        (current - SKEW..=current + SKEW)
+            .find(|&step| constant_time_eq(self.code_at(step).as_bytes(), code.as_bytes()))
+    }
+}
+
+// Códigos TOTP são sempre DIGITS dígitos; qualquer outra
+// coisa é tratada como código de recuperação.
+pub fn is_totp_code(code: &str) -> bool {
+    code.len() == DIGITS as usize && code.bytes().all(|b| b.is_ascii_digit())
+}
+
+// Código de recuperação no formato xxxxx-xxxxx (hexadecimal).
+pub fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    OsRng.fill_bytes(&mut bytes);
+
+    let code = hex(&bytes);
+    format!("{}-{}", &code[..5], &code[5..])
+}
+
+// Hash guardado de um código de recuperação. O código é
+// normalizado antes, então o hífen e maiúsculas não importam.
+pub fn hash_recovery_code(code: &str) -> String {
+    let normalized: String = code
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+
+    hex(&Sha256::digest(normalized.as_bytes()))
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+// Login que já passou pela senha e espera o segundo fator.
+struct Challenge {
+    username: String,
+    expires_at: i64,
+}
+
+// Desafios pendentes, apenas em memória. O Mutex é o
+// da std, pois nunca é mantido travado através de um .await.
+pub struct LoginChallenges {
+    ttl: Duration,
+    pending: Mutex<HashMap<String, Challenge>>,
+}
+
+impl LoginChallenges {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Cria um desafio para username, retornando
+    // (id do desafio, expira_em).
+    pub fn issue(&self, username: &str, now: i64) -> (String, i64) {
+        let mut bytes = [0u8; 16];
+        OsRng.fill_bytes(&mut bytes);
+
+        let id = hex(&bytes);
+        let expires_at = now + self.ttl.as_secs() as i64;
+
+        let mut pending = self.pending.lock().unwrap();
+        pending.retain(|_, c| c.expires_at > now);
+        pending.insert(id.clone(), Challenge {
+            username: username.to_string(),
+            expires_at,
+        });
+
+        (id, expires_at)
+    }
+
+    // Dono do desafio, se ele existir e não tiver expirado.
+    pub fn owner(&self, id: &str, now: i64) -> Option<String> {
+        self.pending
+            .lock()
+            .unwrap()
+            .get(id)
+            .filter(|c| c.expires_at > now)
+            .map(|c| c.username.clone())
+    }
+
+    // Descarta o desafio, depois de respondido.
+    pub fn remove(&self, id: &str) {
+        self.pending.lock().unwrap().remove(id);
+    }
+}
@@ -0,0 +1,111 @@
+/*
+Indicadores de "digitando", nunca guardados na database.
+
+Enquanto alguém digita para um destinatário o client manda
+ClientProtocol::Typing repetidamente. Para que um client mal
+comportado não encha o channel do destinatário, no máximo um
+aviso é repassado a cada interval para o mesmo par
+(remetente, destinatário); os outros só estendem o prazo.
+
+Sem nenhum Typing novo por timeout o indicador expira e o
+destinatário é avisado de que o remetente parou de digitar.
+*/
+
+use std::{
+    collections::HashMap,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone)]
+pub struct TypingConfig {
+    // Tempo mínimo entre dois avisos repassados.
+    pub interval: Duration,
+    // Tempo sem Typing até o indicador expirar.
+    pub timeout: Duration,
+}
+
+impl Default for TypingConfig {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(2),
+            timeout: Duration::from_secs(5),
+        }
+    }
+}
+
+// O que fazer com um Typing recebido.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Typing {
+    // Começou a digitar: o aviso é repassado e o prazo
+    // para expirar passa a contar.
+    Started,
+    // Continua digitando e já passou interval desde o
+    // último aviso, que é repassado de novo.
+    Repeated,
+    // Continua digitando; o aviso é descartado.
+    Throttled,
+}
+
+struct Indicator {
+    last_relay: Instant,
+    expires_at: Instant,
+}
+
+// O Mutex é o da std, pois nunca é mantido travado
+// através de um .await.
+pub struct TypingIndicators {
+    config: TypingConfig,
+    // (remetente, destinatário) -> indicador ativo
+    active: Mutex<HashMap<(String, String), Indicator>>,
+}
+
+impl TypingIndicators {
+    pub fn new(config: TypingConfig) -> Self {
+        Self {
+            config,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn touch(&self, from: &str, to: &str, now: Instant) -> Typing {
+        let mut active = self.active.lock().unwrap();
+        let expires_at = now + self.config.timeout;
+
+        let key = (from.to_string(), to.to_string());
+
+        let Some(indicator) = active.get_mut(&key) else {
+            active.insert(key, Indicator {
+                last_relay: now,
+                expires_at,
+            });
+
+            return Typing::Started;
+        };
+
+        indicator.expires_at = expires_at;
+
+        if now.duration_since(indicator.last_relay) < self.config.interval {
+            return Typing::Throttled;
+        }
+
+        indicator.last_relay = now;
+        Typing::Repeated
+    }
+
+    // Quando o indicador de from para to expira, se ainda
+    // não tiver expirado em now. Um indicador expirado é
+    // descartado e None é retornado.
+    pub fn deadline(&self, from: &str, to: &str, now: Instant) -> Option<Instant> {
+        let mut active = self.active.lock().unwrap();
+        let key = (from.to_string(), to.to_string());
+
+        match active.get(&key) {
+            Some(indicator) if indicator.expires_at > now => Some(indicator.expires_at),
+            _ => {
+                active.remove(&key);
+                None
+            }
+        }
+    }
+}
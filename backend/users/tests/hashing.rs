@@ -0,0 +1,79 @@
+/*
+Testes do rehash de senhas: duas instâncias de Users com
+parâmetros de hash diferentes compartilhando o mesmo
+MemoryStorage, como um servidor antes e depois de mudar
+a configuração.
+*/
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+
+use tokio::sync::mpsc::unbounded_channel;
+
+use error::AuthenticateErrorType;
+
+use users::{
+    HashConfig,
+    MemoryStorage,
+    PasswordHashing,
+    Storage,
+    Users,
+};
+
+const IP: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+fn hashing(memory_cost: u32, pepper: Option<&str>) -> PasswordHashing {
+    PasswordHashing::new(HashConfig {
+        memory_cost,
+        time_cost: 1,
+        parallelism: 1,
+        pepper: pepper.map(|p| p.as_bytes().to_vec()),
+    })
+    .unwrap()
+}
+
+async fn login(users: &mut Users, password: &str) -> Result<(), AuthenticateErrorType> {
+    let (tx, _rx) = unbounded_channel();
+    users.authenticate_user("alice", password, IP, tx).await.map(|_| ())
+}
+
+#[tokio::test]
+async fn outdated_hash_is_upgraded_on_login() {
+    let storage = Arc::new(MemoryStorage::new());
+
+    let old = Users::new(storage.clone()).with_password_hashing(hashing(64, None));
+    old.add_user("alice", "senha-1234").await.unwrap();
+
+    let before = storage.password_hash("alice").await.unwrap().unwrap();
+    assert!(before.contains("m=64,"));
+
+    let mut new = Users::new(storage.clone()).with_password_hashing(hashing(128, Some("pepper")));
+    login(&mut new, "senha-1234").await.unwrap();
+
+    let after = storage.password_hash("alice").await.unwrap().unwrap();
+    assert!(after.contains("m=128,"));
+    assert!(after.contains("keyid="));
+
+    // O novo hash continua conferindo com os parâmetros novos...
+    login(&mut new, "senha-1234").await.unwrap();
+    assert!(matches!(
+        login(&mut new, "errada-1234").await,
+        Err(AuthenticateErrorType::PasswordMismatch)
+    ));
+
+    // ...mas não sem o pepper.
+    let mut without_pepper = Users::new(storage).with_password_hashing(hashing(128, None));
+    assert!(login(&mut without_pepper, "senha-1234").await.is_err());
+}
+
+#[tokio::test]
+async fn invalid_parameters_are_rejected() {
+    let config = HashConfig {
+        memory_cost: 4,
+        ..HashConfig::default()
+    };
+
+    assert!(PasswordHashing::new(config).is_err());
+}
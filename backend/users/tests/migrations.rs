@@ -0,0 +1,317 @@
+/*
+Testes das migrations rodando contra uma database
+sqlite em memória.
+*/
+
+use sqlx::sqlite::{SqlitePool, SqlitePoolOptions};
+
+use error::MigrationError;
+
+use users::{
+    Migrator,
+    migrations::{statements, MigrationState, SQLITE},
+};
+
+async fn memory_pool() -> SqlitePool {
+    SqlitePoolOptions::new()
+        .max_connections(1)
+        .connect("sqlite::memory:")
+        .await
+        .unwrap()
+}
+
+async fn tables(pool: &SqlitePool) -> Vec<String> {
+    sqlx::query_scalar(
+        "SELECT name FROM sqlite_master WHERE type = 'table' AND name NOT LIKE 'sqlite_%' ORDER BY name",
+    )
+    .fetch_all(pool)
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn up_applies_every_pending_migration_once() {
+    let pool = memory_pool().await;
+    let migrator = Migrator::sqlite(pool.clone());
+
+    let applied = migrator.up(None).await.unwrap();
+    assert_eq!(applied.len(), SQLITE.len());
+    assert!(tables(&pool).await.contains(&String::from("users")));
+
+    assert!(migrator.up(None).await.unwrap().is_empty());
+
+    for status in migrator.status().await.unwrap() {
+        assert!(matches!(status.state, MigrationState::Applied { .. }));
+    }
+}
+
+#[tokio::test]
+async fn down_reverts_the_last_migration() {
+    let pool = memory_pool().await;
+    let migrator = Migrator::sqlite(pool.clone());
+
+    migrator.up(None).await.unwrap();
+    let reverted = migrator.down(SQLITE.len()).await.unwrap();
+
+    assert_eq!(reverted.first(), SQLITE.last().map(|m| &m.version));
+    assert_eq!(tables(&pool).await, vec![String::from("schema_migrations")]);
+
+    for status in migrator.status().await.unwrap() {
+        assert_eq!(status.state, MigrationState::Pending);
+    }
+}
+
+#[tokio::test]
+async fn modified_migration_is_detected() {
+    let pool = memory_pool().await;
+    let migrator = Migrator::sqlite(pool.clone());
+
+    migrator.up(None).await.unwrap();
+
+    sqlx::query("UPDATE schema_migrations SET checksum = 'outro' WHERE version = 1")
+        .execute(&pool)
+        .await
+        .unwrap();
+
+    assert!(matches!(
+        migrator.up(None).await,
+        Err(MigrationError::ChecksumMismatch { version: 1 })
+    ));
+}
+
+#[tokio::test]
+async fn offline_messages_become_pending_messages() {
+    let pool = memory_pool().await;
+    let migrator = Migrator::sqlite(pool.clone());
+
+    migrator.up(Some(7)).await.unwrap();
+
+    sqlx::raw_sql(
+        r#"
+        INSERT INTO users (username, password_hash) VALUES ('alice', 'x'), ('bob', 'x');
+
+        -- Guardada antes de 0007: só existe em offline_messages.
+        INSERT INTO offline_messages (sender, receiver, message, sent_at)
+        VALUES ('alice', 'bob', 'antiga', '2024-01-01 00:00:00');
+
+        -- Guardada depois de 0007: já está no histórico.
+        INSERT INTO offline_messages (sender, receiver, message, sent_at)
+        VALUES ('alice', 'bob', 'nova', '2024-01-02 00:00:00');
+        INSERT INTO messages (sender, receiver, body, sent_at)
+        VALUES ('alice', 'bob', 'nova', 1704153601);
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    migrator.up(Some(8)).await.unwrap();
+
+    let pending: Vec<(String, i64)> = sqlx::query_as(
+        r#"
+        SELECT m.body, m.sent_at FROM pending_messages p
+        JOIN messages m ON m.id = p.message_id
+        WHERE p.receiver = 'bob'
+        ORDER BY m.sent_at
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+
+    assert_eq!(pending, vec![
+        (String::from("antiga"), 1704067200),
+        (String::from("nova"), 1704153601),
+    ]);
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 2);
+
+    migrator.down(1).await.unwrap();
+
+    let restored: Vec<String> = sqlx::query_scalar(
+        "SELECT message FROM offline_messages ORDER BY sent_at",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(restored, vec![String::from("antiga"), String::from("nova")]);
+}
+
+#[tokio::test]
+async fn legacy_usernames_become_canonical() {
+    let pool = memory_pool().await;
+    let migrator = Migrator::sqlite(pool.clone());
+
+    migrator.up(Some(12)).await.unwrap();
+
+    sqlx::raw_sql(
+        r#"
+        INSERT INTO users (username, password_hash)
+        VALUES ('Alice', 'x'), ('bob', 'x'), (' Carol', 'x'), ('carol', 'x'), ('Élodie', 'x');
+
+        INSERT INTO sessions (id, username, expires_at) VALUES ('s1', 'Alice', 0);
+        INSERT INTO messages (sender, receiver, body, sent_at) VALUES ('bob', 'Alice', 'oi', 1);
+        INSERT INTO pending_messages (message_id, receiver) VALUES (1, 'Alice');
+        INSERT INTO read_cursors (reader, peer, last_read_id, read_at) VALUES ('Alice', 'bob', 1, 1);
+        INSERT INTO posts (author, body, created_at) VALUES ('Alice', 'post', 1);
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    migrator.up(None).await.unwrap();
+
+    let users: Vec<String> = sqlx::query_scalar("SELECT username FROM users ORDER BY username")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert_eq!(users, [" Carol", "alice", "bob", "carol", "Élodie"]);
+
+    // Tudo que apontava para 'Alice' acompanha a conta.
+    let session: String = sqlx::query_scalar("SELECT username FROM sessions")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(session, "alice");
+
+    let message: (String, String) = sqlx::query_as(
+        r#"
+        SELECT m.receiver, p.receiver FROM messages m
+        JOIN pending_messages p ON p.message_id = m.id
+        "#,
+    )
+    .fetch_one(&pool)
+    .await
+    .unwrap();
+    assert_eq!(message, (String::from("alice"), String::from("alice")));
+
+    let reader: String = sqlx::query_scalar("SELECT reader FROM read_cursors")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(reader, "alice");
+
+    let author: String = sqlx::query_scalar("SELECT author FROM posts")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(author, "alice");
+
+    let conflicts: Vec<(String, String)> = sqlx::query_as(
+        "SELECT username, reason FROM username_conflicts ORDER BY username",
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+    assert_eq!(conflicts, vec![
+        (String::from(" Carol"), String::from("collision")),
+        (String::from("Élodie"), String::from("unicode")),
+    ]);
+
+    let broken: Vec<(String, i64, String, i64)> = sqlx::query_as("PRAGMA foreign_key_check")
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+    assert!(broken.is_empty());
+}
+
+#[tokio::test]
+async fn identical_offline_messages_stay_separate() {
+    let pool = memory_pool().await;
+    let migrator = Migrator::sqlite(pool.clone());
+
+    migrator.up(Some(7)).await.unwrap();
+
+    sqlx::raw_sql(
+        r#"
+        INSERT INTO users (username, password_hash) VALUES ('alice', 'x'), ('bob', 'x');
+
+        -- Duas iguais no mesmo segundo, antes de 0007.
+        INSERT INTO offline_messages (sender, receiver, message, sent_at)
+        VALUES ('alice', 'bob', 'oi', '2024-01-01 00:00:00'),
+            ('alice', 'bob', 'oi', '2024-01-01 00:00:00');
+
+        -- Duas iguais no mesmo segundo, depois de 0007, e uma
+        -- terceira que foi entregue na hora.
+        INSERT INTO messages (sender, receiver, body, sent_at)
+        VALUES ('alice', 'bob', 'oi', 1704153600),
+            ('alice', 'bob', 'oi', 1704153600),
+            ('alice', 'bob', 'oi', 1704153700);
+        INSERT INTO offline_messages (sender, receiver, message, sent_at)
+        VALUES ('alice', 'bob', 'oi', '2024-01-02 00:00:00'),
+            ('alice', 'bob', 'oi', '2024-01-02 00:00:01');
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    migrator.up(Some(8)).await.unwrap();
+
+    let pending: Vec<(i64, i64)> = sqlx::query_as(
+        r#"
+        SELECT m.id, m.sent_at FROM pending_messages p
+        JOIN messages m ON m.id = p.message_id
+        ORDER BY m.id
+        "#,
+    )
+    .fetch_all(&pool)
+    .await
+    .unwrap();
+
+    assert_eq!(pending, vec![
+        (1, 1704153600),
+        (2, 1704153600),
+        (4, 1704067200),
+        (5, 1704067200),
+    ]);
+
+    let count: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM messages")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+    assert_eq!(count, 5);
+}
+
+#[test]
+fn scripts_are_split_into_statements() {
+    let script = r#"
+        -- Comentário com ; no meio.
+        CREATE TABLE a (id INT);
+
+        INSERT INTO a VALUES (1); INSERT INTO b VALUES ('x;''y');
+        SET FOREIGN_KEY_CHECKS = 1;
+        -- Só comentário no fim; nada a rodar.
+    "#;
+
+    assert_eq!(statements(script), [
+        "-- Comentário com ; no meio.\n        CREATE TABLE a (id INT)",
+        "INSERT INTO a VALUES (1)",
+        "INSERT INTO b VALUES ('x;''y')",
+        "SET FOREIGN_KEY_CHECKS = 1",
+    ]);
+}
+
+#[tokio::test]
+async fn migrations_can_run_statement_by_statement() {
+    let whole = memory_pool().await;
+    Migrator::sqlite(whole.clone()).up(None).await.unwrap();
+
+    let split = memory_pool().await;
+
+    for migration in SQLITE {
+        for statement in statements(migration.up) {
+            sqlx::raw_sql(statement).execute(&split).await.unwrap();
+        }
+    }
+
+    let mut expected = tables(&whole).await;
+    expected.retain(|t| t != "schema_migrations");
+
+    assert_eq!(tables(&split).await, expected);
+}
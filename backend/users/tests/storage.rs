@@ -0,0 +1,328 @@
+/*
+Testes do SqliteStorage rodando contra uma database
+sqlite em memória, com todas as migrations aplicadas.
+*/
+
+use sqlx::sqlite::SqlitePool;
+
+use error::AuthenticateErrorType;
+
+use users::storage::{
+    BanRecord,
+    PoolConfig,
+    RoomSummary,
+    SqliteStorage,
+    Storage,
+    StoredMessage,
+};
+
+async fn storage(usernames: &[&str]) -> SqliteStorage {
+    let storage = SqliteStorage::connect("sqlite::memory:", PoolConfig::default())
+        .await
+        .unwrap();
+
+    storage.migrator().unwrap().up(None).await.unwrap();
+
+    for username in usernames {
+        storage.insert_user(username, "hash").await.unwrap();
+    }
+
+    storage
+}
+
+fn ids(messages: &[StoredMessage]) -> Vec<i64> {
+    messages.iter().map(|m| m.id).collect()
+}
+
+#[tokio::test]
+async fn conversations_are_paginated_from_the_newest() {
+    let storage = storage(&["alice", "bob", "carol"]).await;
+
+    let mut sent = Vec::new();
+
+    for i in 0..5 {
+        let (from, to) = match i % 2 {
+            0 => ("alice", "bob"),
+            _ => ("bob", "alice"),
+        };
+
+        sent.push(storage.insert_message(from, to, &format!("m{i}"), 100 + i).await.unwrap());
+    }
+
+    storage.insert_message("alice", "carol", "outra conversa", 200).await.unwrap();
+
+    let page = storage.get_conversation("alice", "bob", None, 2).await.unwrap();
+    assert_eq!(ids(&page), [sent[4], sent[3]]);
+
+    // A conversa é a mesma vista de qualquer um dos lados.
+    let rest = storage.get_conversation("bob", "alice", Some(sent[3]), 10).await.unwrap();
+    assert_eq!(ids(&rest), [sent[2], sent[1], sent[0]]);
+
+    assert_eq!(storage.get_message(sent[1]).await.unwrap(), Some(StoredMessage {
+        id: sent[1],
+        sender: "bob".into(),
+        receiver: "alice".into(),
+        body: "m1".into(),
+        sent_at: 101,
+    }));
+    assert_eq!(storage.get_message(i64::MAX).await.unwrap(), None);
+}
+
+#[tokio::test]
+async fn pending_messages_wait_for_the_receiver() {
+    let storage = storage(&["alice", "bob"]).await;
+
+    let first = storage.insert_message("alice", "bob", "um", 1).await.unwrap();
+    let second = storage.insert_message("alice", "bob", "dois", 2).await.unwrap();
+    storage.store_message(second, "bob").await.unwrap();
+    storage.store_message(first, "bob").await.unwrap();
+
+    let pending = storage.get_stored_messages("bob").await.unwrap();
+    assert_eq!(ids(&pending), [first, second]);
+    assert!(storage.get_stored_messages("alice").await.unwrap().is_empty());
+
+    // Só o destinatário tira a mensagem das pendentes, e
+    // só uma vez; ids desconhecidos são ignorados.
+    assert!(storage.delete_stored_messages(&[first], "alice").await.unwrap().is_empty());
+    assert_eq!(storage.delete_stored_messages(&[first, i64::MAX], "bob").await.unwrap(), [pending[0].clone()]);
+    assert!(storage.delete_stored_messages(&[first], "bob").await.unwrap().is_empty());
+
+    assert_eq!(ids(&storage.get_stored_messages("bob").await.unwrap()), [second]);
+
+    // Várias de uma vez voltam em ordem de id.
+    let third = storage.insert_message("alice", "bob", "três", 3).await.unwrap();
+    storage.store_message(third, "bob").await.unwrap();
+
+    let acked = storage.delete_stored_messages(&[third, second], "bob").await.unwrap();
+    assert_eq!(ids(&acked), [second, third]);
+    assert!(storage.get_stored_messages("bob").await.unwrap().is_empty());
+
+    // O histórico continua com todas.
+    assert_eq!(storage.get_conversation("alice", "bob", None, 10).await.unwrap().len(), 3);
+}
+
+#[tokio::test]
+async fn concurrent_acks_release_a_message_once() {
+    let storage = storage(&["alice", "bob"]).await;
+
+    let id = storage.insert_message("alice", "bob", "oi", 1).await.unwrap();
+    storage.store_message(id, "bob").await.unwrap();
+
+    let ids = [id];
+    let (a, b) = tokio::join!(
+        storage.delete_stored_messages(&ids, "bob"),
+        storage.delete_stored_messages(&ids, "bob"),
+    );
+
+    assert_eq!([a.unwrap(), b.unwrap()].iter().flatten().count(), 1);
+}
+
+#[tokio::test]
+async fn rooms_keep_members_and_pending_messages() {
+    let storage = storage(&["alice", "bob", "carol"]).await;
+
+    let geral = storage.insert_room("geral", "bob", 1).await.unwrap();
+    storage.insert_room("outra", "carol", 1).await.unwrap();
+    assert!(matches!(
+        storage.insert_room("geral", "alice", 2).await,
+        Err(AuthenticateErrorType::RoomAlreadyExists)
+    ));
+
+    assert_eq!(storage.get_room("geral").await.unwrap().unwrap().id, geral);
+    assert!(storage.get_room("nenhuma").await.unwrap().is_none());
+
+    // Sem um criador válido a sala não chega a existir.
+    assert!(storage.insert_room("vazia", "ninguem", 1).await.is_err());
+    assert!(storage.get_room("vazia").await.unwrap().is_none());
+
+    assert!(!storage.insert_room_member(geral, "bob", 1).await.unwrap());
+    assert!(storage.insert_room_member(geral, "alice", 1).await.unwrap());
+    assert!(!storage.insert_room_member(geral, "alice", 2).await.unwrap());
+
+    assert_eq!(storage.get_room_members(geral).await.unwrap(), ["alice", "bob"]);
+    assert_eq!(storage.list_rooms("alice").await.unwrap(), [
+        RoomSummary { name: "geral".into(), members: 2, joined: true },
+        RoomSummary { name: "outra".into(), members: 1, joined: false },
+    ]);
+
+    let (id, recipients) = storage.insert_room_message(geral, "alice", "bom dia", 5).await.unwrap();
+    assert_eq!(recipients, ["bob"]);
+
+    let pending = storage.get_stored_room_messages("bob").await.unwrap();
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0].room, "geral");
+    assert_eq!(pending[0].body, "bom dia");
+
+    assert_eq!(storage.delete_stored_room_messages(&[id], "alice").await.unwrap(), 0);
+    assert_eq!(storage.delete_stored_room_messages(&[id, i64::MAX], "bob").await.unwrap(), 1);
+    assert_eq!(storage.delete_stored_room_messages(&[id], "bob").await.unwrap(), 0);
+
+    // Sair da sala descarta o que ainda estava pendente.
+    storage.insert_room_message(geral, "alice", "de novo", 6).await.unwrap();
+
+    assert!(storage.delete_room_member(geral, "bob").await.unwrap());
+    assert!(!storage.delete_room_member(geral, "bob").await.unwrap());
+    assert!(storage.get_stored_room_messages("bob").await.unwrap().is_empty());
+    assert_eq!(storage.get_room_members(geral).await.unwrap(), ["alice"]);
+}
+
+#[tokio::test]
+async fn room_messages_skip_banned_members() {
+    let storage = storage(&["alice", "bob", "carol", "dave"]).await;
+
+    let room = storage.insert_room("geral", "alice", 1).await.unwrap();
+
+    for member in ["bob", "carol", "dave"] {
+        storage.insert_room_member(room, member, 1).await.unwrap();
+    }
+
+    storage.set_ban("carol", Some(&BanRecord { reason: "spam".into(), until: None })).await.unwrap();
+    storage.set_ban("dave", Some(&BanRecord { reason: "spam".into(), until: Some(5) })).await.unwrap();
+
+    // A suspensão de dave já acabou quando a mensagem sai.
+    let (_, recipients) = storage.insert_room_message(room, "alice", "oi", 10).await.unwrap();
+    assert_eq!(recipients, ["bob", "dave"]);
+    assert!(storage.get_stored_room_messages("carol").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn failed_room_message_leaves_nothing_behind() {
+    let url = "sqlite:file:room_message_rollback?mode=memory&cache=shared";
+    let storage = SqliteStorage::connect(url, PoolConfig::default()).await.unwrap();
+    storage.migrator().unwrap().up(None).await.unwrap();
+
+    for username in ["alice", "bob", "carol"] {
+        storage.insert_user(username, "hash").await.unwrap();
+    }
+
+    let room = storage.insert_room("geral", "alice", 1).await.unwrap();
+    storage.insert_room_member(room, "bob", 1).await.unwrap();
+    storage.insert_room_member(room, "carol", 1).await.unwrap();
+
+    // Outra conexão com a mesma database faz a mensagem
+    // pendente de carol falhar.
+    let pool = SqlitePool::connect(url).await.unwrap();
+    sqlx::raw_sql(
+        r#"
+        CREATE TRIGGER fail_carol BEFORE INSERT ON pending_room_messages
+        WHEN NEW.receiver = 'carol'
+        BEGIN SELECT RAISE(ABORT, 'falha'); END;
+        "#,
+    )
+    .execute(&pool)
+    .await
+    .unwrap();
+
+    assert!(storage.insert_room_message(room, "alice", "oi", 2).await.is_err());
+
+    // Nem a mensagem nem as pendentes dos outros ficam.
+    let messages: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM room_messages")
+        .fetch_one(&pool)
+        .await
+        .unwrap();
+
+    assert_eq!(messages, 0);
+    assert!(storage.get_stored_room_messages("bob").await.unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn deleting_a_user_removes_what_depends_on_it() {
+    let storage = storage(&["alice", "bob"]).await;
+
+    let room = storage.insert_room("geral", "alice", 1).await.unwrap();
+    storage.insert_room_member(room, "bob", 1).await.unwrap();
+
+    storage.insert_room_message(room, "alice", "oi", 2).await.unwrap();
+
+    let message = storage.insert_message("alice", "bob", "oi", 2).await.unwrap();
+    storage.store_message(message, "bob").await.unwrap();
+    storage.advance_read_cursor("bob", "alice", message, 3).await.unwrap();
+
+    storage.insert_post("alice", "post", 2).await.unwrap();
+
+    storage.delete_user("alice").await.unwrap();
+
+    assert_eq!(storage.get_room_members(room).await.unwrap(), ["bob"]);
+    assert!(storage.get_stored_room_messages("bob").await.unwrap().is_empty());
+    assert!(storage.get_stored_messages("bob").await.unwrap().is_empty());
+    assert!(storage.get_conversation("alice", "bob", None, 10).await.unwrap().is_empty());
+    assert!(storage.get_unread_counts("bob").await.unwrap().is_empty());
+    assert!(storage.get_posts(None, 10).await.unwrap().is_empty());
+
+    // A sala continua existindo sem ela.
+    assert!(storage.get_room("geral").await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn feed_is_paginated_from_the_newest() {
+    let storage = storage(&["alice", "bob"]).await;
+
+    let mut posts = Vec::new();
+
+    for i in 0..5 {
+        let author = if i % 2 == 0 { "alice" } else { "bob" };
+        posts.push(storage.insert_post(author, &format!("p{i}"), 10 + i).await.unwrap());
+    }
+
+    let page = storage.get_posts(None, 2).await.unwrap();
+    assert_eq!(page.iter().map(|p| p.id).collect::<Vec<_>>(), [posts[4], posts[3]]);
+    assert_eq!(page[0].author, "alice");
+    assert_eq!(page[0].body, "p4");
+    assert_eq!(page[0].created_at, 14);
+
+    let rest = storage.get_posts(Some(posts[3]), 10).await.unwrap();
+    assert_eq!(rest.iter().map(|p| p.id).collect::<Vec<_>>(), [posts[2], posts[1], posts[0]]);
+}
+
+#[tokio::test]
+async fn read_cursors_only_move_forward() {
+    let storage = storage(&["alice", "bob", "carol"]).await;
+
+    let mut from_bob = Vec::new();
+
+    for i in 0..3 {
+        from_bob.push(storage.insert_message("bob", "alice", "oi", i).await.unwrap());
+    }
+
+    storage.insert_message("carol", "alice", "oi", 3).await.unwrap();
+    storage.insert_message("alice", "bob", "oi", 4).await.unwrap();
+
+    assert_eq!(storage.get_unread_counts("alice").await.unwrap(), [
+        (String::from("bob"), 3),
+        (String::from("carol"), 1),
+    ]);
+
+    assert!(storage.advance_read_cursor("alice", "bob", from_bob[1], 10).await.unwrap());
+    assert!(!storage.advance_read_cursor("alice", "bob", from_bob[1], 11).await.unwrap());
+    assert!(!storage.advance_read_cursor("alice", "bob", from_bob[0], 12).await.unwrap());
+
+    assert_eq!(storage.get_unread_counts("alice").await.unwrap(), [
+        (String::from("bob"), 1),
+        (String::from("carol"), 1),
+    ]);
+
+    assert!(storage.advance_read_cursor("alice", "bob", from_bob[2], 13).await.unwrap());
+
+    assert_eq!(storage.get_unread_counts("alice").await.unwrap(), [
+        (String::from("carol"), 1),
+    ]);
+
+    // O cursor de alice não muda nada para bob.
+    assert_eq!(storage.get_unread_counts("bob").await.unwrap(), [
+        (String::from("alice"), 1),
+    ]);
+}
+
+#[tokio::test]
+async fn last_seen_is_kept_per_user() {
+    let storage = storage(&["alice"]).await;
+
+    assert_eq!(storage.get_last_seen("alice").await.unwrap(), None);
+
+    storage.set_last_seen("alice", 42).await.unwrap();
+    assert_eq!(storage.get_last_seen("alice").await.unwrap(), Some(42));
+
+    // Usuários que não existem são ignorados.
+    storage.set_last_seen("ninguem", 42).await.unwrap();
+    assert_eq!(storage.get_last_seen("ninguem").await.unwrap(), None);
+}
@@ -0,0 +1,83 @@
+/*
+Testes do TOTP com relógio fixo, usando os vetores de
+teste da RFC 6238 (segredo ascii "12345678901234567890",
+truncados para 6 dígitos).
+*/
+
+use std::time::Duration;
+
+use users::{
+    LoginChallenges,
+    Totp,
+    two_factor::hash_recovery_code,
+};
+
+fn rfc_totp() -> Totp {
+    Totp::new(b"12345678901234567890")
+}
+
+#[test]
+fn codes_match_the_rfc_test_vectors() {
+    let totp = rfc_totp();
+
+    let vectors = [
+        (59, "287082"),
+        (1111111109, "081804"),
+        (1111111111, "050471"),
+        (1234567890, "005924"),
+        (2000000000, "279037"),
+        (20000000000, "353130"),
+    ];
+
+    for (now, code) in vectors {
+        assert_eq!(totp.code(now), code, "t = {now}");
+    }
+}
+
+#[test]
+fn neighbouring_steps_are_accepted() {
+    let totp = rfc_totp();
+    let now = 1111111111;
+    let step = Totp::step(now);
+
+    assert_eq!(totp.matching_step(&totp.code_at(step), now), Some(step));
+    assert_eq!(totp.matching_step(&totp.code_at(step - 1), now), Some(step - 1));
+    assert_eq!(totp.matching_step(&totp.code_at(step + 1), now), Some(step + 1));
+
+    assert_eq!(totp.matching_step(&totp.code_at(step - 2), now), None);
+    assert_eq!(totp.matching_step(&totp.code_at(step + 2), now), None);
+}
+
+#[test]
+fn secret_round_trips_through_the_uri() {
+    let totp = Totp::generate();
+    let secret = totp.secret_base32();
+
+    let decoded = Totp::from_base32(&secret).unwrap();
+    assert_eq!(decoded.code(1234567890), totp.code(1234567890));
+
+    let uri = totp.uri("dw web", "alice");
+    assert!(uri.starts_with("otpauth://totp/dw%20web:alice?"));
+    assert!(uri.contains(&format!("secret={secret}")));
+    assert!(uri.contains("issuer=dw%20web"));
+}
+
+#[test]
+fn recovery_codes_ignore_case_and_separators() {
+    assert_eq!(hash_recovery_code("abcde-01234"), hash_recovery_code("ABCDE01234"));
+    assert_ne!(hash_recovery_code("abcde-01234"), hash_recovery_code("abcde-01235"));
+}
+
+#[test]
+fn challenges_expire() {
+    let challenges = LoginChallenges::new(Duration::from_secs(300));
+
+    let (id, expires_at) = challenges.issue("alice", 1000);
+    assert_eq!(expires_at, 1300);
+
+    assert_eq!(challenges.owner(&id, 1299).as_deref(), Some("alice"));
+    assert_eq!(challenges.owner(&id, 1300), None);
+
+    challenges.remove(&id);
+    assert_eq!(challenges.owner(&id, 1000), None);
+}
@@ -0,0 +1,51 @@
+/*
+Testes dos indicadores de "digitando" com instantes fixos.
+*/
+
+use std::time::{Duration, Instant};
+
+use users::{
+    Typing,
+    TypingConfig,
+    TypingIndicators,
+};
+
+fn indicators() -> TypingIndicators {
+    TypingIndicators::new(TypingConfig {
+        interval: Duration::from_secs(2),
+        timeout: Duration::from_secs(5),
+    })
+}
+
+#[test]
+fn repeated_notices_are_throttled() {
+    let typing = indicators();
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+
+    assert_eq!(typing.touch("alice", "bob", at(0)), Typing::Started);
+    assert_eq!(typing.touch("alice", "bob", at(1)), Typing::Throttled);
+    assert_eq!(typing.touch("alice", "bob", at(2)), Typing::Repeated);
+    assert_eq!(typing.touch("alice", "bob", at(3)), Typing::Throttled);
+
+    // Cada par (remetente, destinatário) é independente.
+    assert_eq!(typing.touch("alice", "carol", at(3)), Typing::Started);
+    assert_eq!(typing.touch("bob", "alice", at(3)), Typing::Started);
+}
+
+#[test]
+fn indicators_expire_after_the_last_notice() {
+    let typing = indicators();
+    let start = Instant::now();
+    let at = |secs: u64| start + Duration::from_secs(secs);
+
+    typing.touch("alice", "bob", at(0));
+    assert_eq!(typing.deadline("alice", "bob", at(1)), Some(at(5)));
+
+    // Mesmo descartado, o aviso estende o prazo.
+    typing.touch("alice", "bob", at(1));
+    assert_eq!(typing.deadline("alice", "bob", at(5)), Some(at(6)));
+
+    assert_eq!(typing.deadline("alice", "bob", at(6)), None);
+    assert_eq!(typing.touch("alice", "bob", at(7)), Typing::Started);
+}
@@ -4,89 +4,291 @@ chamadas em /utils/src/main.rs
 */
 
 use sqlx::{
-    mysql::MySqlPoolOptions,
+    mysql::{MySqlPool, MySqlPoolOptions},
     Executor,
 };
 
 use std::{
     fs,
-    path::PathBuf,
+    path::Path,
+    time::Duration,
 };
 
+use users::{
+    Users,
+    PoolConfig,
+    migrations::MigrationState,
+    Migrator,
+    Role,
+};
+
+type Error = Box<dyn std::error::Error>;
+
+// Tudo que é preciso para criar (ou recriar) a database
+// mysql do servidor. Preenchido pela linha de comando em main.
+pub struct MysqlSetup {
+    pub root_user: String,
+    pub root_pass: String,
+    pub db_user: String,
+    pub db_pass: String,
+    pub db_name: String,
+    pub host: String,
+    pub port: u16,
+}
+
+impl MysqlSetup {
+    // Nomes de usuário e database entram direto no SQL
+    // (não dá para usar bind em CREATE USER/DATABASE), então
+    // só são aceitos caracteres que não precisam de escape.
+    fn validate(&self) -> Result<(), Error> {
+        let valid = |s: &str| !s.is_empty()
+            && s.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if !valid(&self.db_user) {
+            return Err(format!("Nome de usuário inválido: {:?}", self.db_user).into());
+        }
+
+        if !valid(&self.db_name) {
+            return Err(format!("Nome de database inválido: {:?}", self.db_name).into());
+        }
+
+        Ok(())
+    }
+
+    async fn admin_pool(&self) -> Result<MySqlPool, Error> {
+        let admin_url = format!("mysql://{}:{}@{}:{}/",
+            encode(&self.root_user), encode(&self.root_pass), self.host, self.port);
+
+        Ok(MySqlPoolOptions::new().connect(&admin_url).await?)
+    }
+
+    pub fn database_url(&self) -> String {
+        format!("mysql://{}:{}@{}:{}/{}",
+            encode(&self.db_user), encode(&self.db_pass), self.host, self.port, self.db_name)
+    }
+}
+
+// Percent-encoding mínimo para usuário e senha dentro
+// de uma url (ex: uma senha com @ ou :).
+fn encode(s: &str) -> String {
+    s.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// s como uma string literal do mysql, entre aspas simples.
+fn quote(s: &str) -> String {
+    format!("'{}'", s.replace('\\', "\\\\").replace('\'', "''"))
+}
+
 // Cria automaticamente, se não existir, a database de nome
-// db_name no ip host e porta port. Além de dar permissões 
-// de acceso ao database pro db_user e criar as tabelas 
-// importantes dentrodo database.
-// (atualmente só cria a de user/password)
+// db_name no ip host e porta port. Além de dar permissões
+// de acceso ao database pro db_user e aplicar as migrations
+// (que criam as tabelas) dentro do database.
+// Por fim escreve a DATABASE_URL em env_path.
 pub async fn init_mysql_database(
-    root_user: &str,
-    root_pass: &str,
-    db_user: &str,
-    db_pass: &str,
-    db_name: &str,
-    host: &str,
-    port: u16,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let admin_url = format!("mysql://{}:{}@{}:{}/", root_user, root_pass, host, port);
-    let admin_pool = MySqlPoolOptions::new()
-        .connect(&admin_url)
-        .await?;
+    setup: &MysqlSetup,
+    env_path: &Path,
+) -> Result<(), Error> {
+    setup.validate()?;
+    let admin_pool = setup.admin_pool().await?;
+
+    // O usuário pode já existir de um init anterior; o ALTER
+    // garante que a senha dele é a que vai para o .env.
+    let db_pass = quote(&setup.db_pass);
 
     let create_user_sql = format!(
-        "CREATE USER IF NOT EXISTS '{}'@'%' IDENTIFIED BY '{}'",
-        db_user, db_pass
+        "CREATE USER IF NOT EXISTS '{}'@'%' IDENTIFIED BY {}",
+        setup.db_user, db_pass
     );
-    let _ = admin_pool.execute(create_user_sql.as_str()).await;
+    admin_pool.execute(create_user_sql.as_str()).await?;
+
+    let alter_user_sql = format!("ALTER USER '{}'@'%' IDENTIFIED BY {}", setup.db_user, db_pass);
+    admin_pool.execute(alter_user_sql.as_str()).await?;
 
-    let drop_user_localhost = format!("DROP USER IF EXISTS '{}'@'localhost'", db_user);
+    let drop_user_localhost = format!("DROP USER IF EXISTS '{}'@'localhost'", setup.db_user);
     admin_pool.execute(drop_user_localhost.as_str()).await?;
 
-    let create_db_sql = format!("CREATE DATABASE IF NOT EXISTS `{}`", db_name);
+    let create_db_sql = format!("CREATE DATABASE IF NOT EXISTS `{}`", setup.db_name);
     admin_pool.execute(create_db_sql.as_str()).await?;
 
     let grant_sql = format!(
         "GRANT ALL PRIVILEGES ON `{}`.* TO '{}'@'%'",
-        db_name, db_user
+        setup.db_name, setup.db_user
     );
     admin_pool.execute(grant_sql.as_str()).await?;
     admin_pool.execute("FLUSH PRIVILEGES").await?;
 
-    let user_url = format!("mysql://{}:{}@{}:{}/{}", db_user, db_pass, host, port, db_name);
-    let user_pool = MySqlPoolOptions::new().connect(&user_url).await?;
-
-    let create_users_table = r#"
-        CREATE TABLE IF NOT EXISTS users (
-            id INT AUTO_INCREMENT PRIMARY KEY,
-            username VARCHAR(255) NOT NULL UNIQUE,
-            password_hash TEXT NOT NULL
-        );
-    "#;
-    user_pool.execute(create_users_table).await?;
-
-    let create_offline_table = r#"
-        CREATE TABLE IF NOT EXISTS offline_messages (
-            id INT AUTO_INCREMENT PRIMARY KEY,
-            sender VARCHAR(255) NOT NULL,
-            receiver VARCHAR(255) NOT NULL,
-            message TEXT NOT NULL,
-            sent_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
-            FOREIGN KEY (sender) REFERENCES users(username) ON DELETE CASCADE,
-            FOREIGN KEY (receiver) REFERENCES users(username) ON DELETE CASCADE
-        );
-    "#;
-    user_pool.execute(create_offline_table).await?;
-
-    let db_url = format!("mysql://{}:{}@{}:{}/{}", db_user, db_pass, host, port, db_name);
-
-    let mut env_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
-    env_path.pop();
-    env_path.push(".env");
+    let db_url = setup.database_url();
+    let user_pool = MySqlPoolOptions::new().connect(&db_url).await?;
+
+    let applied = Migrator::mysql(user_pool).up(None).await?;
+
+    for version in applied {
+        println!("[OK] Migration {} aplicada", version);
+    }
 
     let contents = format!("DATABASE_URL={}\n", db_url);
-    fs::write(&env_path, contents)?;
+    fs::write(env_path, contents)?;
 
-    println!("[OK] Banco `{}` e usuário `{}` configurados com sucesso!", db_name, db_user);
+    println!("[OK] Banco `{}` e usuário `{}` configurados com sucesso!", setup.db_name, setup.db_user);
     println!("[OK] .env criado em {:?}", env_path);
 
     Ok(())
 }
+
+// Apaga a database inteira e a cria de novo do zero,
+// com todas as migrations aplicadas.
+pub async fn reset_mysql_database(
+    setup: &MysqlSetup,
+    env_path: &Path,
+) -> Result<(), Error> {
+    setup.validate()?;
+    let admin_pool = setup.admin_pool().await?;
+
+    let drop_db_sql = format!("DROP DATABASE IF EXISTS `{}`", setup.db_name);
+    admin_pool.execute(drop_db_sql.as_str()).await?;
+
+    println!("[OK] Banco `{}` apagado", setup.db_name);
+
+    init_mysql_database(setup, env_path).await
+}
+
+pub enum MigrateCommand {
+    // Aplica as pendentes até a versão dada (ou todas).
+    Up(Option<i64>),
+    // Desfaz as últimas n migrations aplicadas.
+    Down(usize),
+    Status,
+}
+
+async fn connect(database_url: &str) -> Result<Users, Error> {
+    Ok(Users::connect_with(database_url, PoolConfig::default()).await?)
+}
+
+// Avisa das contas que a migration de usernames canônicos
+// não conseguiu renomear. Retorna quantas são.
+async fn report_username_conflicts(users: &Users) -> Result<usize, Error> {
+    let conflicts = users.username_conflicts().await?;
+
+    for c in &conflicts {
+        println!("[!] Username {:?} não está na forma canônica ({}): renomeie a conta", c.username, c.reason);
+    }
+
+    Ok(conflicts.len())
+}
+
+// Roda um comando de migration na database em database_url.
+pub async fn migrate(
+    database_url: &str,
+    command: MigrateCommand,
+) -> Result<(), Error> {
+    let users = connect(database_url).await?;
+    let migrator = users.migrator()?;
+
+    match command {
+        MigrateCommand::Up(target) => {
+            let applied = migrator.up(target).await?;
+
+            if applied.is_empty() {
+                println!("Nenhuma migration pendente");
+            }
+
+            for version in applied {
+                println!("[OK] Migration {} aplicada", version);
+            }
+
+            // Com um alvo, username_conflicts pode ainda não existir.
+            if target.is_none() {
+                report_username_conflicts(&users).await?;
+            }
+        },
+
+        MigrateCommand::Down(steps) => {
+            for version in migrator.down(steps).await? {
+                println!("[OK] Migration {} desfeita", version);
+            }
+        },
+
+        MigrateCommand::Status => {
+            for status in migrator.status().await? {
+                let state = match status.state {
+                    MigrationState::Applied { applied_at } => format!("aplicada (unix {})", applied_at),
+                    MigrationState::Pending => String::from("pendente"),
+                    MigrationState::Modified => String::from("ALTERADA depois de aplicada"),
+                };
+
+                println!("{:04} {:<30} {}", status.version, status.name, state);
+            }
+        },
+    }
+
+    Ok(())
+}
+
+// Verifica se a database em database_url está acessível
+// e com todas as migrations aplicadas.
+pub async fn check(
+    database_url: &str,
+) -> Result<(), Error> {
+    let users = connect(database_url).await?;
+    let migrator = users.migrator()?;
+    println!("[OK] Conexão com a database estabelecida");
+
+    let status = migrator.status().await?;
+
+    let pending = status.iter()
+        .filter(|s| s.state == MigrationState::Pending)
+        .count();
+
+    if let Some(s) = status.iter().find(|s| s.state == MigrationState::Modified) {
+        return Err(format!("Migration {} foi alterada depois de aplicada", s.version).into());
+    }
+
+    if pending > 0 {
+        return Err(format!("{} migration(s) pendente(s), rode `migrate up`", pending).into());
+    }
+
+    println!("[OK] Todas as {} migrations aplicadas", status.len());
+
+    if report_username_conflicts(&users).await? > 0 {
+        return Err("Há contas com username fora da forma canônica".into());
+    }
+
+    Ok(())
+}
+
+// Gera um código de uso único para que username redefina
+// a senha (ClientProtocol::ResetPassword). O código vale
+// por ttl e precisa ser entregue ao usuário por fora.
+pub async fn reset_password(
+    database_url: &str,
+    username: &str,
+    ttl: Duration,
+) -> Result<(), Error> {
+    let users = Users::connect_with(database_url, PoolConfig::default()).await?;
+    let code = users.issue_reset_code(username, ttl).await?;
+
+    println!("[OK] Código de redefinição de senha para `{}`: {}", username, code);
+    println!("[OK] Válido por {} minuto(s), uma única vez", ttl.as_secs() / 60);
+
+    Ok(())
+}
+
+// Muda o papel de username. Sessões abertas passam a ter
+// as novas permissões já no próximo pedido.
+pub async fn set_role(
+    database_url: &str,
+    username: &str,
+    role: Role,
+) -> Result<(), Error> {
+    let users = Users::connect_with(database_url, PoolConfig::default()).await?;
+    users.set_role(username, role).await?;
+
+    println!("[OK] `{}` agora é {}", username, role);
+
+    Ok(())
+}
@@ -1,17 +1,263 @@
 use utils::*;
-use tokio::io::Result;
 
-#[tokio::main]
-async fn main() -> Result<()> {
-    init_mysql_database(
-        "root",
-        "root",
-        "nyoxon",
-        "1234",
-        "auth_database",
-        "localhost",
-        3306,
-    ).await.expect("Erro ao tentar criar um database");
+use std::{
+    env,
+    io::{self, Write},
+    path::PathBuf,
+    process::ExitCode,
+    time::Duration,
+};
+
+use users::Role;
+
+use clap::{
+    Args,
+    Parser,
+    Subcommand,
+};
+
+// Valor padrão de --env-file: o mesmo .env que o servidor
+// lê, o primeiro encontrado subindo a partir do diretório
+// atual. Se não houver nenhum, um novo no diretório atual.
+fn default_env_file() -> PathBuf {
+    let current = env::current_dir().unwrap_or_default();
+
+    current
+        .ancestors()
+        .map(|dir| dir.join(".env"))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| current.join(".env"))
+}
+
+// Ferramentas para criar e manter a database do servidor.
+// Toda flag também pode vir da variável de ambiente indicada.
+#[derive(Parser)]
+#[command(name = "utils", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Cria a database mysql, o usuário do servidor,
+    /// aplica as migrations e escreve o .env
+    Init(MysqlArgs),
+
+    /// Aplica, desfaz ou lista migrations
+    Migrate {
+        #[command(subcommand)]
+        action: MigrateAction,
+
+        #[command(flatten)]
+        database: DatabaseArgs,
+    },
+
+    /// Apaga e recria a database mysql do zero
+    Reset {
+        #[command(flatten)]
+        mysql: MysqlArgs,
+
+        /// Não pede confirmação
+        #[arg(long, short)]
+        yes: bool,
+    },
+
+    /// Verifica a conexão e se há migrations pendentes
+    Check(DatabaseArgs),
+
+    /// Gera um código de uso único para o usuário
+    /// redefinir a senha
+    ResetPassword {
+        username: String,
+
+        /// Minutos até o código expirar
+        #[arg(long, default_value_t = 60)]
+        minutes: u64,
+
+        #[command(flatten)]
+        database: DatabaseArgs,
+    },
+
+    /// Muda o papel (user, moderator ou admin) de um
+    /// usuário. Usado para criar o primeiro admin
+    SetRole {
+        username: String,
+
+        role: Role,
+
+        #[command(flatten)]
+        database: DatabaseArgs,
+    },
+}
+
+#[derive(Subcommand)]
+enum MigrateAction {
+    /// Aplica as migrations pendentes (até VERSION, se dada)
+    Up { version: Option<i64> },
+
+    /// Desfaz as últimas STEPS migrations aplicadas
+    Down {
+        #[arg(default_value_t = 1)]
+        steps: usize,
+    },
+
+    /// Lista o estado de cada migration
+    Status,
+}
+
+#[derive(Args)]
+struct MysqlArgs {
+    /// Host do servidor mysql
+    #[arg(long, env = "MYSQL_HOST", default_value = "localhost")]
+    host: String,
+
+    /// Porta do servidor mysql
+    #[arg(long, env = "MYSQL_PORT", default_value_t = 3306)]
+    port: u16,
+
+    /// Usuário mysql com privilégios para criar databases
+    #[arg(long, env = "MYSQL_ROOT_USER", default_value = "root")]
+    root_user: String,
+
+    /// Senha do usuário com privilégios (perguntada se omitida)
+    #[arg(long, env = "MYSQL_ROOT_PASSWORD", hide_env_values = true)]
+    root_password: Option<String>,
+
+    /// Nome da database do servidor
+    #[arg(long, env = "DB_NAME", default_value = "auth_database")]
+    db_name: String,
+
+    /// Usuário que o servidor usa para acessar a database
+    #[arg(long, env = "DB_USER")]
+    db_user: String,
+
+    /// Senha do usuário do servidor (perguntada se omitida)
+    #[arg(long, env = "DB_PASSWORD", hide_env_values = true)]
+    db_password: Option<String>,
+
+    /// Onde escrever o .env com a DATABASE_URL
+    #[arg(long, default_value_os_t = default_env_file())]
+    env_file: PathBuf,
+}
+
+impl MysqlArgs {
+    fn into_setup(self) -> io::Result<(MysqlSetup, PathBuf)> {
+        let root_pass = match self.root_password {
+            Some(pass) => pass,
+            None => rpassword::prompt_password(
+                format!("Senha do usuário mysql '{}': ", self.root_user))?,
+        };
+
+        let db_pass = match self.db_password {
+            Some(pass) => pass,
+            None => rpassword::prompt_password(
+                format!("Senha para o usuário '{}' do servidor: ", self.db_user))?,
+        };
+
+        let setup = MysqlSetup {
+            root_user: self.root_user,
+            root_pass,
+            db_user: self.db_user,
+            db_pass,
+            db_name: self.db_name,
+            host: self.host,
+            port: self.port,
+        };
+
+        Ok((setup, self.env_file))
+    }
+}
+
+#[derive(Args)]
+struct DatabaseArgs {
+    /// Url da database (mysql://, sqlite://). Se omitida,
+    /// é lida do --env-file
+    #[arg(long, env = "DATABASE_URL", hide_env_values = true)]
+    database_url: Option<String>,
+
+    /// .env de onde ler a DATABASE_URL
+    #[arg(long, default_value_os_t = default_env_file())]
+    env_file: PathBuf,
+}
+
+impl DatabaseArgs {
+    fn database_url(self) -> Result<String, String> {
+        if let Some(url) = self.database_url {
+            return Ok(url);
+        }
+
+        dotenvy::from_path(&self.env_file)
+            .map_err(|e| format!("Erro ao ler {:?}: {e}", self.env_file))?;
+
+        env::var("DATABASE_URL")
+            .map_err(|_| format!("DATABASE_URL não definida em {:?}", self.env_file))
+    }
+}
+
+// Pede para o usuário digitar o nome da database antes
+// de apagá-la.
+fn confirm_reset(db_name: &str) -> io::Result<bool> {
+    print!("Isso vai APAGAR a database `{db_name}`. Digite o nome dela para confirmar: ");
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+
+    Ok(answer.trim() == db_name)
+}
+
+async fn run(cli: Cli) -> Result<(), Box<dyn std::error::Error>> {
+    match cli.command {
+        Command::Init(mysql) => {
+            let (setup, env_file) = mysql.into_setup()?;
+            init_mysql_database(&setup, &env_file).await?;
+        },
+
+        Command::Migrate { action, database } => {
+            let command = match action {
+                MigrateAction::Up { version } => MigrateCommand::Up(version),
+                MigrateAction::Down { steps } => MigrateCommand::Down(steps),
+                MigrateAction::Status => MigrateCommand::Status,
+            };
+
+            migrate(&database.database_url()?, command).await?;
+        },
+
+        Command::Reset { mysql, yes } => {
+            if !yes && !confirm_reset(&mysql.db_name)? {
+                println!("Cancelado");
+                return Ok(());
+            }
+
+            let (setup, env_file) = mysql.into_setup()?;
+            reset_mysql_database(&setup, &env_file).await?;
+        },
+
+        Command::Check(database) => {
+            check(&database.database_url()?).await?;
+        },
+
+        Command::ResetPassword { username, minutes, database } => {
+            let ttl = Duration::from_secs(minutes * 60);
+            reset_password(&database.database_url()?, &username, ttl).await?;
+        },
+
+        Command::SetRole { username, role, database } => {
+            set_role(&database.database_url()?, &username, role).await?;
+        },
+    }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    match run(Cli::parse()).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("[ERRO] {e}");
+            ExitCode::FAILURE
+        }
+    }
+}